@@ -0,0 +1,434 @@
+//! Access point configuration.
+//!
+//! The access point (`ap0`) is run by hostapd. Its SSID, passphrase, channel,
+//! country code and hidden-SSID flag are read from and written to the hostapd
+//! configuration file; all other settings in the file are left untouched.
+//!
+//! Changes are applied with a confirm-or-revert timer, since an admin connected
+//! via the access point is disconnected when it restarts.
+//!
+//! Connected clients (stations) are listed by combining `iw` station
+//! statistics with the dnsmasq DHCP leases. Clients can be kicked
+//! (deauthenticated) or blocked by MAC address using hostapd's deny list.
+
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+
+use log::{info, warn};
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::forms::WiFi;
+use crate::revert::Reverter;
+
+/// Path of the hostapd configuration file
+pub const HOSTAPD_CONF: &str = "/etc/hostapd/hostapd.conf";
+/// Path of the hostapd MAC address deny list
+pub const HOSTAPD_DENY: &str = "/etc/hostapd/hostapd.deny";
+/// Path of the dnsmasq DHCP leases file
+const DNSMASQ_LEASES: &str = "/var/lib/misc/dnsmasq.leases";
+/// Access point interface
+const AP_IFACE: &str = "ap0";
+
+/// Access point settings (user-defined)
+#[derive(Debug, Deserialize, Serialize, FromForm)]
+pub struct ApConfig {
+    pub ssid: String,
+    // an empty passphrase leaves the current passphrase unchanged
+    #[serde(default, skip_serializing)]
+    pub pass: String,
+    pub channel: u8,
+    pub country: String, // ISO 3166-1 alpha-2 code (eg. "NZ")
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+impl ApConfig {
+    /// Ensure the access point settings are valid
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let msg = if self.ssid.is_empty() || self.ssid.len() > 32 {
+            Some("SSID must be between 1 and 32 bytes long".to_string())
+        } else if self.ssid.chars().any(char::is_control) {
+            Some("SSID must not contain control characters".to_string())
+        } else if !self.pass.is_empty() && !valid_passphrase(&self.pass) {
+            Some(
+                "Passphrase must be 8 to 63 printable ASCII characters (or 64 hex digits)"
+                    .to_string(),
+            )
+        } else if self.country.len() != 2 || !self.country.chars().all(|c| c.is_ascii_uppercase()) {
+            Some("Country code must be two capital letters (eg. NZ)".to_string())
+        } else if !(1..=13).contains(&self.channel) && !(self.channel == 14 && self.country == "JP")
+        {
+            Some("Channel must be between 1 and 13 (14 is only allowed in JP)".to_string())
+        } else {
+            None
+        };
+
+        match msg {
+            Some(msg) => Err(PeachWebError::InvalidApConfig { msg }),
+            None => Ok(()),
+        }
+    }
+}
+
+// wpa2 passphrases are 8-63 printable ascii characters or a 64 digit hex psk
+fn valid_passphrase(pass: &str) -> bool {
+    let printable = pass.chars().all(|c| (' '..='~').contains(&c));
+    let hex = pass.chars().all(|c| c.is_ascii_hexdigit());
+    (printable && pass.len() >= 8 && pass.len() <= 63) || (hex && pass.len() == 64)
+}
+
+/// Parse the access point settings from the contents of a hostapd
+/// configuration file. The passphrase is not included.
+pub fn parse(conf: &str) -> ApConfig {
+    let mut config = ApConfig {
+        ssid: String::new(),
+        pass: String::new(),
+        channel: 1,
+        country: String::new(),
+        hidden: false,
+    };
+    for line in conf.lines() {
+        let (key, value) = match split(line) {
+            Some(setting) => setting,
+            None => continue,
+        };
+        match key {
+            "ssid" => config.ssid = value.to_string(),
+            "channel" => config.channel = value.parse().unwrap_or(1),
+            "country_code" => config.country = value.to_string(),
+            "ignore_broadcast_ssid" => config.hidden = value != "0",
+            _ => (),
+        }
+    }
+
+    config
+}
+
+/// Credentials for joining the access point, as configured in the contents
+/// of a hostapd configuration file (used for sharing it via QR code)
+pub fn credentials(conf: &str) -> WiFi {
+    let config = parse(conf);
+    let pass = conf
+        .lines()
+        .filter_map(split)
+        .find(|(key, _)| *key == "wpa_passphrase")
+        .map(|(_, value)| value.to_string());
+    WiFi {
+        ssid: config.ssid,
+        security: Some(if pass.is_some() { "wpa-psk" } else { "open" }.to_string()),
+        pass: pass.unwrap_or_default(),
+        hidden: config.hidden,
+        ..WiFi::default()
+    }
+}
+
+// split a `key=value` line, ignoring comments and blank lines
+fn split(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut parts = line.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if !key.is_empty() => Some((key.trim(), value.trim())),
+        _ => None,
+    }
+}
+
+/// Update the contents of a hostapd configuration file with the given
+/// settings. Existing lines are replaced in place and missing settings are
+/// appended; comments and other settings are preserved.
+pub fn update(conf: &str, config: &ApConfig) -> String {
+    let mut settings = vec![
+        ("ssid", config.ssid.clone()),
+        ("channel", config.channel.to_string()),
+        ("country_code", config.country.clone()),
+        ("ignore_broadcast_ssid", (config.hidden as u8).to_string()),
+    ];
+    if !config.pass.is_empty() {
+        settings.push(("wpa", "2".to_string()));
+        settings.push(("wpa_key_mgmt", "WPA-PSK".to_string()));
+        settings.push(("rsn_pairwise", "CCMP".to_string()));
+        settings.push(("wpa_passphrase", config.pass.clone()));
+    }
+
+    set_options(conf, &settings)
+}
+
+// replace or append `key=value` settings in a hostapd configuration file
+fn set_options(conf: &str, settings: &[(&str, String)]) -> String {
+    let mut written = Vec::new();
+    let mut lines: Vec<String> = conf
+        .lines()
+        .map(|line| match split(line) {
+            Some((key, _)) => match settings.iter().find(|(k, _)| *k == key) {
+                Some((key, value)) => {
+                    written.push(*key);
+                    format!("{}={}", key, value)
+                }
+                None => line.to_string(),
+            },
+            None => line.to_string(),
+        })
+        .collect();
+    for (key, value) in settings {
+        if !written.contains(key) {
+            lines.push(format!("{}={}", key, value));
+        }
+    }
+
+    let mut updated = lines.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Retrieve the current access point settings
+pub fn get_config() -> Result<ApConfig, PeachWebError> {
+    let conf = device::read_file(HOSTAPD_CONF)?;
+
+    Ok(parse(&conf))
+}
+
+// write the hostapd configuration and restart the access point (if running)
+fn write_config(conf: &str) -> Result<(), PeachWebError> {
+    device::write_file(HOSTAPD_CONF, conf)?;
+    device::run_privileged("systemctl", &["try-restart", "hostapd"])?;
+
+    Ok(())
+}
+
+/// Validate and apply new access point settings. Unless the change is
+/// confirmed (`Reverter::confirm("ap")`) before the timeout, the previous
+/// settings are restored.
+pub fn apply(config: ApConfig, reverter: &Reverter) -> Result<(), PeachWebError> {
+    config.validate()?;
+    let original = device::read_file(HOSTAPD_CONF)?;
+    let updated = update(&original, &config);
+    if updated == original {
+        return Ok(());
+    }
+
+    info!("Applying access point settings for SSID {}", config.ssid);
+    if let Err(e) = write_config(&updated) {
+        // hostapd stops if it rejects the new settings; bring it back up with
+        // the previous ones
+        if let Err(restore) = write_config(&original) {
+            warn!("Failed to restore the access point settings: {}", restore);
+        }
+        return Err(e);
+    }
+    reverter.schedule("ap", "access point settings", move || {
+        // clients may have been blocked since the change was applied, which
+        // enables the deny list; keep it enabled in the restored settings
+        if get_blocked()?.is_empty() {
+            write_config(&original)
+        } else {
+            write_config(&with_deny_list(&original))
+        }
+    });
+
+    Ok(())
+}
+
+/// Station (client) associated with the access point
+#[derive(Debug, Serialize)]
+pub struct Station {
+    pub mac: String,
+    pub hostname: Option<String>, // from the dhcp lease
+    pub ip: Option<String>,       // from the dhcp lease
+    pub signal: Option<i32>,      // dBm
+    pub rx_bytes: u64,            // bytes received from the station
+    pub tx_bytes: u64,            // bytes transmitted to the station
+    pub connected: Option<u64>,   // seconds since the station associated
+}
+
+/// Parse the output of `iw dev <iface> station dump`
+pub fn parse_stations(dump: &str) -> Vec<Station> {
+    let mut stations = Vec::new();
+    for line in dump.lines() {
+        let line = line.trim();
+        if let Some(rest) = line.strip_prefix("Station ") {
+            let mac = rest.split_whitespace().next().unwrap_or_default();
+            stations.push(Station {
+                mac: mac.to_lowercase(),
+                hostname: None,
+                ip: None,
+                signal: None,
+                rx_bytes: 0,
+                tx_bytes: 0,
+                connected: None,
+            });
+            continue;
+        }
+        let station = match stations.last_mut() {
+            Some(station) => station,
+            None => continue,
+        };
+        let mut parts = line.splitn(2, ':');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => continue,
+        };
+        // the value is followed by units or per-chain values (eg. "-52 [-52] dBm")
+        let first = value.split_whitespace().next().unwrap_or_default();
+        match key {
+            "rx bytes" => station.rx_bytes = first.parse().unwrap_or(0),
+            "tx bytes" => station.tx_bytes = first.parse().unwrap_or(0),
+            "signal" => station.signal = first.parse().ok(),
+            "connected time" => station.connected = first.parse().ok(),
+            _ => (),
+        }
+    }
+
+    stations
+}
+
+/// Parse dnsmasq DHCP leases into a map of MAC address to IP address and
+/// hostname. Each lease line is `<expiry> <mac> <ip> <hostname> <client id>`,
+/// with `*` for an unknown hostname.
+pub fn parse_leases(leases: &str) -> HashMap<String, (String, Option<String>)> {
+    leases
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            let hostname = match fields[3] {
+                "*" => None,
+                name => Some(name.to_string()),
+            };
+            Some((fields[1].to_lowercase(), (fields[2].to_string(), hostname)))
+        })
+        .collect()
+}
+
+/// Ensure a MAC address is well-formed, returning it in lowercase
+pub fn validate_mac(mac: &str) -> Result<String, PeachWebError> {
+    let octets: Vec<&str> = mac.trim().split(':').collect();
+    let valid = octets.len() == 6
+        && octets
+            .iter()
+            .all(|o| o.len() == 2 && o.chars().all(|c| c.is_ascii_hexdigit()));
+    if valid {
+        Ok(mac.trim().to_lowercase())
+    } else {
+        Err(PeachWebError::InvalidMacAddress {
+            mac: mac.to_string(),
+        })
+    }
+}
+
+/// Retrieve the stations associated with the access point, along with their
+/// dhcp hostname and address (if leased)
+pub fn get_clients() -> Result<Vec<Station>, PeachWebError> {
+    let output = Command::new("iw")
+        .args(&["dev", AP_IFACE, "station", "dump"])
+        .output()
+        .map_err(|e| PeachWebError::SystemCommand {
+            cmd: "iw".to_string(),
+            msg: e.to_string(),
+        })?;
+    let mut stations = parse_stations(&String::from_utf8_lossy(&output.stdout));
+
+    // leases are unavailable if dnsmasq has not yet handed any out
+    let leases = parse_leases(&fs::read_to_string(DNSMASQ_LEASES).unwrap_or_default());
+    for station in stations.iter_mut() {
+        if let Some((ip, hostname)) = leases.get(&station.mac) {
+            station.ip = Some(ip.clone());
+            station.hostname = hostname.clone();
+        }
+    }
+
+    Ok(stations)
+}
+
+/// Retrieve the blocked MAC addresses
+pub fn get_blocked() -> Result<Vec<String>, PeachWebError> {
+    // the deny list does not exist until a client is first blocked
+    let deny = device::read_file(HOSTAPD_DENY).unwrap_or_default();
+
+    Ok(deny
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+// run a hostapd control command against the access point. options are ended
+// with `--` so that the arguments of the command cannot be taken as options
+// (which sudo would otherwise run as root, eg. `-a <action script>`).
+fn hostapd_cli(args: &[&str]) -> Result<String, PeachWebError> {
+    let mut cli_args = vec!["-i", AP_IFACE, "--"];
+    cli_args.extend_from_slice(args);
+    device::run_privileged("hostapd_cli", &cli_args)
+}
+
+/// Disconnect a client from the access point. The client may reconnect.
+pub fn kick(mac: &str) -> Result<(), PeachWebError> {
+    let mac = validate_mac(mac)?;
+    info!("Deauthenticating access point client {}", mac);
+    hostapd_cli(&["deauthenticate", &mac])?;
+
+    Ok(())
+}
+
+/// Update a hostapd configuration to accept all clients except those in the
+/// deny list
+pub fn with_deny_list(conf: &str) -> String {
+    set_options(
+        conf,
+        &[
+            ("macaddr_acl", "0".to_string()),
+            ("deny_mac_file", HOSTAPD_DENY.to_string()),
+        ],
+    )
+}
+
+// write the deny list, ensuring hostapd is configured to read it on startup
+fn write_blocked(blocked: &[String]) -> Result<(), PeachWebError> {
+    let mut deny = blocked.join("\n");
+    deny.push('\n');
+    device::write_file(HOSTAPD_DENY, &deny)?;
+
+    let conf = device::read_file(HOSTAPD_CONF)?;
+    let updated = with_deny_list(&conf);
+    if updated != conf {
+        device::write_file(HOSTAPD_CONF, &updated)?;
+    }
+
+    Ok(())
+}
+
+/// Block a MAC address from the access point, disconnecting it if connected
+pub fn block(mac: &str) -> Result<(), PeachWebError> {
+    let mac = validate_mac(mac)?;
+    let mut blocked = get_blocked()?;
+    if !blocked.contains(&mac) {
+        blocked.push(mac.clone());
+        write_blocked(&blocked)?;
+    }
+    info!("Blocking access point client {}", mac);
+    // apply to the running access point (the deny list is read on startup)
+    hostapd_cli(&["deny_acl", "ADD_MAC", &mac])?;
+    hostapd_cli(&["deauthenticate", &mac])?;
+
+    Ok(())
+}
+
+/// Remove a MAC address from the access point deny list
+pub fn unblock(mac: &str) -> Result<(), PeachWebError> {
+    let mac = validate_mac(mac)?;
+    let mut blocked = get_blocked()?;
+    blocked.retain(|m| *m != mac);
+    write_blocked(&blocked)?;
+    info!("Unblocking access point client {}", mac);
+    hostapd_cli(&["deny_acl", "DEL_MAC", &mac])?;
+
+    Ok(())
+}
@@ -5,7 +5,8 @@
 use log::info;
 
 use crate::error::PeachWebError;
-use crate::forms::{AddAdminForm, DnsForm, PasswordForm, ResetPasswordForm};
+use crate::forms::{AddAdminForm, DnsForm, PasswordForm, ResetPasswordForm, SetupPasswordForm};
+use crate::tls;
 use peach_lib::config_manager;
 use peach_lib::dyndns_client;
 use peach_lib::dyndns_client::{check_is_new_dyndns_domain, get_full_dynamic_domain};
@@ -18,6 +19,8 @@ pub fn save_dns_configuration(dns_form: DnsForm) -> Result<(), PeachWebError> {
     // first save local configurations
     config_manager::set_external_domain(&dns_form.external_domain)?;
     config_manager::set_dyndns_enabled_value(dns_form.enable_dyndns)?;
+    // request a certificate for the external domain (if it has changed)
+    tls::spawn_update();
     // if dynamic dns is enabled and this is a new domain name, then register it
     if dns_form.enable_dyndns {
         let full_dynamic_domain = get_full_dynamic_domain(&dns_form.dynamic_domain);
@@ -98,6 +101,17 @@ pub fn save_reset_password_form(password_form: ResetPasswordForm) -> Result<(),
     Ok(())
 }
 
+/// this function is for setting the first password during first-run setup
+pub fn save_setup_password_form(password_form: SetupPasswordForm) -> Result<(), PeachWebError> {
+    password_utils::validate_new_passwords(
+        &password_form.new_password1,
+        &password_form.new_password2,
+    )?;
+    // if the previous line did not throw an error, then the new password is valid
+    password_utils::set_new_password(&password_form.new_password1)?;
+    Ok(())
+}
+
 pub fn save_add_admin_form(admin_form: AddAdminForm) -> Result<(), PeachWebError> {
     let _result = config_manager::add_ssb_admin_id(&admin_form.ssb_id)?;
     // if the previous  line didn't throw an error then it was a success
@@ -6,11 +6,19 @@
 
 // Context object struct names:
 //
+// ApClientsContext
+// ApContext
+// DateTimeContext
 // DeviceContext
+// DiagnosticsContext
+// DnsStatusContext
 // ErrorContext
+// FirewallContext
 // FlashContext
 // HelpContext
 // HomeContext
+// HostnameContext
+// IpContext
 // LoginContext
 // MessageContext
 // NetworkContext
@@ -18,12 +26,18 @@
 // NetworkAlertContext
 // NetworkDetailContext
 // NetworkListContext
+// NetworkPriorityContext
 // PeerContext
 // ProfileContext
+// SetupContext
 // ShutdownContext
+// SurveyContext
+// WiredContext
+// YggdrasilContext
 
 use std::collections::HashMap;
 
+use log::warn;
 use serde::Serialize;
 
 use peach_lib::config_manager::load_peach_config;
@@ -36,12 +50,171 @@ use peach_lib::sbot_client;
 use peach_lib::stats_client;
 use peach_lib::stats_client::{CpuStatPercentages, DiskUsage, LoadAverage, MemStat, Traffic};
 
+use crate::ap;
+use crate::ap::{ApConfig, Station};
+use crate::datetime;
+use crate::datetime::TimeStatus;
+use crate::diagnostics;
+use crate::diagnostics::{Diagnostics, Report};
+use crate::dyndns;
+use crate::error::PeachWebError;
+use crate::fallback;
+use crate::firewall;
+use crate::firewall::{FirewallConfig, ServiceAccess};
+use crate::forms::WiFi;
+use crate::hostname;
+use crate::ipconfig;
+use crate::ipconfig::IpConfig;
 use crate::monitor;
-use crate::monitor::{Alert, Data, Threshold};
+use crate::monitor::{Alert, Cycle, CycleStatus, CycleUsage, Data, ResourceAlert, Threshold};
+use crate::qr;
+use crate::revert::{PendingStatus, Reverter};
+use crate::setup;
+use crate::signal;
+use crate::signal::{Survey, SurveyOptions, SurveyReport};
+use crate::tls;
+use crate::wifi;
+use crate::wifi::SavedNetwork;
+use crate::wired;
+use crate::wired::WiredInterface;
+use crate::yggdrasil;
+
+// number of wifi fallback events shown on the network page
+const FALLBACK_EVENTS_SHOWN: usize = 5;
+
+// used in /network/ap/clients for connected access point clients
+#[derive(Debug, Serialize)]
+pub struct ApClientsContext {
+    pub back: Option<String>,
+    pub blocked: Vec<String>,
+    pub clients: Option<Vec<Station>>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub title: Option<String>,
+}
+
+impl ApClientsContext {
+    pub fn build() -> ApClientsContext {
+        let clients = match ap::get_clients() {
+            Ok(clients) => Some(clients),
+            Err(e) => {
+                warn!("Failed to retrieve access point clients: {}", e);
+                None
+            }
+        };
+        let blocked = ap::get_blocked().unwrap_or_default();
+
+        ApClientsContext {
+            back: None,
+            blocked,
+            clients,
+            flash_name: None,
+            flash_msg: None,
+            title: None,
+        }
+    }
+}
+
+// used in /network/diagnostics for connectivity diagnostics
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsContext {
+    pub back: Option<String>,
+    // default targets (used when a target is left empty)
+    pub default_dns: String,
+    pub default_http: String,
+    pub default_trace: String,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    // progress and results of the latest run
+    pub report: Report,
+    pub title: Option<String>,
+}
+
+impl DiagnosticsContext {
+    pub fn build(diagnostics: &Diagnostics) -> DiagnosticsContext {
+        DiagnosticsContext {
+            back: None,
+            default_dns: diagnostics::DEFAULT_DNS_HOST.to_string(),
+            default_http: diagnostics::DEFAULT_HTTP_URL.to_string(),
+            default_trace: diagnostics::DEFAULT_TRACE_HOST.to_string(),
+            flash_name: None,
+            flash_msg: None,
+            report: diagnostics.report(),
+            title: None,
+        }
+    }
+}
+
+// used in /network/wifi/survey for the wifi site survey
+#[derive(Debug, Serialize)]
+pub struct SurveyContext {
+    pub back: Option<String>,
+    // default number of scans and interval
+    pub defaults: SurveyOptions,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    // progress and results of the latest survey
+    pub report: SurveyReport,
+    pub title: Option<String>,
+}
+
+impl SurveyContext {
+    pub fn build(survey: &Survey) -> SurveyContext {
+        SurveyContext {
+            back: None,
+            defaults: SurveyOptions::default(),
+            flash_name: None,
+            flash_msg: None,
+            report: survey.report(),
+            title: None,
+        }
+    }
+}
+
+// used in /network/ap for access point settings
+#[derive(Debug, Serialize)]
+pub struct ApContext {
+    pub ap: Option<ApConfig>,
+    pub ap_state: String,
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    // unconfirmed settings which will be reverted (if any)
+    pub pending: Option<PendingStatus>,
+    pub title: Option<String>,
+}
+
+impl ApContext {
+    pub fn build(reverter: &Reverter) -> ApContext {
+        let ap = match ap::get_config() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Failed to read access point settings: {}", e);
+                None
+            }
+        };
+        let ap_state = match network_client::state("ap0") {
+            Ok(state) => state,
+            Err(_) => "Interface unavailable".to_string(),
+        };
+
+        ApContext {
+            ap,
+            ap_state,
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            pending: reverter.status("ap"),
+            title: None,
+        }
+    }
+}
 
 // used in /device for system statistics
 #[derive(Debug, Serialize)]
 pub struct DeviceContext {
+    pub alerts_active: Vec<ResourceAlert>,
+    pub alerts_history: Vec<ResourceAlert>,
     pub back: Option<String>,
     pub cpu_stat_percent: Option<CpuStatPercentages>,
     pub disk_stats: Vec<DiskUsage>,
@@ -134,6 +307,12 @@ impl DeviceContext {
             }
         }
 
+        // active and previous resource alerts
+        let (alerts_active, alerts_history) = match monitor::get_resource_alerts() {
+            Ok(alerts) => (alerts.active, alerts.history),
+            Err(_) => (Vec::new(), Vec::new()),
+        };
+
         // test if go-sbot is running
         let sbot_is_online: bool;
         let sbot_is_online_result = sbot_client::is_sbot_online();
@@ -147,6 +326,8 @@ impl DeviceContext {
         }
 
         DeviceContext {
+            alerts_active,
+            alerts_history,
             back: None,
             cpu_stat_percent,
             disk_stats,
@@ -186,6 +367,48 @@ impl ErrorContext {
     }
 }
 
+// used in /network/firewall for firewall settings
+#[derive(Debug, Serialize)]
+pub struct FirewallContext {
+    pub back: Option<String>,
+    pub config: Option<FirewallConfig>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    // interfaces which a service can be restricted to
+    pub interfaces: Vec<String>,
+    // unconfirmed rules which will be reverted (if any)
+    pub pending: Option<PendingStatus>,
+    // nftables rules file
+    pub ruleset: Option<String>,
+    pub services: Vec<ServiceAccess>,
+    pub title: Option<String>,
+}
+
+impl FirewallContext {
+    pub fn build(reverter: &Reverter) -> FirewallContext {
+        let config = match firewall::get_config() {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Failed to read firewall settings: {}", e);
+                None
+            }
+        };
+        let services = config.as_ref().map(firewall::services).unwrap_or_default();
+
+        FirewallContext {
+            back: None,
+            config,
+            flash_name: None,
+            flash_msg: None,
+            interfaces: ipconfig::interfaces(),
+            pending: reverter.status("firewall"),
+            ruleset: firewall::get_ruleset().ok(),
+            services,
+            title: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct FlashContext {
     pub flash_name: Option<String>,
@@ -211,6 +434,51 @@ impl HelpContext {
     }
 }
 
+// used in /setup and /setup/<step> for the first-run setup wizard
+#[derive(Debug, Serialize)]
+pub struct SetupContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub hostname: Option<String>,
+    pub next: Option<String>,
+    pub step: Option<String>,
+    pub step_number: usize,
+    pub steps_total: usize,
+    pub title: Option<String>,
+}
+
+impl SetupContext {
+    pub fn build() -> SetupContext {
+        SetupContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            hostname: None,
+            next: None,
+            step: None,
+            step_number: 0,
+            steps_total: setup::STEPS.len(),
+            title: None,
+        }
+    }
+
+    // context for a step of the wizard
+    pub fn build_step(step: &str) -> SetupContext {
+        let mut context = SetupContext::build();
+        context.step_number = setup::STEPS
+            .iter()
+            .position(|s| *s == step)
+            .map_or(context.steps_total, |index| index + 1);
+        context.next = setup::next_step(step).map(|next| next.to_string());
+        if step == "hostname" {
+            context.hostname = hostname::get().ok();
+        }
+        context.step = Some(step.to_string());
+        context
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct HomeContext {
     pub flash_name: Option<String>,
@@ -228,6 +496,99 @@ impl HomeContext {
     }
 }
 
+// used in /settings/datetime for the time, timezone and ntp status
+#[derive(Debug, Serialize)]
+pub struct DateTimeContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub status: Option<TimeStatus>,
+    pub timezones: Vec<String>,
+    pub title: Option<String>,
+}
+
+impl DateTimeContext {
+    pub fn build() -> DateTimeContext {
+        let status = match datetime::get_status() {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!("Failed to retrieve time status: {}", e);
+                None
+            }
+        };
+
+        DateTimeContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            status,
+            timezones: datetime::timezones(),
+            title: None,
+        }
+    }
+}
+
+// used in /settings/hostname for the hostname and mdns name
+#[derive(Debug, Serialize)]
+pub struct HostnameContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub hostname: Option<String>,
+    pub mdns_name: String,
+    pub title: Option<String>,
+}
+
+impl HostnameContext {
+    pub fn build() -> HostnameContext {
+        HostnameContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            hostname: hostname::get().ok(),
+            mdns_name: hostname::mdns_name(),
+            title: None,
+        }
+    }
+}
+
+// used in /network/ip for per-interface ip settings
+#[derive(Debug, Serialize)]
+pub struct IpContext {
+    pub back: Option<String>,
+    pub config: Option<IpConfig>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub iface: String,
+    pub interfaces: Vec<String>,
+    // unconfirmed settings which will be reverted (if any)
+    pub pending: Option<PendingStatus>,
+    pub title: Option<String>,
+}
+
+impl IpContext {
+    pub fn build(iface: &str, reverter: &Reverter) -> IpContext {
+        let config = match ipconfig::get_config(iface) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                warn!("Failed to read IP settings for {}: {}", iface, e);
+                None
+            }
+        };
+
+        IpContext {
+            back: None,
+            config,
+            flash_name: None,
+            flash_msg: None,
+            iface: iface.to_string(),
+            interfaces: ipconfig::interfaces(),
+            pending: reverter.status(&format!("ip-{}", iface)),
+            title: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct LoginContext {
     pub back: Option<String>,
@@ -272,6 +633,8 @@ pub struct ConfigureDNSContext {
     pub dyndns_subdomain: String,
     pub enable_dyndns: bool,
     pub is_dyndns_online: bool,
+    // https certificates and the state of the acme requests
+    pub tls: Option<tls::Status>,
     pub back: Option<String>,
     pub title: Option<String>,
     pub flash_name: Option<String>,
@@ -285,11 +648,19 @@ impl ConfigureDNSContext {
         let is_dyndns_online = is_dns_updater_online().unwrap();
         let dyndns_subdomain =
             get_dyndns_subdomain(&dyndns_fulldomain).unwrap_or(dyndns_fulldomain);
+        let tls = match tls::get_status() {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!("Failed to retrieve certificate status: {}", e);
+                None
+            }
+        };
         ConfigureDNSContext {
             external_domain: peach_config.external_domain,
             dyndns_subdomain,
             enable_dyndns: peach_config.dyn_enabled,
             is_dyndns_online,
+            tls,
             back: None,
             title: None,
             flash_name: None,
@@ -298,6 +669,36 @@ impl ConfigureDNSContext {
     }
 }
 
+// used in /network/dns/status for the dynamic dns status and history
+#[derive(Debug, Serialize)]
+pub struct DnsStatusContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub status: Option<dyndns::Status>,
+    pub title: Option<String>,
+}
+
+impl DnsStatusContext {
+    pub fn build() -> DnsStatusContext {
+        let status = match dyndns::get_status() {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!("Failed to retrieve dynamic DNS status: {}", e);
+                None
+            }
+        };
+
+        DnsStatusContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            status,
+            title: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct ChangePasswordContext {
     pub back: Option<String>,
@@ -399,10 +800,15 @@ impl SendPasswordResetContext {
 
 #[derive(Debug, Serialize)]
 pub struct NetworkContext {
+    pub ap_clients: Option<usize>,
     pub ap_ip: String,
+    // svg qr code for joining the access point
+    pub ap_qr: Option<String>,
     pub ap_ssid: String,
     pub ap_state: String,
     pub ap_traffic: Option<Traffic>,
+    // wifi fallback settings and most recent events
+    pub fallback: Option<fallback::Status>,
     pub wlan_ip: String,
     pub wlan_rssi: Option<String>,
     pub wlan_scan: Option<Vec<Scan>>,
@@ -410,6 +816,7 @@ pub struct NetworkContext {
     pub wlan_state: String,
     pub wlan_status: String,
     pub wlan_traffic: Option<Traffic>,
+    pub wired: Vec<WiredInterface>,
     pub flash_name: Option<String>,
     pub flash_msg: Option<String>,
     // allows for passing in the ssid of a chosen access point
@@ -423,6 +830,7 @@ pub struct NetworkContext {
 
 impl NetworkContext {
     pub fn build() -> NetworkContext {
+        let ap_clients = ap::get_clients().ok().map(|clients| clients.len());
         let ap_ip = match network_client::ip("ap0") {
             Ok(ip) => ip,
             Err(_) => "x.x.x.x".to_string(),
@@ -435,6 +843,13 @@ impl NetworkContext {
             Ok(state) => state,
             Err(_) => "Interface unavailable".to_string(),
         };
+        let ap_qr = if ap_state == "up" {
+            qr::ap_svg()
+                .map_err(|err| warn!("Failed to generate access point QR code: {}", err))
+                .ok()
+        } else {
+            None
+        };
         let ap_traffic = match network_client::traffic("ap0") {
             Ok(traffic) => {
                 let mut t = traffic;
@@ -471,10 +886,7 @@ impl NetworkContext {
             Ok(ip) => ip,
             Err(_) => "x.x.x.x".to_string(),
         };
-        let wlan_rssi = match network_client::rssi_percent("wlan0") {
-            Ok(rssi) => Some(rssi),
-            Err(_) => None,
-        };
+        let wlan_rssi = signal::current_quality().map(|quality| quality.to_string());
         let wlan_scan = match network_client::available_networks("wlan0") {
             Ok(networks) => {
                 let scan: Vec<Scan> = serde_json::from_str(networks.as_str())
@@ -527,12 +939,25 @@ impl NetworkContext {
             }
             Err(_) => None,
         };
+        let fallback = match fallback::get_status() {
+            Ok(mut status) => {
+                status.events.truncate(FALLBACK_EVENTS_SHOWN);
+                Some(status)
+            }
+            Err(err) => {
+                warn!("Failed to retrieve WiFi fallback status: {}", err);
+                None
+            }
+        };
 
         NetworkContext {
+            ap_clients,
             ap_ip,
+            ap_qr,
             ap_ssid,
             ap_state,
             ap_traffic,
+            fallback,
             wlan_ip,
             wlan_rssi,
             wlan_scan,
@@ -540,6 +965,7 @@ impl NetworkContext {
             wlan_state,
             wlan_status,
             wlan_traffic,
+            wired: wired::get_interfaces(),
             flash_name: None,
             flash_msg: None,
             selected: None,
@@ -557,6 +983,8 @@ pub struct NetworkAddContext {
     pub flash_msg: Option<String>,
     pub selected: Option<String>,
     pub title: Option<String>,
+    // credentials read from a wifi qr code, used to prefill the form
+    pub wifi: Option<WiFi>,
 }
 
 impl NetworkAddContext {
@@ -567,6 +995,7 @@ impl NetworkAddContext {
             flash_msg: None,
             selected: None,
             title: None,
+            wifi: None,
         }
     }
 }
@@ -576,20 +1005,53 @@ impl NetworkAddContext {
 pub struct NetworkAlertContext {
     pub alert: Alert,
     pub back: Option<String>,
+    pub cycle: Cycle,
+    // usage for the current and previous billing cycles
+    pub cycle_status: Option<CycleStatus>,
+    pub cycles: Vec<CycleUsage>,
     pub data_total: Data, // combined stored and current wifi traffic in bytes
     pub flash_name: Option<String>,
     pub flash_msg: Option<String>,
     pub threshold: Threshold,
     pub title: Option<String>,
-    pub traffic: Traffic, // current wifi traffic in bytes (since boot)
+    pub traffic: Traffic,           // current wifi traffic in bytes (since boot)
+    pub wired: Vec<WiredInterface>, // included in the usage total
 }
 
 impl NetworkAlertContext {
     pub fn build() -> NetworkAlertContext {
-        let alert = monitor::get_alerts().unwrap();
-        // stored wifi data values as bytes
-        let stored_traffic = monitor::get_data().unwrap();
-        let threshold = monitor::get_thresholds().unwrap();
+        // a missing or corrupt data store is reported rather than rendered
+        // as zeroed values
+        let mut store_err = None;
+        let mut report = |err: PeachWebError| {
+            warn!("Failed to read data usage store: {}", err);
+            store_err = Some(err.to_string());
+        };
+        let alert = monitor::get_alerts().unwrap_or_else(|e| {
+            report(e);
+            Alert::default()
+        });
+        // stored and current wifi data values as bytes
+        let data_total = monitor::get_data().unwrap_or_else(|e| {
+            report(e);
+            Data::default()
+        });
+        let threshold = monitor::get_thresholds().unwrap_or_else(|e| {
+            report(e);
+            Threshold::default()
+        });
+        let cycle = monitor::get_cycle().unwrap_or_else(|e| {
+            report(e);
+            Cycle::default()
+        });
+        let cycle_status = monitor::get_cycle_status().unwrap_or_else(|e| {
+            report(e);
+            None
+        });
+        let cycles = monitor::get_cycles().unwrap_or_else(|e| {
+            report(e);
+            Vec::new()
+        });
         // current wifi traffic values as bytes
         let traffic = match network_client::traffic("wlan0") {
             Ok(t) => t,
@@ -601,19 +1063,19 @@ impl NetworkAlertContext {
             },
         };
 
-        let current_traffic = traffic.received + traffic.transmitted;
-        let total = stored_traffic.total + current_traffic;
-        let data_total = Data { total };
-
         NetworkAlertContext {
             alert,
             back: None,
+            cycle,
+            cycle_status,
+            cycles,
             data_total,
-            flash_name: None,
-            flash_msg: None,
+            flash_name: store_err.as_ref().map(|_| "error".to_string()),
+            flash_msg: store_err.map(|err| format!("Failed to read data usage: {}", err)),
             threshold,
             title: None,
             traffic,
+            wired: wired::get_interfaces(),
         }
     }
 }
@@ -623,6 +1085,10 @@ pub struct NetworkDetailContext {
     pub back: Option<String>,
     pub flash_name: Option<String>,
     pub flash_msg: Option<String>,
+    // svg qr code for joining the selected network (if saved)
+    pub qr_code: Option<String>,
+    // svg sparkline of the recorded signal strength of the selected network
+    pub rssi_history: Option<String>,
     pub saved_aps: Vec<Networks>,
     pub selected: Option<String>,
     pub title: Option<String>,
@@ -661,10 +1127,7 @@ impl NetworkDetailContext {
             }
             Err(_) => Vec::new(),
         };
-        let wlan_rssi = match network_client::rssi_percent("wlan0") {
-            Ok(rssi) => Some(rssi),
-            Err(_) => None,
-        };
+        let wlan_rssi = signal::current_quality().map(|quality| quality.to_string());
         // list of networks currently in range (online & accessible)
         let wlan_scan = match network_client::available_networks("wlan0") {
             Ok(networks) => {
@@ -722,15 +1185,16 @@ impl NetworkDetailContext {
         let mut wlan_networks = HashMap::new();
         for ap in wlan_scan {
             let ssid = ap.ssid.clone();
-            let rssi = ap.signal_level.clone();
-            // parse the string to a signed integer (for math)
-            let rssi_parsed = rssi.parse::<i32>().unwrap();
             // perform rssi (dBm) to quality (%) conversion
-            let quality_percent = 2 * (rssi_parsed + 100);
+            let quality_percent = ap
+                .signal_level
+                .parse::<i32>()
+                .ok()
+                .map(|rssi| i32::from(signal::quality(rssi)));
             let ap_detail = AccessPoint {
                 detail: Some(ap),
                 state: "Available".to_string(),
-                signal: Some(quality_percent),
+                signal: quality_percent,
             };
             wlan_networks.insert(ssid, ap_detail);
         }
@@ -751,6 +1215,8 @@ impl NetworkDetailContext {
             back: None,
             flash_name: None,
             flash_msg: None,
+            qr_code: None,
+            rssi_history: None,
             saved_aps,
             selected: None,
             title: None,
@@ -832,6 +1298,37 @@ impl NetworkListContext {
     }
 }
 
+// used in /network/wifi/priority for saved network preferences
+#[derive(Debug, Serialize)]
+pub struct NetworkPriorityContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    // saved networks, most preferred first
+    pub networks: Option<Vec<SavedNetwork>>,
+    pub title: Option<String>,
+}
+
+impl NetworkPriorityContext {
+    pub fn build() -> NetworkPriorityContext {
+        let networks = match wifi::saved_networks() {
+            Ok(networks) => Some(networks),
+            Err(e) => {
+                warn!("Failed to retrieve saved network priorities: {}", e);
+                None
+            }
+        };
+
+        NetworkPriorityContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            networks,
+            title: None,
+        }
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct PeerContext {
     pub back: Option<String>,
@@ -888,3 +1385,60 @@ impl ShutdownContext {
         }
     }
 }
+
+// used in /network/wired?<iface> for wired interface details
+#[derive(Debug, Serialize)]
+pub struct WiredContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub iface: Option<WiredInterface>,
+    pub ip_config: Option<IpConfig>,
+    pub title: Option<String>,
+}
+
+impl WiredContext {
+    pub fn build(name: &str) -> WiredContext {
+        let iface = wired::get_interface(name);
+        let ip_config = iface.as_ref().and_then(|_| ipconfig::get_config(name).ok());
+
+        WiredContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            iface,
+            ip_config,
+            title: None,
+        }
+    }
+}
+
+// used in /network/yggdrasil for mesh networking settings and peers
+#[derive(Debug, Serialize)]
+pub struct YggdrasilContext {
+    pub back: Option<String>,
+    pub flash_name: Option<String>,
+    pub flash_msg: Option<String>,
+    pub status: Option<yggdrasil::Status>,
+    pub title: Option<String>,
+}
+
+impl YggdrasilContext {
+    pub fn build() -> YggdrasilContext {
+        let status = match yggdrasil::get_status() {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!("Failed to retrieve Yggdrasil status: {}", e);
+                None
+            }
+        };
+
+        YggdrasilContext {
+            back: None,
+            flash_name: None,
+            flash_msg: None,
+            status,
+            title: None,
+        }
+    }
+}
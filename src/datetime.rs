@@ -0,0 +1,172 @@
+//! Date, time, timezone and NTP settings.
+//!
+//! The Raspberry Pi has no real-time clock, so a device which boots without
+//! a network connection starts with the time it was last shut down, which
+//! breaks Scuttlebutt timestamps and TLS certificate checks. The clock is
+//! normally kept right by NTP (`systemd-timesyncd`); when that is not
+//! possible, it can be set from the time of the admin's browser.
+//!
+//! The time status is read from `timedatectl show`, and the timezone and NTP
+//! settings are changed with `timedatectl`.
+
+use std::process::Command;
+
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use log::info;
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use crate::device;
+use crate::error::PeachWebError;
+
+/// Earliest time the clock can be set to (2021-01-01 00:00:00 UTC)
+const MIN_TIMESTAMP: i64 = 1_609_459_200;
+/// Latest time the clock can be set to (2100-01-01 00:00:00 UTC)
+const MAX_TIMESTAMP: i64 = 4_102_444_800;
+
+/// Time, timezone and NTP status of the device
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct TimeStatus {
+    pub timestamp: i64,         // unix timestamp
+    pub local: String,          // local time (eg. "2021-06-01 14:30:00 NZST")
+    pub timezone: String,       // eg. "Pacific/Auckland"
+    pub ntp_available: bool,    // an NTP service is installed
+    pub ntp_enabled: bool,      // the NTP service is enabled
+    pub ntp_synchronized: bool, // the clock has been synchronised
+}
+
+/// Timezone setting (user-defined)
+#[derive(Debug, Deserialize, FromForm)]
+pub struct TimezoneForm {
+    pub timezone: String,
+}
+
+/// NTP setting (user-defined)
+#[derive(Debug, Deserialize, FromForm)]
+pub struct NtpForm {
+    pub enabled: bool,
+}
+
+/// Time to set the clock to (eg. from the browser)
+#[derive(Debug, Deserialize, FromForm)]
+pub struct ClockForm {
+    pub timestamp: i64, // unix timestamp
+}
+
+/// Parse the `key=value` output of `timedatectl show`
+pub fn parse_show(output: &str) -> TimeStatus {
+    let mut status = TimeStatus::default();
+    for line in output.lines() {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or_default();
+        let value = parts.next().unwrap_or_default().trim();
+        match key {
+            "Timezone" => status.timezone = value.to_string(),
+            "CanNTP" => status.ntp_available = value == "yes",
+            "NTP" => status.ntp_enabled = value == "yes",
+            "NTPSynchronized" => status.ntp_synchronized = value == "yes",
+            _ => (),
+        }
+    }
+
+    status
+}
+
+/// Format a unix timestamp as a local time in the given timezone (UTC if the
+/// timezone is unknown)
+pub fn local_time(timestamp: i64, timezone: &str) -> String {
+    let tz: Tz = timezone.parse().unwrap_or(Tz::UTC);
+    tz.timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S %Z")
+        .to_string()
+}
+
+/// Retrieve the time, timezone and NTP status
+pub fn get_status() -> Result<TimeStatus, PeachWebError> {
+    let output = Command::new("timedatectl")
+        .arg("show")
+        .output()
+        .map_err(|e| PeachWebError::SystemCommand {
+            cmd: "timedatectl".to_string(),
+            msg: e.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(PeachWebError::SystemCommand {
+            cmd: "timedatectl".to_string(),
+            msg: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    let mut status = parse_show(&String::from_utf8_lossy(&output.stdout));
+    status.timestamp = Utc::now().timestamp();
+    status.local = local_time(status.timestamp, &status.timezone);
+
+    Ok(status)
+}
+
+/// Timezones of the tz database, as known to the system
+pub fn timezones() -> Vec<String> {
+    Command::new("timedatectl")
+        .arg("list-timezones")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|tz| tz.trim().to_string())
+                .filter(|tz| !tz.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Ensure a timezone is in the tz database
+pub fn validate_timezone(timezone: &str) -> Result<(), PeachWebError> {
+    match timezone.parse::<Tz>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(PeachWebError::InvalidDateTime {
+            msg: format!("Unknown timezone: {}", timezone),
+        }),
+    }
+}
+
+/// Ensure a time to set the clock to is plausible
+pub fn validate_timestamp(timestamp: i64) -> Result<(), PeachWebError> {
+    if timestamp < MIN_TIMESTAMP || timestamp >= MAX_TIMESTAMP {
+        Err(PeachWebError::InvalidDateTime {
+            msg: "Time must be between 2021 and 2100".to_string(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Validate and set the timezone
+pub fn set_timezone(timezone: &str) -> Result<(), PeachWebError> {
+    validate_timezone(timezone)?;
+    info!("Setting timezone to {}", timezone);
+    device::run_privileged("timedatectl", &["set-timezone", timezone])?;
+
+    Ok(())
+}
+
+/// Enable or disable time synchronisation with NTP
+pub fn set_ntp(enabled: bool) -> Result<(), PeachWebError> {
+    info!("Setting NTP synchronisation to {}", enabled);
+    let value = if enabled { "true" } else { "false" };
+    device::run_privileged("timedatectl", &["set-ntp", value])?;
+
+    Ok(())
+}
+
+/// Validate and set the system clock. Unlike `timedatectl set-time`, this
+/// works while NTP is enabled, which corrects the clock again once it can.
+pub fn set_clock(timestamp: i64) -> Result<(), PeachWebError> {
+    validate_timestamp(timestamp)?;
+    info!("Setting the clock to {}", Utc.timestamp(timestamp, 0));
+    device::run_privileged("date", &["--utc", &format!("--set=@{}", timestamp)])?;
+
+    Ok(())
+}
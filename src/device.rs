@@ -1,10 +1,13 @@
 //! System calls for modifying the state of the PeachCloud device.
 
 use std::io;
-use std::process::{Command, Output};
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
 
 use log::info;
 
+use crate::error::PeachWebError;
+
 /// Executes a system command to reboot the device immediately.
 pub fn reboot() -> io::Result<Output> {
     info!("Rebooting the device");
@@ -26,3 +29,59 @@ pub fn shutdown() -> io::Result<Output> {
     // TODO: send "shutting down..." message to `peach-oled` for display
     Command::new("sudo").arg("shutdown").arg("now").output()
 }
+
+/// Executes a system command with root privileges, returning the standard
+/// output if the command succeeds.
+pub fn run_privileged(cmd: &str, args: &[&str]) -> Result<String, PeachWebError> {
+    let output = Command::new("sudo")
+        .arg(cmd)
+        .args(args)
+        .output()
+        .map_err(|e| PeachWebError::SystemCommand {
+            cmd: cmd.to_string(),
+            msg: e.to_string(),
+        })?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(PeachWebError::SystemCommand {
+            cmd: cmd.to_string(),
+            msg: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        })
+    }
+}
+
+/// Reads a system configuration file which is only readable by root.
+pub fn read_file(path: &str) -> Result<String, PeachWebError> {
+    run_privileged("cat", &[path])
+}
+
+/// Writes a system configuration file which is only writable by root.
+pub fn write_file(path: &str, contents: &str) -> Result<(), PeachWebError> {
+    info!("Writing {}", path);
+    let err = |msg: String| PeachWebError::SystemCommand {
+        cmd: "tee".to_string(),
+        msg,
+    };
+    let mut child = Command::new("sudo")
+        .arg("tee")
+        .arg(path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| err(e.to_string()))?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin
+            .write_all(contents.as_bytes())
+            .map_err(|e| err(e.to_string()))?;
+    }
+    let output = child.wait_with_output().map_err(|e| err(e.to_string()))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()))
+    }
+}
@@ -0,0 +1,503 @@
+//! Connectivity diagnostics.
+//!
+//! A diagnostics run works through a fixed sequence of checks: the default
+//! route, a ping of the gateway, DNS resolution, an HTTP request, a
+//! traceroute and a path MTU probe. The targets of each check can be chosen
+//! by the admin; sensible defaults are used otherwise.
+//!
+//! Checks run one at a time in a background thread and each result is
+//! published as soon as it is available, so the UI can show progress while
+//! slower checks (eg. the traceroute) are still running. Once all checks have
+//! completed, the results are summarised in a plain-language verdict.
+//!
+//! Results are not streamed over the request which starts a run. A run can
+//! take a minute or more, and Rocket serves each request on one of a small
+//! pool of worker threads for as long as its response lasts. Instead, the
+//! diagnostics page polls `GET /api/v1/network/diagnostics` while the run is
+//! in progress (or reloads itself if JavaScript is disabled) and shows each
+//! result as it arrives.
+
+use std::net::ToSocketAddrs;
+use std::process::{Command, Output};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use chrono::Utc;
+use log::{debug, info};
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PeachWebError;
+
+/// Default hostname to resolve
+pub const DEFAULT_DNS_HOST: &str = "peachcloud.org";
+/// Default URL for the HTTP reachability check
+pub const DEFAULT_HTTP_URL: &str = "http://connectivitycheck.gstatic.com/generate_204";
+/// Default host for the traceroute and MTU probe
+pub const DEFAULT_TRACE_HOST: &str = "1.1.1.1";
+
+// maximum number of hops for the traceroute
+const MAX_HOPS: &str = "15";
+// smallest and largest icmp payloads probed (ipv4 mtu minus 28 header bytes)
+const MIN_PAYLOAD: u32 = 548;
+const MAX_PAYLOAD: u32 = 1472;
+// ip and icmp header bytes added to the ping payload
+const HEADER_BYTES: u32 = 28;
+
+/// Targets for a diagnostics run (user-defined; empty values use defaults)
+#[derive(Clone, Debug, Default, Deserialize, FromForm, Serialize)]
+pub struct Targets {
+    pub gateway: Option<String>, // defaults to the gateway of the default route
+    pub dns: Option<String>,     // hostname to resolve
+    pub http: Option<String>,    // url to request
+    pub trace: Option<String>,   // host for the traceroute and mtu probe
+}
+
+// filter out empty optional form values
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+// hostnames and ip addresses only (this also keeps targets from being
+// mistaken for command options)
+fn valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= 253
+        && !host.starts_with('-')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == ':')
+}
+
+impl Targets {
+    /// Ensure the targets are valid hosts and URLs
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let err = |msg: &str| {
+            Err(PeachWebError::InvalidDiagnostics {
+                msg: msg.to_string(),
+            })
+        };
+        for host in &[&self.gateway, &self.dns, &self.trace] {
+            if let Some(host) = non_empty(host) {
+                if !valid_host(host) {
+                    return err(&format!("Invalid host: {}", host));
+                }
+            }
+        }
+        if let Some(url) = non_empty(&self.http) {
+            let scheme = url.starts_with("http://") || url.starts_with("https://");
+            if !scheme || url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                return err("The HTTP check URL must start with http:// or https://");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hostname to resolve
+    pub fn dns_host(&self) -> String {
+        non_empty(&self.dns).unwrap_or(DEFAULT_DNS_HOST).to_string()
+    }
+
+    /// URL for the HTTP reachability check
+    pub fn http_url(&self) -> String {
+        non_empty(&self.http)
+            .unwrap_or(DEFAULT_HTTP_URL)
+            .to_string()
+    }
+
+    /// Host for the traceroute and MTU probe
+    pub fn trace_host(&self) -> String {
+        non_empty(&self.trace)
+            .unwrap_or(DEFAULT_TRACE_HOST)
+            .to_string()
+    }
+}
+
+/// Result of a single check
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckResult {
+    pub check: String, // "connection", "gateway", "dns", "http", "traceroute" or "mtu"
+    pub target: String,
+    pub ok: bool,
+    pub summary: String,
+    pub details: Vec<String>, // eg. resolved addresses or traceroute hops
+    pub value: Option<u32>,   // numeric result (eg. the path mtu in bytes)
+    pub duration: u64,        // milliseconds
+}
+
+impl CheckResult {
+    fn new(check: &str, target: &str, ok: bool, summary: String) -> CheckResult {
+        CheckResult {
+            check: check.to_string(),
+            target: target.to_string(),
+            ok,
+            summary,
+            details: Vec::new(),
+            value: None,
+            duration: 0,
+        }
+    }
+}
+
+/// Progress and results of the latest diagnostics run
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Report {
+    pub running: bool,
+    pub started: Option<i64>, // unix timestamp
+    pub targets: Targets,
+    pub results: Vec<CheckResult>,
+    pub verdict: Option<String>,
+}
+
+/// Shared diagnostics state (placed in managed state)
+#[derive(Clone, Default)]
+pub struct Diagnostics {
+    report: Arc<Mutex<Report>>,
+}
+
+impl Diagnostics {
+    // a panic while holding the lock leaves the report usable
+    fn lock(&self) -> MutexGuard<Report> {
+        self.report.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Progress and results of the latest run
+    pub fn report(&self) -> Report {
+        self.lock().clone()
+    }
+
+    /// Start a diagnostics run in a background thread. Only one run may be
+    /// in progress at a time.
+    pub fn start(&self, targets: Targets) -> Result<(), PeachWebError> {
+        targets.validate()?;
+        {
+            let mut report = self.lock();
+            if report.running {
+                return Err(PeachWebError::DiagnosticsRunning);
+            }
+            *report = Report {
+                running: true,
+                started: Some(Utc::now().timestamp()),
+                targets: targets.clone(),
+                results: Vec::new(),
+                verdict: None,
+            };
+        }
+
+        info!("Running connectivity diagnostics");
+        let diagnostics = self.clone();
+        thread::spawn(move || {
+            let mut gateway = non_empty(&targets.gateway).map(str::to_string);
+            let run = |check: &dyn Fn() -> CheckResult| {
+                let start = Instant::now();
+                let mut result = check();
+                result.duration = start.elapsed().as_millis() as u64;
+                debug!("Diagnostics check {}: {}", result.check, result.summary);
+                diagnostics.lock().results.push(result.clone());
+                result
+            };
+
+            let connection = run(&check_connection);
+            if gateway.is_none() {
+                gateway = connection.details.first().cloned();
+            }
+            run(&|| check_gateway(gateway.as_deref()));
+            run(&|| check_dns(&targets.dns_host()));
+            run(&|| check_http(&targets.http_url()));
+            run(&|| check_traceroute(&targets.trace_host()));
+            run(&|| check_mtu(&targets.trace_host()));
+
+            let mut report = diagnostics.lock();
+            report.verdict = Some(verdict(&report.results));
+            report.running = false;
+        });
+
+        Ok(())
+    }
+}
+
+// run a command, treating failure to start it as a failed check
+fn run_command(cmd: &str, args: &[&str]) -> Result<Output, String> {
+    Command::new(cmd)
+        .args(args)
+        .output()
+        .map_err(|err| format!("Failed to run {}: {}", cmd, err))
+}
+
+/// Parse the gateway and interface of the default route from the output of
+/// `ip route show default`
+pub fn parse_default_route(output: &str) -> Option<(String, String)> {
+    output.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let value = |key: &str| {
+            fields
+                .iter()
+                .position(|f| *f == key)
+                .and_then(|i| fields.get(i + 1))
+                .map(|v| v.to_string())
+        };
+        if fields.first() != Some(&"default") {
+            return None;
+        }
+        Some((value("via")?, value("dev")?))
+    })
+}
+
+/// Parse the number of replies and the average round-trip time (in
+/// milliseconds) from the output of `ping`
+pub fn parse_ping(output: &str) -> (u32, Option<f64>) {
+    let mut received = 0;
+    let mut average = None;
+    for line in output.lines() {
+        if line.contains("packets transmitted") {
+            received = line
+                .split(',')
+                .find(|part| part.contains("received"))
+                .and_then(|part| part.split_whitespace().next())
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(0);
+        } else if line.starts_with("rtt") || line.starts_with("round-trip") {
+            // rtt min/avg/max/mdev = 1.012/2.034/3.120/0.410 ms
+            average = line
+                .split('=')
+                .nth(1)
+                .and_then(|values| values.trim().split('/').nth(1))
+                .and_then(|avg| avg.parse().ok());
+        }
+    }
+    (received, average)
+}
+
+/// Parse the destination address and the hops from the output of
+/// `traceroute -n`
+pub fn parse_traceroute(output: &str) -> (Option<String>, Vec<String>) {
+    let mut lines = output.lines();
+    // traceroute to peachcloud.org (116.203.12.107), 15 hops max, 60 byte packets
+    let destination = lines.next().and_then(|header| {
+        let start = header.find('(')? + 1;
+        let end = header[start..].find(')')? + start;
+        Some(header[start..end].to_string())
+    });
+    let hops = lines
+        .map(|line| line.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect();
+    (destination, hops)
+}
+
+/// Check for a default route (ie. a connection to a network with a gateway)
+pub fn check_connection() -> CheckResult {
+    let output = match run_command("ip", &["route", "show", "default"]) {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(err) => return CheckResult::new("connection", "", false, err),
+    };
+    match parse_default_route(&output) {
+        Some((gateway, iface)) => {
+            let mut result = CheckResult::new(
+                "connection",
+                &iface,
+                true,
+                format!("Default route via {} on {}", gateway, iface),
+            );
+            result.details.push(gateway);
+            result
+        }
+        None => CheckResult::new(
+            "connection",
+            "",
+            false,
+            "No default route: the device is not connected to a network with internet access"
+                .to_string(),
+        ),
+    }
+}
+
+/// Ping the gateway
+pub fn check_gateway(gateway: Option<&str>) -> CheckResult {
+    let gateway = match gateway {
+        Some(gateway) => gateway,
+        None => return CheckResult::new("gateway", "", false, "No gateway to ping".to_string()),
+    };
+    let output = match run_command("ping", &["-c", "3", "-W", "2", gateway]) {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(err) => return CheckResult::new("gateway", gateway, false, err),
+    };
+    let (received, average) = parse_ping(&output);
+    let mut result = if received > 0 {
+        CheckResult::new(
+            "gateway",
+            gateway,
+            true,
+            format!(
+                "{} of 3 replies received{}",
+                received,
+                average.map_or(String::new(), |avg| format!(", average {:.1} ms", avg))
+            ),
+        )
+    } else {
+        CheckResult::new(
+            "gateway",
+            gateway,
+            false,
+            "The gateway did not reply".to_string(),
+        )
+    };
+    result.value = average.map(|avg| avg.round() as u32);
+    result
+}
+
+/// Resolve a hostname with the system resolver
+pub fn check_dns(host: &str) -> CheckResult {
+    match (host, 0).to_socket_addrs() {
+        Ok(addrs) => {
+            let mut addresses: Vec<String> = addrs.map(|addr| addr.ip().to_string()).collect();
+            addresses.dedup();
+            let mut result = CheckResult::new(
+                "dns",
+                host,
+                !addresses.is_empty(),
+                format!("Resolved to {} address(es)", addresses.len()),
+            );
+            result.details = addresses;
+            result
+        }
+        Err(err) => CheckResult::new("dns", host, false, format!("Failed to resolve: {}", err)),
+    }
+}
+
+/// Request a URL and check for a successful (2xx or 3xx) response
+pub fn check_http(url: &str) -> CheckResult {
+    let args = [
+        "--silent",
+        "--show-error",
+        "--output",
+        "/dev/null",
+        "--max-time",
+        "10",
+        "--write-out",
+        "%{http_code}",
+        url,
+    ];
+    let output = match run_command("curl", &args) {
+        Ok(output) => output,
+        Err(err) => return CheckResult::new("http", url, false, err),
+    };
+    let code: u32 = String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .unwrap_or(0);
+    let mut result = match code {
+        200..=399 => CheckResult::new("http", url, true, format!("HTTP {} response", code)),
+        0 => CheckResult::new(
+            "http",
+            url,
+            false,
+            format!(
+                "No response: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ),
+        _ => CheckResult::new("http", url, false, format!("HTTP {} response", code)),
+    };
+    result.value = Some(code);
+    result
+}
+
+/// Trace the route to a host
+pub fn check_traceroute(host: &str) -> CheckResult {
+    let args = ["-n", "-q", "1", "-w", "2", "-m", MAX_HOPS, host];
+    let output = match run_command("traceroute", &args) {
+        Ok(output) => String::from_utf8_lossy(&output.stdout).to_string(),
+        Err(err) => return CheckResult::new("traceroute", host, false, err),
+    };
+    let (destination, hops) = parse_traceroute(&output);
+    // the last hop is the destination if it was reached
+    let reached = match (&destination, hops.last()) {
+        (Some(destination), Some(last)) => last.split_whitespace().nth(1) == Some(destination),
+        _ => false,
+    };
+    let summary = if reached {
+        format!("Reached {} in {} hops", host, hops.len())
+    } else {
+        format!("Did not reach {} within {} hops", host, MAX_HOPS)
+    };
+    let mut result = CheckResult::new("traceroute", host, reached, summary);
+    result.value = Some(hops.len() as u32);
+    result.details = hops;
+    result
+}
+
+// ping a host once with the given payload size, without fragmentation
+fn probe(host: &str, payload: u32) -> bool {
+    let size = payload.to_string();
+    run_command(
+        "ping",
+        &["-c", "1", "-W", "2", "-M", "do", "-s", &size, host],
+    )
+    .map_or(false, |output| output.status.success())
+}
+
+/// Find the largest packet size which reaches a host without fragmentation
+/// (binary search over the icmp payload size)
+pub fn check_mtu(host: &str) -> CheckResult {
+    if !probe(host, MIN_PAYLOAD) {
+        return CheckResult::new(
+            "mtu",
+            host,
+            false,
+            format!("{} did not reply to ping", host),
+        );
+    }
+    let (mut low, mut high) = (MIN_PAYLOAD, MAX_PAYLOAD);
+    while low < high {
+        let mid = (low + high + 1) / 2;
+        if probe(host, mid) {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    let mtu = low + HEADER_BYTES;
+    let mut result = CheckResult::new("mtu", host, true, format!("Path MTU is {} bytes", mtu));
+    result.value = Some(mtu);
+    result
+}
+
+/// Summarise the results of a diagnostics run in plain language
+pub fn verdict(results: &[CheckResult]) -> String {
+    let find = |check: &str| results.iter().find(|result| result.check == check);
+    let passed = |check: &str| find(check).map_or(false, |result| result.ok);
+    let network = match find("connection") {
+        Some(result) if result.target.starts_with("wlan") => "WiFi",
+        _ => "the local network",
+    };
+
+    if !passed("connection") {
+        return "Not connected to a network with internet access".to_string();
+    }
+    if !passed("gateway") {
+        return format!("Connected to {} but the router is not responding", network);
+    }
+    if !passed("http") {
+        return if passed("dns") {
+            format!(
+                "Connected to {} but websites are unreachable (a captive portal or firewall may be blocking access)",
+                network
+            )
+        } else if passed("traceroute") {
+            "Connected to the internet but DNS is not working".to_string()
+        } else {
+            format!("Connected to {} but no internet", network)
+        };
+    }
+
+    match find("mtu").and_then(|result| result.value) {
+        Some(mtu) if mtu < 1500 => format!(
+            "Connected to the internet (the path MTU of {} bytes is lower than usual, which can cause some sites to stall)",
+            mtu
+        ),
+        _ => "Connected to the internet".to_string(),
+    }
+}
@@ -0,0 +1,364 @@
+//! Dynamic DNS status and history.
+//!
+//! The public IP address of the device is pushed to the PeachCloud dynamic
+//! DNS server by `peach-dyndns-updater` (using `nsupdate` and the TSIG key
+//! obtained when the domain was registered), which records the time of the
+//! last successful update.
+//!
+//! The updater does not record the address it pushed, so whenever `peach-web`
+//! sees a new successful update it looks up the address published for the
+//! domain by the PeachCloud name server (with `dig`) and keeps a history of
+//! its changes in the data store. Failed runs of the updater are read from
+//! its systemd unit and recorded along with failed lookups and updates. It
+//! can also push an update on demand.
+
+use std::net::Ipv4Addr;
+use std::process::Command;
+use std::{thread, time::Duration};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use nest::Value;
+use peach_lib::config_manager;
+use peach_lib::dyndns_client;
+use serde::{Deserialize, Serialize};
+
+use crate::error::PeachWebError;
+use crate::store::DataStore;
+
+/// Default interval between checks of the published address (in seconds)
+const CHECK_INTERVAL: u64 = 300;
+/// Maximum number of recorded address changes
+const HISTORY_LEN: usize = 50;
+/// Maximum number of recorded failures
+const FAILURES_LEN: usize = 20;
+/// Tolerance when comparing times of successful updates (in seconds), since
+/// the updater only reports the time elapsed since its last update
+const UPDATE_SLACK: i64 = 2;
+/// Name server of the dynamic DNS zone, which publishes the pushed addresses
+const NAMESERVER: &str = "ns.peachcloud.org";
+/// Systemd unit running the updater
+const UPDATER_UNIT: &str = "peach-dyndns-updater.service";
+/// Path of the dynamic DNS state within the data store
+const DYNDNS_FILE: [&str; 2] = ["dns", "dyndns"];
+/// Path of the address history within the data store
+const HISTORY_PATH: [&str; 3] = ["dns", "dyndns", "history"];
+/// Path of the recorded failures within the data store
+const FAILURES_PATH: [&str; 3] = ["dns", "dyndns", "failures"];
+/// Path of the time of the last successful update seen within the data store
+const LAST_UPDATE_PATH: [&str; 3] = ["dns", "dyndns", "last_update"];
+/// Path of the exit time of the last failed updater run recorded within the
+/// data store
+const LAST_FAILED_RUN_PATH: [&str; 3] = ["dns", "dyndns", "last_failed_run"];
+
+/// A change of the address published for the dynamic domain
+#[derive(Debug, Deserialize, Serialize)]
+pub struct IpChange {
+    pub time: i64, // unix timestamp
+    pub ip: String,
+}
+
+/// A failed update (or a period without successful updates)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Failure {
+    pub time: i64, // unix timestamp
+    pub msg: String,
+}
+
+/// Dynamic DNS status and history
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub enabled: bool,
+    pub domain: String,            // full dynamic domain
+    pub online: bool,              // the updater succeeded recently
+    pub last_ip: Option<String>,   // published address at the last update seen
+    pub last_success: Option<i64>, // last successful update (unix timestamp)
+    pub failures: Vec<Failure>,    // most recent first
+    pub history: Vec<IpChange>,    // most recent first
+}
+
+/// Find the IPv4 address in the output of `dig +short`, which lists the
+/// targets of any CNAME records before it
+pub fn parse_dig(output: &str) -> Option<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .find(|line| line.parse::<Ipv4Addr>().is_ok())
+        .map(str::to_string)
+}
+
+/// Look up the address published for a domain by the dynamic DNS name server,
+/// ie. the address last pushed by the updater
+pub fn published_ip(domain: &str) -> Result<String, PeachWebError> {
+    let output = Command::new("dig")
+        .args(&["+short", "+time=5", "+tries=2"])
+        .arg(format!("@{}", NAMESERVER))
+        .args(&[domain, "A"])
+        .output()
+        .map_err(|e| PeachWebError::DynDns {
+            msg: format!("Failed to run dig: {}", e),
+        })?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        // dig reports errors such as timeouts on stdout
+        return Err(PeachWebError::DynDns {
+            msg: format!(
+                "Failed to look up {} on {}: {}",
+                domain,
+                NAMESERVER,
+                stdout.trim()
+            ),
+        });
+    }
+
+    parse_dig(&stdout).ok_or_else(|| PeachWebError::DynDns {
+        msg: format!("{} publishes no address for {}", NAMESERVER, domain),
+    })
+}
+
+/// Last run of the updater, from the `key=value` output of `systemctl show`
+#[derive(Debug, Default)]
+pub struct UpdaterRun {
+    pub result: String, // "success" or the reason of the failure
+    pub status: String, // exit status
+    pub exited: String, // exit time, empty if the updater has not run
+}
+
+/// Parse the output of `systemctl show` for the updater unit
+pub fn parse_updater_run(output: &str) -> UpdaterRun {
+    let mut run = UpdaterRun::default();
+    for line in output.lines() {
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or_default();
+        let value = parts.next().unwrap_or_default().trim();
+        match key {
+            "Result" => run.result = value.to_string(),
+            "ExecMainStatus" => run.status = value.to_string(),
+            "ExecMainExitTimestamp" => run.exited = value.to_string(),
+            _ => (),
+        }
+    }
+
+    run
+}
+
+// retrieve the last run of the updater from its systemd unit
+fn updater_run() -> Result<UpdaterRun, PeachWebError> {
+    let output = Command::new("systemctl")
+        .args(&["show", UPDATER_UNIT])
+        .args(&["--property=Result", "--property=ExecMainStatus"])
+        .arg("--property=ExecMainExitTimestamp")
+        .output()
+        .map_err(|e| PeachWebError::SystemCommand {
+            cmd: "systemctl".to_string(),
+            msg: e.to_string(),
+        })?;
+    if !output.status.success() {
+        return Err(PeachWebError::SystemCommand {
+            cmd: "systemctl".to_string(),
+            msg: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(parse_updater_run(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Record the last run of the updater in the given store if it failed and has
+/// not been recorded yet. Returns whether a failure was recorded.
+pub fn record_run_in(store: &DataStore, run: &UpdaterRun, now: i64) -> Result<bool, PeachWebError> {
+    if run.result == "success"
+        || run.exited.is_empty()
+        || store.get_string(&LAST_FAILED_RUN_PATH)? == run.exited
+    {
+        return Ok(false);
+    }
+    store.set_values(
+        &DYNDNS_FILE,
+        vec![("last_failed_run", Value::String(run.exited.clone()))],
+    )?;
+    record_failure_in(
+        store,
+        &format!(
+            "Updater failed: {} (exit status {})",
+            run.result, run.status
+        ),
+        now,
+    )?;
+
+    Ok(true)
+}
+
+/// Whether a successful update at the given time (unix timestamp) has not
+/// been recorded in the given store yet
+pub fn is_new_update_in(store: &DataStore, last_success: i64) -> Result<bool, PeachWebError> {
+    Ok(last_success > store.get_i64(&LAST_UPDATE_PATH)? + UPDATE_SLACK)
+}
+
+/// Record a successful update at the given time (unix timestamp) in the given
+/// store, along with the address it pushed. Returns whether the address
+/// changed.
+pub fn record_update_in(
+    store: &DataStore,
+    last_success: i64,
+    ip: &str,
+    now: i64,
+) -> Result<bool, PeachWebError> {
+    store.set(&LAST_UPDATE_PATH, Value::Int(last_success))?;
+    record_ip_in(store, ip, now)
+}
+
+/// Record an address pushed for the domain in the given store if it differs
+/// from the last one, dropping the oldest changes beyond the history length.
+/// Returns whether the address changed.
+pub fn record_ip_in(store: &DataStore, ip: &str, now: i64) -> Result<bool, PeachWebError> {
+    store.update(&DYNDNS_FILE, |values| {
+        let mut history: Vec<IpChange> = values.get_list("history")?;
+        if history.last().map_or(false, |last| last.ip == ip) {
+            return Ok(false);
+        }
+        history.push(IpChange {
+            time: now,
+            ip: ip.to_string(),
+        });
+        let excess = history.len().saturating_sub(HISTORY_LEN);
+        values.set_list("history", &history[excess..])?;
+
+        Ok(true)
+    })
+}
+
+/// Record a failure in the given store, dropping the oldest failures beyond
+/// the maximum number
+pub fn record_failure_in(store: &DataStore, msg: &str, now: i64) -> Result<(), PeachWebError> {
+    store.update(&DYNDNS_FILE, |values| {
+        let mut failures: Vec<Failure> = values.get_list("failures")?;
+        failures.push(Failure {
+            time: now,
+            msg: msg.to_string(),
+        });
+        let excess = failures.len().saturating_sub(FAILURES_LEN);
+        values.set_list("failures", &failures[excess..])
+    })
+}
+
+/// Record whether the updater is online in the given store, recording a
+/// failure when it goes offline
+pub fn record_online_in(store: &DataStore, online: bool, now: i64) -> Result<(), PeachWebError> {
+    let was_online = store.get_bool(&["dns", "dyndns", "online"])?;
+    if was_online && !online {
+        record_failure_in(store, "No successful update recently", now)?;
+    }
+    if was_online != online {
+        store.set_values(&DYNDNS_FILE, vec![("online", Value::Bool(online))])?;
+    }
+
+    Ok(())
+}
+
+// full dynamic domain, if dynamic dns is enabled
+fn dynamic_domain() -> Result<Option<String>, PeachWebError> {
+    let config = config_manager::load_peach_config()?;
+    if config.dyn_enabled && !config.dyn_domain.is_empty() {
+        Ok(Some(config.dyn_domain))
+    } else {
+        Ok(None)
+    }
+}
+
+// record the state of the updater, its failed runs and, after a new successful
+// update, the address it pushed
+fn check(store: &DataStore, domain: &str) -> Result<(), PeachWebError> {
+    let now = Utc::now().timestamp();
+    let online = dyndns_client::is_dns_updater_online()?;
+    record_online_in(store, online, now)?;
+    if record_run_in(store, &updater_run()?, now)? {
+        warn!("Dynamic DNS updater failed");
+    }
+    let last_success = match dyndns_client::get_num_seconds_since_successful_dns_update()? {
+        Some(secs) => now - secs,
+        None => return Ok(()),
+    };
+    if !is_new_update_in(store, last_success)? {
+        return Ok(());
+    }
+    match published_ip(domain) {
+        Ok(ip) => {
+            if record_update_in(store, last_success, &ip, now)? {
+                info!("Dynamic domain {} now points to {}", domain, ip);
+            }
+        }
+        // retried at the next check, since the update is still unrecorded
+        Err(e) => record_failure_in(store, &e.to_string(), now)?,
+    }
+
+    Ok(())
+}
+
+/// Push the public address of the device to the dynamic DNS server now,
+/// recording the outcome
+pub fn force_update() -> Result<(), PeachWebError> {
+    let domain = dynamic_domain()?.ok_or_else(|| PeachWebError::DynDns {
+        msg: "Dynamic DNS is not enabled".to_string(),
+    })?;
+    let store = DataStore::open()?;
+    info!("Updating dynamic domain {}", domain);
+    match dyndns_client::dyndns_update_ip() {
+        Ok(_) => check(&store, &domain),
+        Err(e) => {
+            let msg = format!("Update failed: {}", e);
+            record_failure_in(&store, &msg, Utc::now().timestamp())?;
+            Err(PeachWebError::DynDns { msg })
+        }
+    }
+}
+
+/// Retrieve the dynamic DNS status and history
+pub fn get_status() -> Result<Status, PeachWebError> {
+    let config = config_manager::load_peach_config()?;
+    let store = DataStore::open()?;
+    let mut history: Vec<IpChange> = store.get_list(&HISTORY_PATH)?;
+    history.reverse();
+    let mut failures: Vec<Failure> = store.get_list(&FAILURES_PATH)?;
+    failures.reverse();
+
+    let (online, last_success) = if config.dyn_enabled {
+        let since = dyndns_client::get_num_seconds_since_successful_dns_update()?;
+        (
+            dyndns_client::is_dns_updater_online()?,
+            since.map(|secs| Utc::now().timestamp() - secs),
+        )
+    } else {
+        (false, None)
+    };
+
+    Ok(Status {
+        enabled: config.dyn_enabled,
+        domain: config.dyn_domain,
+        online,
+        last_ip: history.first().map(|change| change.ip.clone()),
+        last_success,
+        failures,
+        history,
+    })
+}
+
+/// Periodically record the address published for the dynamic domain and
+/// whether the updater is online.
+///
+/// The interval (in seconds) can be configured with the
+/// `PEACH_WEB_DYNDNS_INTERVAL` environment variable.
+pub fn run() {
+    let interval = crate::interval("PEACH_WEB_DYNDNS_INTERVAL", CHECK_INTERVAL);
+
+    loop {
+        debug!("Checking dynamic DNS.");
+        let result = dynamic_domain().and_then(|domain| match domain {
+            Some(domain) => DataStore::open().and_then(|store| check(&store, &domain)),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            warn!("Failed to check dynamic DNS: {}", e);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
@@ -14,6 +14,94 @@ pub enum PeachWebError {
     FailedToRegisterDynDomain { msg: String },
     #[snafu(display("{}: {}", source, msg))]
     PeachLibError { source: PeachError, msg: String },
+    #[snafu(display("Error accessing the monitor store: {}", source))]
+    Store { source: nest::Error },
+    #[snafu(display("Failed to locate the XDG data directory: {}", source))]
+    StoreXdg { source: xdg::BaseDirectoriesError },
+    #[snafu(display("Failed to create the data store directory {}: {}", path, source))]
+    StoreDir {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Error accessing {} in the data store: {}", path, source))]
+    StoreAccess { path: String, source: nest::Error },
+    #[snafu(display("Missing value for {} in the data store", path))]
+    StoreValueMissing { path: String },
+    #[snafu(display("Invalid value for {} in the data store: expected {}", path, expected))]
+    StoreValueInvalid { path: String, expected: String },
+    #[snafu(display(
+        "Data store schema version {} is newer than the supported version {}",
+        version,
+        supported
+    ))]
+    StoreVersion { version: u64, supported: u64 },
+    #[snafu(display("{}", msg))]
+    InvalidBillingCycle { msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidAlertRule { msg: String },
+    #[snafu(display("No alert rule exists with id {}", id))]
+    AlertRuleNotFound { id: u64 },
+    #[snafu(display("{}", msg))]
+    InvalidExport { msg: String },
+    #[snafu(display("Failed to run {}: {}", cmd, msg))]
+    SystemCommand { cmd: String, msg: String },
+    #[snafu(display("There is no unconfirmed {} change", kind))]
+    NoPendingChange { kind: String },
+    #[snafu(display("{}", msg))]
+    InvalidApConfig { msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidIpConfig { msg: String },
+    #[snafu(display("Invalid MAC address: {}", mac))]
+    InvalidMacAddress { mac: String },
+    #[snafu(display("{}", msg))]
+    InvalidWiFi { msg: String },
+    #[snafu(display("Saved network not found: {}", ssid))]
+    UnknownNetwork { ssid: String },
+    #[snafu(display("{}", msg))]
+    InvalidDiagnostics { msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidHostname { msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidFallback { msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidDateTime { msg: String },
+    #[snafu(display("Diagnostics are already running"))]
+    DiagnosticsRunning,
+    #[snafu(display("{}", msg))]
+    InvalidSurvey { msg: String },
+    #[snafu(display("A site survey is already running"))]
+    SurveyRunning,
+    #[snafu(display("{}", msg))]
+    InvalidFirewall { msg: String },
+    #[snafu(display("Failed to read the firewall rules {}: {}", path, source))]
+    FirewallRules {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Failed to connect to the Yggdrasil admin socket {}: {}", path, source))]
+    YggdrasilSocket {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Yggdrasil {} request failed: {}", request, msg))]
+    YggdrasilRequest { request: String, msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidPeer { msg: String },
+    #[snafu(display("Failed to generate QR code: {}", source))]
+    QrCode { source: qrcode::types::QrError },
+    #[snafu(display("Failed to generate certificate: {}", source))]
+    Certificate { source: openssl::error::ErrorStack },
+    #[snafu(display("Failed to access certificate file {}: {}", path, source))]
+    CertificateFile {
+        path: String,
+        source: std::io::Error,
+    },
+    #[snafu(display("Failed to obtain certificate for {}: {}", domain, msg))]
+    Acme { domain: String, msg: String },
+    #[snafu(display("{}", msg))]
+    InvalidDomain { msg: String },
+    #[snafu(display("{}", msg))]
+    DynDns { msg: String },
 }
 
 impl From<serde_json::error::Error> for PeachWebError {
@@ -36,3 +124,21 @@ impl From<PeachError> for PeachWebError {
         }
     }
 }
+
+impl From<qrcode::types::QrError> for PeachWebError {
+    fn from(err: qrcode::types::QrError) -> PeachWebError {
+        PeachWebError::QrCode { source: err }
+    }
+}
+
+impl From<openssl::error::ErrorStack> for PeachWebError {
+    fn from(err: openssl::error::ErrorStack) -> PeachWebError {
+        PeachWebError::Certificate { source: err }
+    }
+}
+
+impl From<nest::Error> for PeachWebError {
+    fn from(err: nest::Error) -> PeachWebError {
+        PeachWebError::Store { source: err }
+    }
+}
@@ -0,0 +1,263 @@
+//! Export traffic, resource alert and device history as CSV or JSON downloads.
+//!
+//! History is recorded hourly by the `monitor` background task. Exports cover
+//! a range of whole days (in UTC), defaulting to the last 30 days.
+
+use std::io::Cursor;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use rocket::http::ContentType;
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use serde::Serialize;
+use serde_json::json;
+
+use crate::error::PeachWebError;
+use crate::monitor;
+
+/// Number of days exported when no start date is given
+const DEFAULT_RANGE_DAYS: i64 = 30;
+
+/// Export file format
+#[derive(Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl Format {
+    /// Parse the format from a query value, defaulting to CSV
+    pub fn parse(format: Option<&str>) -> Result<Format, PeachWebError> {
+        match format {
+            None | Some("csv") => Ok(Format::Csv),
+            Some("json") => Ok(Format::Json),
+            Some(other) => Err(PeachWebError::InvalidExport {
+                msg: format!("Unknown export format: {}", other),
+            }),
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Json => "json",
+        }
+    }
+
+    fn content_type(&self) -> ContentType {
+        match self {
+            Format::Csv => ContentType::new("text", "csv"),
+            Format::Json => ContentType::JSON,
+        }
+    }
+}
+
+/// Range of whole days (in UTC) to export
+#[derive(Debug, PartialEq)]
+pub struct Range {
+    pub from: NaiveDate,
+    pub to: NaiveDate, // inclusive
+}
+
+impl Range {
+    /// Parse the range from `YYYY-MM-DD` query values. The end date defaults
+    /// to today and the start date to 30 days before the end date.
+    pub fn parse(
+        from: Option<&str>,
+        to: Option<&str>,
+        today: NaiveDate,
+    ) -> Result<Range, PeachWebError> {
+        let to = match to {
+            Some(date) if !date.is_empty() => parse_date(date)?,
+            _ => today,
+        };
+        let from = match from {
+            Some(date) if !date.is_empty() => parse_date(date)?,
+            _ => to - chrono::Duration::days(DEFAULT_RANGE_DAYS - 1),
+        };
+        if from > to {
+            return Err(PeachWebError::InvalidExport {
+                msg: "Start date must not be after the end date".to_string(),
+            });
+        }
+
+        Ok(Range { from, to })
+    }
+
+    /// Start and end of the range as unix timestamps (end exclusive)
+    pub fn bounds(&self) -> (i64, i64) {
+        let start = self.from.and_hms(0, 0, 0).timestamp();
+        let end = (self.to + chrono::Duration::days(1))
+            .and_hms(0, 0, 0)
+            .timestamp();
+        (start, end)
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, PeachWebError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| PeachWebError::InvalidExport {
+        msg: format!("Invalid date (expected YYYY-MM-DD): {}", date),
+    })
+}
+
+/// File download, served as an attachment
+pub struct Download {
+    pub filename: String,
+    pub content_type: ContentType,
+    pub body: String,
+}
+
+impl<'r> Responder<'r> for Download {
+    fn respond_to(self, _: &Request) -> response::Result<'r> {
+        Response::build()
+            .header(self.content_type)
+            .raw_header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", self.filename),
+            )
+            .sized_body(Cursor::new(self.body))
+            .ok()
+    }
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// build a csv document from a header and rows of fields
+fn csv(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut out = header.join(",");
+    out.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+        out.push_str(&fields.join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+// format a unix timestamp as a utc date and time
+fn time(timestamp: i64) -> String {
+    Utc.timestamp(timestamp, 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+// format an optional metric value
+fn optional(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.1}", v)).unwrap_or_default()
+}
+
+// build a json document with the export parameters and records
+fn json_export<T: Serialize>(
+    kind: &str,
+    range: &Range,
+    records: &[T],
+) -> Result<String, PeachWebError> {
+    let export = json!({
+        "kind": kind,
+        "from": range.from.to_string(),
+        "to": range.to.to_string(),
+        "records": records,
+    });
+
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+/// Export the given history as a download
+pub fn export(kind: &str, format: Format, range: Range) -> Result<Download, PeachWebError> {
+    let (start, end) = range.bounds();
+
+    let body = match (kind, &format) {
+        ("traffic", Format::Csv) => {
+            let rows = monitor::get_traffic_history(start, end)?
+                .iter()
+                .map(|s| vec![time(s.timestamp), s.bytes.to_string()])
+                .collect();
+            csv(&["hour_utc", "bytes"], rows)
+        }
+        ("traffic", Format::Json) => {
+            json_export(kind, &range, &monitor::get_traffic_history(start, end)?)?
+        }
+        ("alerts", Format::Csv) => {
+            let rows = monitor::get_alert_history(start, end)?
+                .iter()
+                .map(|a| {
+                    vec![
+                        a.rule.id.to_string(),
+                        a.rule.metric.clone(),
+                        a.rule.comparator.clone(),
+                        a.rule.threshold.to_string(),
+                        format!("{:.1}", a.value),
+                        time(a.raised),
+                        a.cleared.map(time).unwrap_or_default(),
+                    ]
+                })
+                .collect();
+            csv(
+                &[
+                    "rule_id",
+                    "metric",
+                    "comparator",
+                    "threshold",
+                    "value",
+                    "raised_utc",
+                    "cleared_utc",
+                ],
+                rows,
+            )
+        }
+        ("alerts", Format::Json) => {
+            json_export(kind, &range, &monitor::get_alert_history(start, end)?)?
+        }
+        ("device", Format::Csv) => {
+            let rows = monitor::get_device_history(start, end)?
+                .iter()
+                .map(|s| {
+                    vec![
+                        time(s.timestamp),
+                        optional(s.cpu),
+                        optional(s.memory),
+                        optional(s.disk),
+                        optional(s.temperature),
+                    ]
+                })
+                .collect();
+            csv(
+                &[
+                    "time_utc",
+                    "cpu_percent",
+                    "memory_percent",
+                    "disk_percent",
+                    "temperature_celsius",
+                ],
+                rows,
+            )
+        }
+        ("device", Format::Json) => {
+            json_export(kind, &range, &monitor::get_device_history(start, end)?)?
+        }
+        _ => {
+            return Err(PeachWebError::InvalidExport {
+                msg: format!("Unknown export: {}", kind),
+            })
+        }
+    };
+
+    Ok(Download {
+        filename: format!(
+            "peachcloud-{}-{}-to-{}.{}",
+            kind,
+            range.from,
+            range.to,
+            format.extension()
+        ),
+        content_type: format.content_type(),
+        body,
+    })
+}
@@ -0,0 +1,392 @@
+//! Automatic fallback to access point mode.
+//!
+//! A device in WiFi client mode becomes unreachable when its saved network
+//! disappears. The watchdog (disabled by default) periodically checks the
+//! state of `wlan0` and whether the gateway of its default route responds.
+//! When the connection has been down for longer than the grace period, the
+//! access point is activated so that the device can be reached again. Client
+//! mode is then retried at the configured interval, falling back to the
+//! access point again if the connection does not come up within the grace
+//! period.
+//!
+//! A failed check is only counted as an outage when it is conclusive: since
+//! some gateways do not answer pings, the connection is also checked over
+//! HTTP, and a `wlan0` which is down is ignored when there are no saved
+//! networks or when another interface (eg. ethernet) provides a connection.
+//!
+//! The watchdog only acts on an access point which it activated itself: an
+//! access point deployed by the user is left alone. Fallback events (access
+//! point activated, client mode retried, connection restored) are recorded in
+//! the data store.
+
+use std::process::Command;
+use std::{thread, time::Duration};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use nest::Value;
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use peach_lib::network_client;
+
+use crate::diagnostics;
+use crate::error::PeachWebError;
+use crate::monitor;
+use crate::store::DataStore;
+use crate::wifi;
+
+/// Default interval between connection checks (in seconds)
+const CHECK_INTERVAL: u64 = 30;
+/// Number of fallback events to keep in the history
+const EVENT_HISTORY_LEN: usize = 50;
+/// Path of the fallback settings and state within the data store
+const FALLBACK_FILE: [&str; 2] = ["net", "fallback"];
+
+/// Fallback settings (user-defined)
+#[derive(Debug, Deserialize, Serialize, FromForm)]
+pub struct Settings {
+    pub enabled: bool,
+    pub grace: u64, // minutes without a connection before falling back
+    pub retry: u64, // minutes between attempts to return to client mode
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            enabled: false,
+            grace: 5,
+            retry: 15,
+        }
+    }
+}
+
+impl Settings {
+    /// Retrieve the fallback settings from the store
+    fn get(store: &DataStore) -> Result<Settings, PeachWebError> {
+        Ok(Settings {
+            enabled: store.get_bool(&["net", "fallback", "enabled"])?,
+            grace: store.get_u64(&["net", "fallback", "grace"])?,
+            retry: store.get_u64(&["net", "fallback", "retry"])?,
+        })
+    }
+
+    /// Store the fallback settings
+    fn set(&self, store: &DataStore) -> Result<(), PeachWebError> {
+        store.set_values(
+            &FALLBACK_FILE,
+            vec![
+                ("enabled", Value::Bool(self.enabled)),
+                ("grace", Value::Uint(self.grace)),
+                ("retry", Value::Uint(self.retry)),
+            ],
+        )
+    }
+
+    /// Ensure the grace period and retry interval are within range
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let msg = if self.grace < 1 || self.grace > 60 {
+            Some("Grace period must be between 1 and 60 minutes")
+        } else if self.retry < 5 || self.retry > 1440 {
+            Some("Retry interval must be between 5 and 1440 minutes")
+        } else {
+            None
+        };
+
+        match msg {
+            Some(msg) => Err(PeachWebError::InvalidFallback {
+                msg: msg.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Watchdog state. Timestamps are unix times in seconds (0 if unset).
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct State {
+    pub active: bool,       // the access point was activated by the watchdog
+    pub failing_since: i64, // start of the current connection outage
+    pub last_retry: i64,    // last fallback or client mode retry
+}
+
+impl State {
+    /// Retrieve the watchdog state from the store
+    fn get(store: &DataStore) -> Result<State, PeachWebError> {
+        Ok(State {
+            active: store.get_bool(&["net", "fallback", "active"])?,
+            failing_since: store.get_i64(&["net", "fallback", "failing_since"])?,
+            last_retry: store.get_i64(&["net", "fallback", "last_retry"])?,
+        })
+    }
+
+    /// Store the watchdog state
+    fn set(&self, store: &DataStore) -> Result<(), PeachWebError> {
+        store.set_values(
+            &FALLBACK_FILE,
+            vec![
+                ("active", Value::Bool(self.active)),
+                ("failing_since", Value::Int(self.failing_since)),
+                ("last_retry", Value::Int(self.last_retry)),
+            ],
+        )
+    }
+}
+
+/// A recorded fallback event
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Event {
+    pub time: i64,      // unix timestamp
+    pub event: String,  // "fallback", "retry", "restored" or "error"
+    pub detail: String, // human-readable description
+}
+
+/// Fallback settings, watchdog state and recent events
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub settings: Settings,
+    pub state: State,
+    pub events: Vec<Event>, // most recent first
+}
+
+/// Network mode of the device, as observed by the watchdog
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    AccessPoint,
+    Client { connected: bool },
+    // the connection cannot be judged (eg. the device has no saved networks)
+    Unknown,
+}
+
+/// Action to be taken by the watchdog
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    None,
+    ActivateAp,
+    RetryClient,
+    Restored,
+}
+
+/// Decide what to do given the observed network mode, updating the watchdog
+/// state. `now` is a unix timestamp.
+pub fn decide(settings: &Settings, state: &mut State, mode: Mode, now: i64) -> Action {
+    if !settings.enabled {
+        *state = State::default();
+        return Action::None;
+    }
+
+    match mode {
+        // an access point deployed by the user is left alone
+        Mode::AccessPoint if !state.active => Action::None,
+        Mode::AccessPoint => {
+            if now - state.last_retry >= settings.retry as i64 * 60 {
+                state.last_retry = now;
+                // the grace period starts again once client mode is up
+                state.failing_since = now;
+                Action::RetryClient
+            } else {
+                Action::None
+            }
+        }
+        Mode::Client { connected: true } => {
+            let restored = state.active;
+            *state = State::default();
+            if restored {
+                Action::Restored
+            } else {
+                Action::None
+            }
+        }
+        // an outage is only counted while it is confirmed by every check
+        Mode::Unknown => {
+            state.failing_since = 0;
+            Action::None
+        }
+        Mode::Client { connected: false } => {
+            if state.failing_since == 0 {
+                state.failing_since = now;
+                Action::None
+            } else if now - state.failing_since >= settings.grace as i64 * 60 {
+                state.active = true;
+                state.failing_since = 0;
+                state.last_retry = now;
+                Action::ActivateAp
+            } else {
+                Action::None
+            }
+        }
+    }
+}
+
+// retrieve the list of recorded events (oldest first)
+fn get_events(store: &DataStore) -> Result<Vec<Event>, PeachWebError> {
+    store.get_list(&["net", "fallback", "events"])
+}
+
+// record an event, dropping the oldest events beyond the history length
+fn record(store: &DataStore, event: &str, detail: String) -> Result<(), PeachWebError> {
+    info!("WiFi fallback: {}", detail);
+    store.update(&["net", "fallback"], |values| {
+        let mut events: Vec<Event> = values.get_list("events")?;
+        events.push(Event {
+            time: Utc::now().timestamp(),
+            event: event.to_string(),
+            detail,
+        });
+        let excess = events.len().saturating_sub(EVENT_HISTORY_LEN);
+        events.drain(..excess);
+        values.set_list("events", &events)
+    })
+}
+
+// whether the device can reach the internet over any interface
+fn internet_reachable() -> bool {
+    diagnostics::check_http(diagnostics::DEFAULT_HTTP_URL).ok
+}
+
+// observe the wifi client connection: connected if the gateway of the default
+// route of wlan0 responds (or, since some gateways ignore pings, the internet
+// can be reached)
+fn client_mode() -> Mode {
+    let saved = wifi::saved_networks().map_or(false, |networks| !networks.is_empty());
+    if !saved {
+        return Mode::Unknown;
+    }
+    if network_client::state("wlan0").map_or(true, |state| state != "up") {
+        // the device may be connected via ethernet with wlan0 left down
+        return if internet_reachable() {
+            Mode::Unknown
+        } else {
+            Mode::Client { connected: false }
+        };
+    }
+    let gateway = Command::new("ip")
+        .args(&["route", "show", "default", "dev", "wlan0"])
+        .output()
+        .ok()
+        .and_then(|output| {
+            // default via 192.168.1.1 proto dhcp src 192.168.1.20 metric 303
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .skip_while(|field| *field != "via")
+                .nth(1)
+                .map(|gateway| gateway.to_string())
+        });
+    let connected = diagnostics::check_gateway(gateway.as_deref()).ok || internet_reachable();
+
+    Mode::Client { connected }
+}
+
+// observe the current network mode
+fn current_mode() -> Mode {
+    match network_client::state("ap0") {
+        Ok(state) if state == "up" => Mode::AccessPoint,
+        _ => client_mode(),
+    }
+}
+
+// check the connection once and act on the result
+fn evaluate() -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    let settings = Settings::get(&store)?;
+    if !settings.enabled {
+        return Ok(());
+    }
+    // the wifi client is deliberately disconnected by the data usage cutoff
+    if monitor::get_alerts().map_or(false, |alert| alert.cut) {
+        debug!("WiFi fallback paused while the data usage cutoff is active.");
+        return Ok(());
+    }
+
+    let previous = State::get(&store)?;
+    let mut state = previous.clone();
+    let mode = current_mode();
+    let action = decide(&settings, &mut state, mode, Utc::now().timestamp());
+    if state != previous {
+        state.set(&store)?;
+    }
+
+    match action {
+        Action::None => (),
+        Action::ActivateAp => match network_client::activate_ap() {
+            Ok(_) => record(
+                &store,
+                "fallback",
+                format!(
+                    "No WiFi connection for {} minutes: activated the access point",
+                    settings.grace
+                ),
+            )?,
+            Err(e) => record(
+                &store,
+                "error",
+                format!("Failed to activate the access point: {}", e),
+            )?,
+        },
+        Action::RetryClient => match network_client::activate_client() {
+            Ok(_) => record(&store, "retry", "Retrying WiFi client mode".to_string())?,
+            Err(e) => record(
+                &store,
+                "error",
+                format!("Failed to activate WiFi client mode: {}", e),
+            )?,
+        },
+        Action::Restored => record(&store, "restored", "WiFi connection restored".to_string())?,
+    }
+
+    Ok(())
+}
+
+/// Retrieve the fallback settings, watchdog state and recent events
+pub fn get_status() -> Result<Status, PeachWebError> {
+    let store = DataStore::open()?;
+    let mut events = get_events(&store)?;
+    events.reverse();
+
+    Ok(Status {
+        settings: Settings::get(&store)?,
+        state: State::get(&store)?,
+        events,
+    })
+}
+
+/// Validate and store new fallback settings
+pub fn update_settings(settings: Settings) -> Result<(), PeachWebError> {
+    settings.validate()?;
+    let store = DataStore::open()?;
+    settings.set(&store)?;
+    // disabling the watchdog leaves the device in its current mode
+    if !settings.enabled {
+        State::default().set(&store)?;
+    }
+
+    Ok(())
+}
+
+/// Stop retrying client mode, eg. when the user deploys the access point
+pub fn cancel() -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    if State::get(&store)?.active {
+        info!("WiFi fallback cancelled.");
+        State::default().set(&store)?;
+    }
+
+    Ok(())
+}
+
+/// Periodically check the WiFi client connection, falling back to the access
+/// point when it is lost.
+///
+/// The interval (in seconds) can be configured with the
+/// `PEACH_WEB_FALLBACK_INTERVAL` environment variable.
+pub fn run() {
+    let interval = crate::interval("PEACH_WEB_FALLBACK_INTERVAL", CHECK_INTERVAL);
+
+    loop {
+        debug!("Checking WiFi client connection.");
+        if let Err(e) = evaluate() {
+            warn!("Failed to check WiFi client connection: {}", e);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
@@ -0,0 +1,267 @@
+//! Local firewall management.
+//!
+//! Incoming connections to the services run on the device (SSH, the web
+//! interface and sbot) are allowed or denied through a simple model: each
+//! service is reachable everywhere, only via the access point (`ap0`), only via
+//! a single interface, or not at all. Other incoming connections are dropped,
+//! except for the DHCP, DNS and mDNS traffic the device needs to operate.
+//!
+//! The model is rendered as an nftables ruleset in its own `inet peach_web`
+//! table, which is replaced atomically by `nft -f`. The rules file is included
+//! by `/etc/nftables.conf`, so that the rules are restored at boot, and
+//! records the model it was rendered from in a comment on its first line.
+//!
+//! Changes are applied with a confirm-or-revert timer, since a rule can lock
+//! the admin out of the web interface.
+
+use std::fs;
+use std::io;
+
+use log::info;
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::ipconfig;
+use crate::revert::Reverter;
+
+/// Path of the nftables rules file
+pub const FIREWALL_RULES: &str = "/etc/nftables.d/peach-web.nft";
+/// Prefix of the rules file line recording the firewall settings
+const SETTINGS_PREFIX: &str = "# peach-web firewall: ";
+/// Access point interface
+const AP_IFACE: &str = "ap0";
+
+/// A service run on the device
+#[derive(Debug, Serialize)]
+pub struct Service {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub tcp: &'static [u16],
+    pub udp: &'static [u16],
+}
+
+/// Services whose access can be configured
+pub const SERVICES: [Service; 3] = [
+    Service {
+        name: "ssh",
+        label: "SSH",
+        tcp: &[22],
+        udp: &[],
+    },
+    Service {
+        name: "web",
+        label: "Web interface",
+        tcp: &[80, 443],
+        udp: &[],
+    },
+    Service {
+        name: "sbot",
+        label: "Scuttlebutt (sbot)",
+        tcp: &[8008],
+        // local peer discovery broadcasts
+        udp: &[8008],
+    },
+];
+
+/// Firewall settings (user-defined). The access of each service is
+/// `everywhere`, `ap` (access point only), an interface name or `deny`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize, FromForm)]
+pub struct FirewallConfig {
+    pub enabled: bool,
+    pub ssh: String,
+    pub web: String,
+    pub sbot: String,
+}
+
+impl Default for FirewallConfig {
+    fn default() -> FirewallConfig {
+        FirewallConfig {
+            enabled: false,
+            ssh: "everywhere".to_string(),
+            web: "everywhere".to_string(),
+            sbot: "everywhere".to_string(),
+        }
+    }
+}
+
+impl FirewallConfig {
+    /// Access setting of a service
+    pub fn access(&self, service: &str) -> &str {
+        match service {
+            "ssh" => &self.ssh,
+            "web" => &self.web,
+            _ => &self.sbot,
+        }
+    }
+
+    /// Ensure the access setting of every service is valid
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        for service in SERVICES.iter() {
+            let access = self.access(service.name);
+            let valid = matches!(access, "everywhere" | "ap" | "deny")
+                || ipconfig::interfaces().iter().any(|iface| iface == access);
+            if !valid {
+                return Err(PeachWebError::InvalidFirewall {
+                    msg: format!("Invalid access for {}: {}", service.label, access),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Access setting of a service, for display
+#[derive(Debug, Serialize)]
+pub struct ServiceAccess {
+    pub name: String,
+    pub label: String,
+    pub ports: String, // eg. "80/tcp, 443/tcp"
+    pub access: String,
+}
+
+/// Access settings of all services
+pub fn services(config: &FirewallConfig) -> Vec<ServiceAccess> {
+    SERVICES
+        .iter()
+        .map(|service| {
+            let ports: Vec<String> = service
+                .tcp
+                .iter()
+                .map(|port| format!("{}/tcp", port))
+                .chain(service.udp.iter().map(|port| format!("{}/udp", port)))
+                .collect();
+            ServiceAccess {
+                name: service.name.to_string(),
+                label: service.label.to_string(),
+                ports: ports.join(", "),
+                access: config.access(service.name).to_string(),
+            }
+        })
+        .collect()
+}
+
+// nftables set of ports (eg. `{ 80, 443 }`)
+fn port_set(ports: &[u16]) -> String {
+    let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+    format!("{{ {} }}", ports.join(", "))
+}
+
+/// Render the nftables rules file for the given settings. A disabled firewall
+/// removes the `peach_web` table.
+pub fn render(config: &FirewallConfig) -> String {
+    let mut rules = format!(
+        "{}{}\n",
+        SETTINGS_PREFIX,
+        serde_json::to_string(config).unwrap_or_default()
+    );
+    // declare the table first, so that deleting it cannot fail
+    rules.push_str("table inet peach_web\ndelete table inet peach_web\n");
+    if !config.enabled {
+        return rules;
+    }
+
+    rules.push_str("table inet peach_web {\n");
+    rules.push_str("    chain input {\n");
+    rules.push_str("        type filter hook input priority 0; policy drop;\n");
+    rules.push_str("        ct state established,related accept\n");
+    rules.push_str("        ct state invalid drop\n");
+    rules.push_str("        iifname \"lo\" accept\n");
+    rules.push_str("        meta l4proto { icmp, ipv6-icmp } accept\n");
+    rules.push_str("        # dhcp, dhcpv6 and mdns\n");
+    rules.push_str("        udp dport { 67, 68, 546, 5353 } accept\n");
+    rules.push_str(&format!(
+        "        iifname \"{}\" meta l4proto {{ tcp, udp }} th dport 53 accept\n",
+        AP_IFACE
+    ));
+    for service in SERVICES.iter() {
+        let iface = match config.access(service.name) {
+            "deny" => continue,
+            "everywhere" => String::new(),
+            "ap" => format!("iifname \"{}\" ", AP_IFACE),
+            iface => format!("iifname \"{}\" ", iface),
+        };
+        rules.push_str(&format!("        # {}\n", service.label));
+        for (proto, ports) in &[("tcp", service.tcp), ("udp", service.udp)] {
+            if !ports.is_empty() {
+                rules.push_str(&format!(
+                    "        {}{} dport {} accept\n",
+                    iface,
+                    proto,
+                    port_set(ports)
+                ));
+            }
+        }
+    }
+    rules.push_str("    }\n}\n");
+
+    rules
+}
+
+/// Parse the firewall settings recorded in a rules file
+pub fn parse(rules: &str) -> Option<FirewallConfig> {
+    rules
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix(SETTINGS_PREFIX))
+        .and_then(|settings| serde_json::from_str(settings).ok())
+}
+
+// read the rules file, rendering the default (disabled) settings if the rules
+// file is missing or empty (as installed)
+fn read_rules() -> Result<String, PeachWebError> {
+    match fs::read_to_string(FIREWALL_RULES) {
+        Ok(rules) if !rules.trim().is_empty() => Ok(rules),
+        Ok(_) => Ok(render(&FirewallConfig::default())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(render(&FirewallConfig::default())),
+        Err(source) => Err(PeachWebError::FirewallRules {
+            path: FIREWALL_RULES.to_string(),
+            source,
+        }),
+    }
+}
+
+/// Retrieve the current firewall settings
+pub fn get_config() -> Result<FirewallConfig, PeachWebError> {
+    let rules = read_rules()?;
+    parse(&rules).ok_or_else(|| PeachWebError::InvalidFirewall {
+        msg: format!("Unrecognised firewall rules in {}", FIREWALL_RULES),
+    })
+}
+
+/// Retrieve the current nftables ruleset of the rules file
+pub fn get_ruleset() -> Result<String, PeachWebError> {
+    read_rules()
+}
+
+// write the rules file and load it (in a single nftables transaction)
+fn write_rules(rules: &str) -> Result<(), PeachWebError> {
+    device::write_file(FIREWALL_RULES, rules)?;
+    device::run_privileged("nft", &["-f", FIREWALL_RULES])?;
+
+    Ok(())
+}
+
+/// Validate and apply new firewall settings. Unless the change is confirmed
+/// (`Reverter::confirm("firewall")`) before the timeout, the previous rules
+/// are restored.
+pub fn apply(config: FirewallConfig, reverter: &Reverter) -> Result<(), PeachWebError> {
+    config.validate()?;
+    let original = read_rules()?;
+    let updated = render(&config);
+    if updated == original {
+        return Ok(());
+    }
+
+    info!("Applying firewall rules");
+    if let Err(e) = write_rules(&updated) {
+        // a rejected ruleset leaves the loaded rules unchanged
+        device::write_file(FIREWALL_RULES, &original)?;
+        return Err(e);
+    }
+    reverter.schedule("firewall", "firewall rules", move || write_rules(&original));
+
+    Ok(())
+}
@@ -2,7 +2,7 @@
 //!
 use rocket::request::FromForm;
 use rocket::UriDisplayQuery;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, FromForm)]
 pub struct DnsForm {
@@ -30,10 +30,57 @@ pub struct Ssid {
     pub ssid: String,
 }
 
-#[derive(Debug, Deserialize, FromForm)]
+#[derive(Debug, Default, Deserialize, FromForm, Serialize)]
 pub struct WiFi {
     pub ssid: String,
+    #[serde(default)]
     pub pass: String,
+    // "wpa-psk" (default), "open", "sae", "peap" or "ttls"
+    pub security: Option<String>,
+    #[serde(default)]
+    pub hidden: bool,
+    // wpa-enterprise (eap) username and outer identity
+    pub identity: Option<String>,
+    pub anonymous_identity: Option<String>,
+    // domain of the wpa-enterprise authentication server (eg. "example.edu")
+    pub server_domain: Option<String>,
+}
+
+// a `WIFI:` uri, as encoded in a wifi qr code
+#[derive(Debug, Deserialize, FromForm)]
+pub struct WifiUri {
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct AutoConnect {
+    pub ssid: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct NetworkMove {
+    pub ssid: String,
+    pub direction: String, // "up" or "down"
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NetworkOrder {
+    pub ssids: Vec<String>, // most preferred first
+}
+
+// password form for first-run setup (there is no old password yet)
+#[derive(Debug, Deserialize, FromForm)]
+pub struct SetupPasswordForm {
+    pub new_password1: String,
+    pub new_password2: String,
+    // admin ssb id, added once the password is set (optional)
+    pub ssb_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct HostnameForm {
+    pub hostname: String,
 }
 
 #[derive(Debug, Deserialize, FromForm)]
@@ -45,3 +92,23 @@ pub struct AddAdminForm {
 pub struct DeleteAdminForm {
     pub ssb_id: String,
 }
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct Iface {
+    pub iface: String,
+}
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct MacAddress {
+    pub mac: String,
+}
+
+#[derive(Debug, Deserialize, FromForm)]
+pub struct PeerUri {
+    pub uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RuleId {
+    pub id: u64,
+}
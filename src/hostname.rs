@@ -0,0 +1,178 @@
+//! Device hostname and local network (mDNS) name.
+//!
+//! The hostname is set with `hostnamectl`, which updates `/etc/hostname` and
+//! the running system. It must be a single DNS label (letters, digits and
+//! hyphens), since it is also used as the local network name of the device:
+//! avahi advertises it as `<hostname>.local`, and nginx answers to that name.
+//!
+//! Changing the hostname therefore also updates the `127.0.1.1` entry in
+//! `/etc/hosts`, the avahi `host-name` and the nginx `server_name` snippet
+//! (included by the site configuration written by `postinst`), and issues a new
+//! self-signed certificate. Templates use the `mdns_name()` function to refer
+//! to the device.
+
+use std::collections::HashMap;
+use std::fs;
+
+use log::{info, warn};
+use rocket_contrib::templates::tera;
+use rocket_contrib::templates::tera::Value;
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::tls;
+
+/// Path of the hostname file
+const HOSTNAME_FILE: &str = "/etc/hostname";
+/// Path of the static host table
+const HOSTS_FILE: &str = "/etc/hosts";
+/// Path of the avahi daemon configuration file
+const AVAHI_CONF: &str = "/etc/avahi/avahi-daemon.conf";
+/// Path of the nginx snippet naming the server
+pub const NGINX_SERVER_NAME: &str = "/etc/nginx/snippets/peach-web-server-name.conf";
+/// Hostname used when the hostname file cannot be read
+const DEFAULT_HOSTNAME: &str = "peach";
+
+/// Validate a hostname, returning it in lowercase
+pub fn validate(hostname: &str) -> Result<String, PeachWebError> {
+    let err = |msg: &str| {
+        Err(PeachWebError::InvalidHostname {
+            msg: msg.to_string(),
+        })
+    };
+    let hostname = hostname.trim().to_lowercase();
+    if hostname.is_empty() || hostname.len() > 63 {
+        return err("Hostname must be between 1 and 63 characters long");
+    }
+    if !hostname
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return err("Hostname may only contain letters, digits and hyphens");
+    }
+    if hostname.starts_with('-') || hostname.ends_with('-') {
+        return err("Hostname must not start or end with a hyphen");
+    }
+
+    Ok(hostname)
+}
+
+/// Retrieve the current hostname
+pub fn get() -> Result<String, PeachWebError> {
+    fs::read_to_string(HOSTNAME_FILE)
+        .map(|hostname| hostname.trim().to_string())
+        .map_err(|e| PeachWebError::SystemCommand {
+            cmd: format!("read {}", HOSTNAME_FILE),
+            msg: e.to_string(),
+        })
+}
+
+/// Local network (mDNS) name of the device, eg. `peach.local`
+pub fn mdns_name() -> String {
+    let hostname = get()
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| DEFAULT_HOSTNAME.to_string());
+    format!("{}.local", hostname)
+}
+
+/// Template function returning the mDNS name of the device
+pub fn mdns_name_fn(_args: HashMap<String, Value>) -> tera::Result<Value> {
+    Ok(Value::String(mdns_name()))
+}
+
+/// Update the `127.0.1.1` entry of a host table for the new hostname,
+/// adding the entry if there is none
+pub fn update_hosts(hosts: &str, hostname: &str) -> String {
+    let entry = format!("127.0.1.1\t{}", hostname);
+    let mut found = false;
+    let mut lines: Vec<String> = hosts
+        .lines()
+        .map(|line| {
+            if line.split_whitespace().next() == Some("127.0.1.1") && !found {
+                found = true;
+                entry.clone()
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !found {
+        lines.push(entry);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Set the `host-name` option of the `[server]` section of an avahi daemon
+/// configuration, leaving all other settings untouched
+pub fn update_avahi(conf: &str, hostname: &str) -> String {
+    let option = format!("host-name={}", hostname);
+    let mut section = String::new();
+    let mut done = false;
+    let mut lines = Vec::new();
+    for line in conf.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            // add the option at the end of the server section if it is missing
+            if section == "[server]" && !done {
+                lines.push(option.clone());
+                done = true;
+            }
+            section = trimmed.to_string();
+        }
+        let name = trimmed.trim_start_matches('#').trim_start();
+        if section == "[server]" && !done && name.starts_with("host-name=") {
+            lines.push(option.clone());
+            done = true;
+            continue;
+        }
+        lines.push(line.to_string());
+    }
+    if !done {
+        if section != "[server]" {
+            lines.push("[server]".to_string());
+        }
+        lines.push(option);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Render the nginx snippet naming the server after the hostname
+pub fn render_server_name(hostname: &str) -> String {
+    format!(
+        "server_name {host}.local www.{host}.local;\n\
+         auth_basic \"If you have forgotten your password visit: http://{host}.local/send_password_reset/\";\n",
+        host = hostname
+    )
+}
+
+/// Validate and set the hostname, updating the host table, the avahi
+/// configuration and the nginx server name
+pub fn set(hostname: &str) -> Result<(), PeachWebError> {
+    let hostname = validate(hostname)?;
+    info!("Setting hostname to {}", hostname);
+    device::run_privileged("hostnamectl", &["set-hostname", &hostname])?;
+
+    match fs::read_to_string(HOSTS_FILE) {
+        Ok(hosts) => device::write_file(HOSTS_FILE, &update_hosts(&hosts, &hostname))?,
+        Err(e) => warn!("Failed to read {}: {}", HOSTS_FILE, e),
+    }
+
+    match fs::read_to_string(AVAHI_CONF) {
+        Ok(conf) => {
+            device::write_file(AVAHI_CONF, &update_avahi(&conf, &hostname))?;
+            device::run_privileged("systemctl", &["try-restart", "avahi-daemon"])?;
+        }
+        Err(e) => warn!("Failed to read {}: {}", AVAHI_CONF, e),
+    }
+
+    device::write_file(NGINX_SERVER_NAME, &render_server_name(&hostname))?;
+    device::run_privileged("systemctl", &["reload", "nginx"])?;
+
+    // the self-signed certificate is issued for the mdns name
+    tls::spawn_update();
+
+    Ok(())
+}
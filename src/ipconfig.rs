@@ -0,0 +1,351 @@
+//! Per-interface IP address configuration.
+//!
+//! Addresses are assigned by dhcpcd. An interface uses DHCP (IPv4) and SLAAC
+//! (IPv6) unless static settings are given in its `interface` block of the
+//! dhcpcd configuration file. Only the settings managed here are changed;
+//! comments, other blocks and other options are preserved.
+//!
+//! Changes are applied with a confirm-or-revert timer, since an admin
+//! connected via the interface may lose their connection when its address
+//! changes.
+
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use log::info;
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::revert::Reverter;
+use crate::wired;
+
+/// Path of the dhcpcd configuration file
+pub const DHCPCD_CONF: &str = "/etc/dhcpcd.conf";
+/// Root-owned script (written by the package) which rebinds an existing
+/// network interface with dhcpcd
+const REBIND: &str = "/usr/lib/peach-web/rebind-interface";
+
+// options written to an interface block (all others are left untouched)
+const MANAGED: &[&str] = &[
+    "static ip_address",
+    "static routers",
+    "static domain_name_servers",
+    "static ip6_address",
+    "noipv6rs",
+];
+
+/// IP settings of a single interface (user-defined)
+#[derive(Debug, Default, Deserialize, PartialEq, Serialize, FromForm)]
+pub struct IpConfig {
+    pub iface: String,
+    pub method: String, // "dhcp" or "static"
+    #[serde(default)]
+    pub address: String, // address with prefix length (eg. "192.168.1.10/24")
+    #[serde(default)]
+    pub gateway: String,
+    #[serde(default)]
+    pub dns: String, // space or comma-separated name servers
+    #[serde(default = "default_method6")]
+    pub method6: String, // "slaac" or "static"
+    #[serde(default)]
+    pub address6: String, // address with prefix length (eg. "fd00::10/64")
+}
+
+fn default_method6() -> String {
+    "slaac".to_string()
+}
+
+impl IpConfig {
+    /// Ensure the IP settings are valid
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let err = |msg: &str| {
+            Err(PeachWebError::InvalidIpConfig {
+                msg: msg.to_string(),
+            })
+        };
+
+        if !valid_iface(&self.iface) {
+            return err("Unknown interface");
+        }
+        match self.method.as_str() {
+            "dhcp" => (),
+            "static" => {
+                let (address, prefix) = match parse_v4(&self.address) {
+                    Some(cidr) => cidr,
+                    None => {
+                        return err(
+                            "Address must be an IPv4 address with a prefix length (eg. 192.168.1.10/24)",
+                        )
+                    }
+                };
+                if !self.gateway.is_empty() {
+                    let gateway: Ipv4Addr = match self.gateway.parse() {
+                        Ok(gateway) => gateway,
+                        Err(_) => return err("Gateway must be an IPv4 address"),
+                    };
+                    if gateway == address {
+                        return err("Gateway must differ from the address");
+                    }
+                    if !same_subnet(address, gateway, prefix) {
+                        return err("Gateway must be in the same subnet as the address");
+                    }
+                }
+            }
+            _ => return err("Method must be either dhcp or static"),
+        }
+        if self
+            .dns_servers()
+            .iter()
+            .any(|s| s.parse::<IpAddr>().is_err())
+        {
+            return err("DNS servers must be IPv4 or IPv6 addresses");
+        }
+        match self.method6.as_str() {
+            "slaac" => (),
+            "static" => {
+                if parse_v6(&self.address6).is_none() {
+                    return err("IPv6 address must include a prefix length (eg. fd00::10/64)");
+                }
+            }
+            _ => return err("IPv6 method must be either slaac or static"),
+        }
+
+        Ok(())
+    }
+
+    /// Name servers as a list
+    pub fn dns_servers(&self) -> Vec<&str> {
+        self.dns
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+}
+
+// parse an ipv4 address with prefix length
+fn parse_v4(cidr: &str) -> Option<(Ipv4Addr, u8)> {
+    let mut parts = cidr.trim().splitn(2, '/');
+    let address = parts.next()?.parse().ok()?;
+    let prefix = parts.next()?.parse().ok()?;
+    if (1..=32).contains(&prefix) {
+        Some((address, prefix))
+    } else {
+        None
+    }
+}
+
+// parse an ipv6 address with prefix length
+fn parse_v6(cidr: &str) -> Option<(Ipv6Addr, u8)> {
+    let mut parts = cidr.trim().splitn(2, '/');
+    let address = parts.next()?.parse().ok()?;
+    let prefix = parts.next()?.parse().ok()?;
+    if (1..=128).contains(&prefix) {
+        Some((address, prefix))
+    } else {
+        None
+    }
+}
+
+fn same_subnet(a: Ipv4Addr, b: Ipv4Addr, prefix: u8) -> bool {
+    let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+    u32::from(a) & mask == u32::from(b) & mask
+}
+
+// interface names are restricted to configurable (non-ap) interfaces
+fn valid_iface(iface: &str) -> bool {
+    interfaces().iter().any(|i| i == iface)
+}
+
+/// Interfaces whose IP settings can be configured: `wlan0` and any wired
+/// interfaces
+pub fn interfaces() -> Vec<String> {
+    let mut ifaces = vec!["wlan0".to_string()];
+    ifaces.extend(wired::names());
+    ifaces
+}
+
+// split a dhcpcd option line into option name (including `static`) and value
+fn option(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    if let Some(rest) = line.strip_prefix("static ") {
+        let mut parts = rest.splitn(2, '=');
+        let key = parts.next()?.trim();
+        let value = parts.next().unwrap_or_default().trim();
+        return Some((format!("static {}", key), value.to_string()));
+    }
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let key = parts.next()?;
+    let value = parts.next().unwrap_or_default().trim();
+    Some((key.to_string(), value.to_string()))
+}
+
+// `interface` and `profile` lines start a new block
+fn block_start(line: &str) -> Option<(String, String)> {
+    option(line).filter(|(key, _)| key == "interface" || key == "profile")
+}
+
+/// Parse the IP settings of an interface from the contents of a dhcpcd
+/// configuration file
+pub fn parse(conf: &str, iface: &str) -> IpConfig {
+    let mut config = IpConfig {
+        iface: iface.to_string(),
+        method: "dhcp".to_string(),
+        method6: default_method6(),
+        ..Default::default()
+    };
+    let mut in_block = false;
+    for line in conf.lines() {
+        if let Some((key, value)) = block_start(line) {
+            in_block = key == "interface" && value == iface;
+            continue;
+        }
+        if !in_block {
+            continue;
+        }
+        match option(line) {
+            Some((key, value)) => match key.as_str() {
+                "static ip_address" => {
+                    config.method = "static".to_string();
+                    config.address = value;
+                }
+                "static routers" => config.gateway = value,
+                "static domain_name_servers" => config.dns = value,
+                "static ip6_address" => {
+                    config.method6 = "static".to_string();
+                    config.address6 = value;
+                }
+                _ => (),
+            },
+            None => continue,
+        }
+    }
+
+    config
+}
+
+/// Update the contents of a dhcpcd configuration file with the IP settings
+/// of an interface. Managed options in the interface block are replaced,
+/// the block is created if needed and removed if left empty.
+pub fn update(conf: &str, config: &IpConfig) -> String {
+    let mut options = Vec::new();
+    if config.method == "static" {
+        options.push(format!("static ip_address={}", config.address.trim()));
+        if !config.gateway.is_empty() {
+            options.push(format!("static routers={}", config.gateway.trim()));
+        }
+    }
+    let dns = config.dns_servers();
+    if !dns.is_empty() {
+        options.push(format!("static domain_name_servers={}", dns.join(" ")));
+    }
+    if config.method6 == "static" {
+        options.push("noipv6rs".to_string());
+        options.push(format!("static ip6_address={}", config.address6.trim()));
+    }
+
+    // split the file into the lines before, inside and after the block
+    let lines: Vec<&str> = conf.lines().collect();
+    let start = lines.iter().position(|line| {
+        block_start(line).map_or(false, |(k, v)| k == "interface" && v == config.iface)
+    });
+    let mut updated: Vec<String> = Vec::new();
+    match start {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| block_start(line).is_some())
+                .map_or(lines.len(), |i| start + 1 + i);
+            let kept: Vec<&str> = lines[start + 1..end]
+                .iter()
+                .filter(|line| {
+                    option(line).map_or(true, |(key, _)| !MANAGED.contains(&key.as_str()))
+                })
+                .copied()
+                .collect();
+            updated.extend(lines[..start].iter().map(|l| l.to_string()));
+            let empty = kept.iter().all(|line| line.trim().is_empty());
+            if !(empty && options.is_empty()) {
+                updated.push(lines[start].to_string());
+                updated.extend(options);
+            }
+            updated.extend(kept.iter().map(|l| l.to_string()));
+            updated.extend(lines[end..].iter().map(|l| l.to_string()));
+        }
+        None => {
+            updated.extend(lines.iter().map(|l| l.to_string()));
+            if !options.is_empty() {
+                if updated.last().map_or(false, |l| !l.trim().is_empty()) {
+                    updated.push(String::new());
+                }
+                updated.push(format!("interface {}", config.iface));
+                updated.extend(options);
+            }
+        }
+    }
+
+    let mut updated = updated.join("\n");
+    updated.push('\n');
+    updated
+}
+
+/// Retrieve the IP settings of an interface
+pub fn get_config(iface: &str) -> Result<IpConfig, PeachWebError> {
+    if !valid_iface(iface) {
+        return Err(PeachWebError::InvalidIpConfig {
+            msg: format!("Unknown interface: {}", iface),
+        });
+    }
+    let conf = read_config()?;
+
+    Ok(parse(&conf, iface))
+}
+
+fn read_config() -> Result<String, PeachWebError> {
+    fs::read_to_string(Path::new(DHCPCD_CONF)).map_err(|e| PeachWebError::SystemCommand {
+        cmd: format!("read {}", DHCPCD_CONF),
+        msg: e.to_string(),
+    })
+}
+
+// write the dhcpcd configuration and rebind the interface with it
+fn write_config(conf: &str, iface: &str) -> Result<(), PeachWebError> {
+    device::write_file(DHCPCD_CONF, conf)?;
+    device::run_privileged(REBIND, &[iface])?;
+
+    Ok(())
+}
+
+/// Validate and apply new IP settings for an interface. Unless the change is
+/// confirmed (`Reverter::confirm("ip-<iface>")`) before the timeout, the
+/// previous settings of the interface are restored; changes made to other
+/// interfaces in the meantime are kept.
+pub fn apply(config: IpConfig, reverter: &Reverter) -> Result<(), PeachWebError> {
+    config.validate()?;
+    let original = read_config()?;
+    let updated = update(&original, &config);
+    if updated == original {
+        return Ok(());
+    }
+
+    info!("Applying IP settings for {}", config.iface);
+    let iface = config.iface.clone();
+    let previous = parse(&original, &iface);
+    if let Err(e) = write_config(&updated, &iface) {
+        // the interface keeps its settings when the rebind fails
+        device::write_file(DHCPCD_CONF, &original)?;
+        return Err(e);
+    }
+    reverter.schedule(
+        &format!("ip-{}", iface),
+        &format!("IP settings for {}", iface),
+        move || write_config(&update(&read_config()?, &previous), &iface),
+    );
+
+    Ok(())
+}
@@ -5,35 +5,82 @@
 //!
 //! API ROUTES
 //!
-//! | Method | URL                              | Description                   |
-//! | ------ | -------------------------------- | ----------------------------- |
-//! | POST   | /api/v1/device/reboot            | Reboot device                 |
-//! | POST   | /api/v1/device/shutdown          | Shutdown device               |
-//! | POST   | /api/v1/network/activate_ap      |                               |
-//! | POST   | /api/v1/network/activate_client  |                               |
-//! | GET    | /api/v1/network/ip               |                               |
-//! | GET    | /api/v1/network/rssi             |                               |
-//! | GET    | /api/v1/network/ssid             |                               |
-//! | GET    | /api/v1/network/state            |                               |
-//! | GET    | /api/v1/network/status           |                               |
-//! | GET    | /api/v1/network/wifi             | Retrieve available networks   |
-//! | POST   | /api/v1/network/wifi             | Add WiFi AP credentials       |
-//! | POST   | /api/v1/network/wifi/connect     | Connect to WiFi access point  |
-//! | POST   | /api/v1/network/wifi/disconnect  | Disconnect WiFi access point  |
-//! | POST   | /api/v1/network/wifi/forget      | Forget / remove network       |
-//! | POST   | /api/v1/network/wifi/modify      | Modify network password       |
-//! | POST   | /api/v1/network/wifi/usage       | Update alert thresholds       |
-//! | POST   | /api/v1/network/wifi/usage/reset | Reset stored data usage total |
-//! | GET    | /api/v1/ping                     |                               |
-//! | GET    | /api/v1/ping/network             | Ping `peach-network`          |
-//! | GET    | /api/v1/ping/oled                | Ping `peach-oled`             |
-//! | GET    | /api/v1/ping/stats               | Ping `peach-stats`            |
-//! | POST   | /api/v1/dns/configure            | Modify dns configurations     |
-//! | POST   | /api/v1/settings/change_password | Change password (logged in)   |
-//! | POST   | /public/api/v1/reset_password    | Change password (public)      |
+//! | Method | URL                                | Description                   |
+//! | ------ | ---------------------------------- | ----------------------------- |
+//! | POST   | /api/v1/device/reboot              | Reboot device                 |
+//! | POST   | /api/v1/device/shutdown            | Shutdown device               |
+//! | POST   | /api/v1/network/activate_ap        |                               |
+//! | POST   | /api/v1/network/activate_client    |                               |
+//! | GET    | /api/v1/network/ap                 | Retrieve AP settings          |
+//! | POST   | /api/v1/network/ap                 | Update AP settings            |
+//! | POST   | /api/v1/network/ap/confirm         | Confirm AP settings           |
+//! | POST   | /api/v1/network/ap/revert          | Revert AP settings            |
+//! | GET    | /api/v1/network/ap/clients         | Retrieve AP clients           |
+//! | POST   | /api/v1/network/ap/clients/block   | Block AP client               |
+//! | POST   | /api/v1/network/ap/clients/kick    | Disconnect AP client          |
+//! | POST   | /api/v1/network/ap/clients/unblock | Unblock AP client             |
+//! | GET    | /api/v1/network/diagnostics        | Retrieve diagnostics results  |
+//! | POST   | /api/v1/network/diagnostics        | Run connectivity diagnostics  |
+//! | GET    | /api/v1/network/fallback           | Retrieve AP fallback status   |
+//! | POST   | /api/v1/network/fallback           | Update AP fallback settings   |
+//! | GET    | /api/v1/network/firewall           | Retrieve firewall settings    |
+//! | POST   | /api/v1/network/firewall           | Update firewall settings      |
+//! | POST   | /api/v1/network/firewall/confirm   | Confirm firewall rules        |
+//! | POST   | /api/v1/network/firewall/revert    | Revert firewall rules         |
+//! | GET    | /api/v1/network/ip                 |                               |
+//! | GET    | /api/v1/network/ip/config?<iface>  | Retrieve IP settings          |
+//! | POST   | /api/v1/network/ip/config          | Update IP settings            |
+//! | POST   | /api/v1/network/ip/config/confirm  | Confirm IP settings           |
+//! | POST   | /api/v1/network/ip/config/revert   | Revert IP settings            |
+//! | GET    | /api/v1/network/rssi               |                               |
+//! | GET    | /api/v1/network/rssi/history       | Retrieve RSSI samples         |
+//! | GET    | /api/v1/network/ssid               |                               |
+//! | GET    | /api/v1/network/state              |                               |
+//! | GET    | /api/v1/network/status             |                               |
+//! | GET    | /api/v1/network/wired              | Retrieve wired interfaces     |
+//! | GET    | /api/v1/network/yggdrasil          | Retrieve Yggdrasil status     |
+//! | POST   | /api/v1/network/yggdrasil          | Enable / disable Yggdrasil    |
+//! | POST   | /api/v1/network/yggdrasil/add      | Add Yggdrasil peer            |
+//! | POST   | /api/v1/network/yggdrasil/remove   | Remove Yggdrasil peer         |
+//! | GET    | /api/v1/network/wifi               | Retrieve available networks   |
+//! | POST   | /api/v1/network/wifi               | Add WiFi AP credentials       |
+//! | POST   | /api/v1/network/wifi/uri           | Parse WiFi QR code            |
+//! | POST   | /api/v1/network/wifi/connect       | Connect to WiFi access point  |
+//! | POST   | /api/v1/network/wifi/disconnect    | Disconnect WiFi access point  |
+//! | POST   | /api/v1/network/wifi/forget        | Forget / remove network       |
+//! | POST   | /api/v1/network/wifi/modify        | Modify network password       |
+//! | GET    | /api/v1/network/wifi/saved         | Retrieve saved networks       |
+//! | POST   | /api/v1/network/wifi/priority      | Set saved network order       |
+//! | POST   | /api/v1/network/wifi/autoconnect   | Enable / disable auto-connect |
+//! | GET    | /api/v1/network/wifi/survey        | Retrieve site survey results  |
+//! | POST   | /api/v1/network/wifi/survey        | Run WiFi site survey          |
+//! | POST   | /api/v1/network/wifi/usage         | Update alert thresholds       |
+//! | POST   | /api/v1/network/wifi/usage/reset   | Reset stored data usage total |
+//! | POST   | /api/v1/network/wifi/usage/cycle   | Update billing cycle          |
+//! | GET    | /api/v1/ping                       |                               |
+//! | GET    | /api/v1/ping/network               | Ping `peach-network`          |
+//! | GET    | /api/v1/ping/oled                  | Ping `peach-oled`             |
+//! | GET    | /api/v1/ping/stats                 | Ping `peach-stats`            |
+//! | POST   | /api/v1/dns/configure              | Modify dns configurations     |
+//! | GET    | /api/v1/dns/certificate            | Retrieve HTTPS certificates   |
+//! | POST   | /api/v1/dns/certificate            | Request HTTPS certificate now |
+//! | GET    | /api/v1/dns/status                 | Retrieve dynamic DNS status   |
+//! | POST   | /api/v1/dns/update                 | Push dynamic DNS update now   |
+//! | GET    | /api/v1/alerts                     | Retrieve alert rules & alerts |
+//! | POST   | /api/v1/alerts                     | Add resource alert rule       |
+//! | POST   | /api/v1/alerts/modify              | Modify resource alert rule    |
+//! | POST   | /api/v1/alerts/delete              | Delete resource alert rule    |
+//! | POST   | /api/v1/settings/change_password   | Change password (logged in)   |
+//! | GET    | /api/v1/settings/hostname          | Retrieve hostname             |
+//! | POST   | /api/v1/settings/hostname          | Set hostname and mDNS name    |
+//! | GET    | /api/v1/settings/datetime          | Retrieve time and timezones   |
+//! | POST   | /api/v1/settings/datetime/timezone | Set timezone                  |
+//! | POST   | /api/v1/settings/datetime/ntp      | Enable / disable NTP          |
+//! | POST   | /api/v1/settings/datetime/clock    | Set clock                     |
+//! | POST   | /public/api/v1/reset_password      | Change password (public)      |
 
 use log::{debug, warn};
-use rocket::{get, post};
+use rocket::{get, post, State};
 use rocket_contrib::json;
 use rocket_contrib::json::{Json, JsonValue};
 use serde::Serialize;
@@ -42,13 +89,36 @@ use peach_lib::dyndns_client::is_dns_updater_online;
 use peach_lib::network_client;
 use peach_lib::oled_client;
 use peach_lib::stats_client;
-use peach_lib::stats_client::Traffic;
 
+use crate::ap;
+use crate::ap::ApConfig;
 use crate::common::{save_dns_configuration, save_password_form, save_reset_password_form};
+use crate::datetime;
+use crate::datetime::{ClockForm, NtpForm, TimezoneForm};
 use crate::device;
-use crate::forms::{DnsForm, PasswordForm, ResetPasswordForm, Ssid, WiFi};
+use crate::diagnostics::{Diagnostics, Targets};
+use crate::dyndns;
+use crate::fallback;
+use crate::fallback::Settings;
+use crate::firewall;
+use crate::firewall::FirewallConfig;
+use crate::forms::{
+    AutoConnect, DnsForm, HostnameForm, Iface, MacAddress, NetworkOrder, PasswordForm, PeerUri,
+    ResetPasswordForm, RuleId, Ssid, WiFi, WifiUri,
+};
+use crate::hostname;
+use crate::ipconfig;
+use crate::ipconfig::IpConfig;
 use crate::monitor;
-use crate::monitor::Threshold;
+use crate::monitor::{Cycle, Rule, Threshold};
+use crate::qr;
+use crate::revert::Reverter;
+use crate::signal;
+use crate::signal::{Survey, SurveyOptions};
+use crate::tls;
+use crate::wifi;
+use crate::wired;
+use crate::yggdrasil;
 
 #[derive(Serialize)]
 pub struct JsonResponse {
@@ -101,6 +171,10 @@ pub fn shutdown_device() -> Json<JsonResponse> {
 pub fn activate_ap() -> Json<JsonResponse> {
     // activate the wireless access point
     debug!("Activating WiFi access point.");
+    // the access point was chosen by the user, so client mode is not retried
+    if let Err(err) = fallback::cancel() {
+        warn!("Failed to cancel WiFi fallback: {}", err);
+    }
     match network_client::activate_ap() {
         Ok(_) => {
             let status = "success".to_string();
@@ -114,6 +188,146 @@ pub fn activate_ap() -> Json<JsonResponse> {
     }
 }
 
+#[get("/api/v1/network/ap")]
+pub fn return_ap_config(reverter: State<Reverter>) -> Json<JsonResponse> {
+    match ap::get_config() {
+        Ok(config) => {
+            let status = "success".to_string();
+            let data = json!({
+                "config": config,
+                "pending": reverter.status("ap"),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve access point settings: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve access point settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ap", data = "<config>")]
+pub fn update_ap_config(config: Json<ApConfig>, reverter: State<Reverter>) -> Json<JsonResponse> {
+    match ap::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied access point settings.");
+            let status = "success".to_string();
+            let data = json!({ "timeout": Reverter::timeout() });
+            let msg = "Applied access point settings. Confirm before the timeout or they will be reverted.".to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to apply access point settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to apply access point settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ap/confirm")]
+pub fn confirm_ap_config(reverter: State<Reverter>) -> Json<JsonResponse> {
+    if reverter.confirm("ap") {
+        let status = "success".to_string();
+        let msg = "Confirmed access point settings.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    } else {
+        let status = "error".to_string();
+        let msg = "No unconfirmed access point settings.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    }
+}
+
+#[post("/api/v1/network/ap/revert")]
+pub fn revert_ap_config(reverter: State<Reverter>) -> Json<JsonResponse> {
+    match reverter.revert("ap") {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Reverted access point settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to revert access point settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to revert access point settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/network/ap/clients")]
+pub fn return_ap_clients() -> Json<JsonResponse> {
+    match ap::get_clients() {
+        Ok(clients) => {
+            let status = "success".to_string();
+            let data = json!({
+                "clients": clients,
+                "blocked": ap::get_blocked().unwrap_or_default(),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve access point clients: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve access point clients.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ap/clients/kick", data = "<client>")]
+pub fn kick_ap_client(client: Json<MacAddress>) -> Json<JsonResponse> {
+    match ap::kick(&client.mac) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Disconnected client.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to disconnect access point client: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to disconnect client: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ap/clients/block", data = "<client>")]
+pub fn block_ap_client(client: Json<MacAddress>) -> Json<JsonResponse> {
+    match ap::block(&client.mac) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Blocked client.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to block access point client: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to block client: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ap/clients/unblock", data = "<client>")]
+pub fn unblock_ap_client(client: Json<MacAddress>) -> Json<JsonResponse> {
+    match ap::unblock(&client.mac) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Unblocked client.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to unblock access point client: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to unblock client: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[post("/api/v1/network/activate_client")]
 pub fn activate_client() -> Json<JsonResponse> {
     // activate the wireless client
@@ -143,14 +357,116 @@ pub fn return_ip() -> Json<JsonResponse> {
         Ok(ip) => ip,
         Err(_) => "x.x.x.x".to_string(),
     };
-    let data = json!({
+    let mut data = json!({
         "wlan0": wlan_ip,
         "ap0": ap_ip
     });
+    // add the ip of each wired interface
+    for iface in wired::get_interfaces() {
+        data[iface.name.as_str()] = iface.ip.unwrap_or_else(|| "x.x.x.x".to_string()).into();
+    }
+    let status = "success".to_string();
+    Json(build_json_response(status, Some(data), None))
+}
+
+#[get("/api/v1/network/diagnostics")]
+pub fn return_diagnostics(diagnostics: State<Diagnostics>) -> Json<JsonResponse> {
     let status = "success".to_string();
+    let data = json!(diagnostics.report());
     Json(build_json_response(status, Some(data), None))
 }
 
+#[post("/api/v1/network/diagnostics", data = "<targets>")]
+pub fn run_diagnostics(
+    targets: Json<Targets>,
+    diagnostics: State<Diagnostics>,
+) -> Json<JsonResponse> {
+    match diagnostics.start(targets.into_inner()) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Running diagnostics.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            let status = "error".to_string();
+            let msg = format!("Failed to run diagnostics: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/network/ip/config?<iface>")]
+pub fn return_ip_config(iface: Option<String>, reverter: State<Reverter>) -> Json<JsonResponse> {
+    let iface = iface.unwrap_or_else(|| "wlan0".to_string());
+    match ipconfig::get_config(&iface) {
+        Ok(config) => {
+            let status = "success".to_string();
+            let data = json!({
+                "config": config,
+                "interfaces": ipconfig::interfaces(),
+                "pending": reverter.status(&format!("ip-{}", iface)),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve IP settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve IP settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ip/config", data = "<config>")]
+pub fn update_ip_config(config: Json<IpConfig>, reverter: State<Reverter>) -> Json<JsonResponse> {
+    match ipconfig::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied IP settings.");
+            let status = "success".to_string();
+            let data = json!({ "timeout": Reverter::timeout() });
+            let msg = "Applied IP settings. Confirm before the timeout or they will be reverted."
+                .to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to apply IP settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to apply IP settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/ip/config/confirm", data = "<iface>")]
+pub fn confirm_ip_config(iface: Json<Iface>, reverter: State<Reverter>) -> Json<JsonResponse> {
+    if reverter.confirm(&format!("ip-{}", iface.iface)) {
+        let status = "success".to_string();
+        let msg = "Confirmed IP settings.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    } else {
+        let status = "error".to_string();
+        let msg = "No unconfirmed IP settings.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    }
+}
+
+#[post("/api/v1/network/ip/config/revert", data = "<iface>")]
+pub fn revert_ip_config(iface: Json<Iface>, reverter: State<Reverter>) -> Json<JsonResponse> {
+    match reverter.revert(&format!("ip-{}", iface.iface)) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Reverted IP settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to revert IP settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to revert IP settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[get("/api/v1/network/rssi")]
 pub fn return_rssi() -> Json<JsonResponse> {
     // retrieve rssi for connected network
@@ -168,6 +484,46 @@ pub fn return_rssi() -> Json<JsonResponse> {
     }
 }
 
+#[get("/api/v1/network/rssi/history?<ssid>")]
+pub fn return_rssi_history(ssid: Option<String>) -> Json<JsonResponse> {
+    match signal::get_history(ssid.as_deref()) {
+        Ok(samples) => {
+            let status = "success".to_string();
+            let data = json!(samples);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve signal strength history: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve signal strength history: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/network/wifi/survey")]
+pub fn return_survey(survey: State<Survey>) -> Json<JsonResponse> {
+    let status = "success".to_string();
+    let data = json!(survey.report());
+    Json(build_json_response(status, Some(data), None))
+}
+
+#[post("/api/v1/network/wifi/survey", data = "<options>")]
+pub fn run_survey(options: Json<SurveyOptions>, survey: State<Survey>) -> Json<JsonResponse> {
+    match survey.start(options.into_inner()) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Running site survey.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            let status = "error".to_string();
+            let msg = format!("Failed to run site survey: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[get("/api/v1/network/ssid")]
 pub fn return_ssid() -> Json<JsonResponse> {
     // retrieve ssid for connected network
@@ -197,14 +553,96 @@ pub fn return_state() -> Json<JsonResponse> {
         Ok(state) => state,
         Err(_) => "unavailable".to_string(),
     };
-    let data = json!({
+    let mut data = json!({
         "wlan0": wlan_state,
         "ap0": ap_state
     });
+    // add the operational state of each wired interface
+    for iface in wired::get_interfaces() {
+        data[iface.name.as_str()] = iface.state.into();
+    }
+    let status = "success".to_string();
+    Json(build_json_response(status, Some(data), None))
+}
+
+#[get("/api/v1/network/wired")]
+pub fn return_wired() -> Json<JsonResponse> {
+    // link state, speed, ip and traffic of each wired interface
+    let data = json!(wired::get_interfaces());
     let status = "success".to_string();
     Json(build_json_response(status, Some(data), None))
 }
 
+#[get("/api/v1/network/yggdrasil")]
+pub fn return_yggdrasil() -> Json<JsonResponse> {
+    match yggdrasil::get_status() {
+        Ok(yggdrasil_status) => {
+            let status = "success".to_string();
+            let data = json!(yggdrasil_status);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve Yggdrasil status: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve Yggdrasil status.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/yggdrasil", data = "<settings>")]
+pub fn update_yggdrasil(settings: Json<yggdrasil::Settings>) -> Json<JsonResponse> {
+    match yggdrasil::update_settings(settings.into_inner()) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Updated Yggdrasil settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update Yggdrasil settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update Yggdrasil settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/yggdrasil/add", data = "<peer>")]
+pub fn add_yggdrasil_peer(peer: Json<PeerUri>) -> Json<JsonResponse> {
+    match yggdrasil::add_peer(&peer.uri) {
+        Ok(_) => {
+            debug!("Added Yggdrasil peer {}.", peer.uri);
+            let status = "success".to_string();
+            let msg = "Added peer.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to add Yggdrasil peer: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to add peer: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/yggdrasil/remove", data = "<peer>")]
+pub fn remove_yggdrasil_peer(peer: Json<PeerUri>) -> Json<JsonResponse> {
+    match yggdrasil::remove_peer(&peer.uri) {
+        Ok(_) => {
+            debug!("Removed Yggdrasil peer {}.", peer.uri);
+            let status = "success".to_string();
+            let msg = "Removed peer.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to remove Yggdrasil peer: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to remove peer: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[get("/api/v1/network/status")]
 pub fn return_status() -> Json<JsonResponse> {
     // retrieve status info for wlan0 interface
@@ -242,7 +680,7 @@ pub fn scan_networks() -> Json<JsonResponse> {
 #[post("/api/v1/network/wifi", data = "<wifi>")]
 pub fn add_wifi(wifi: Json<WiFi>) -> Json<JsonResponse> {
     // generate and write wifi config to wpa_supplicant
-    match network_client::add(&wifi.ssid, &wifi.pass) {
+    match wifi::add(&wifi) {
         Ok(_) => {
             debug!("Added WiFi credentials.");
             // force reread of wpa_supplicant.conf file with new credentials
@@ -255,11 +693,27 @@ pub fn add_wifi(wifi: Json<WiFi>) -> Json<JsonResponse> {
             let msg = "WiFi credentials added.".to_string();
             Json(build_json_response(status, None, Some(msg)))
         }
-        Err(_) => {
-            debug!("Failed to add WiFi credentials.");
+        Err(err) => {
+            debug!("Failed to add WiFi credentials: {}", err);
             // json response for failed update
             let status = "error".to_string();
-            let msg = "Failed to add WiFi credentials.".to_string();
+            let msg = format!("Failed to add WiFi credentials. {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/wifi/uri", data = "<qr>")]
+pub fn parse_wifi_qr(qr: Json<WifiUri>) -> Json<JsonResponse> {
+    match qr::parse_wifi_uri(&qr.uri) {
+        Ok(wifi) => {
+            let status = "success".to_string();
+            let data = json!(wifi);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            let status = "error".to_string();
+            let msg = format!("Invalid WiFi QR code: {}", err);
             Json(build_json_response(status, None, Some(msg)))
         }
     }
@@ -326,6 +780,62 @@ pub fn forget_ap(network: Json<Ssid>) -> Json<JsonResponse> {
     }
 }
 
+#[get("/api/v1/network/wifi/saved")]
+pub fn return_saved_networks() -> Json<JsonResponse> {
+    match wifi::saved_networks() {
+        Ok(networks) => {
+            let status = "success".to_string();
+            let data = json!(networks);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve saved networks: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve saved networks.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/wifi/priority", data = "<order>")]
+pub fn set_network_priority(order: Json<NetworkOrder>) -> Json<JsonResponse> {
+    match wifi::set_order(&order.ssids) {
+        Ok(_) => {
+            debug!("Updated saved network priorities.");
+            let status = "success".to_string();
+            let msg = "Updated network priority.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update network priority: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update network priority: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/wifi/autoconnect", data = "<network>")]
+pub fn set_network_autoconnect(network: Json<AutoConnect>) -> Json<JsonResponse> {
+    match wifi::set_auto_connect(&network.ssid, network.enabled) {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = if network.enabled {
+                "Enabled auto-connect."
+            } else {
+                "Disabled auto-connect."
+            };
+            Json(build_json_response(status, None, Some(msg.to_string())))
+        }
+        Err(err) => {
+            warn!("Failed to update network auto-connect: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update auto-connect: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[post("/api/v1/network/wifi/modify", data = "<wifi>")]
 pub fn modify_password(wifi: Json<WiFi>) -> Json<JsonResponse> {
     let ssid = &wifi.ssid;
@@ -372,18 +882,12 @@ pub fn reset_data_total() -> Json<JsonResponse> {
     match monitor::reset_data() {
         Ok(_) => {
             debug!("Reset network data usage total.");
-            let traffic = match network_client::traffic("wlan0") {
-                Ok(t) => t,
-                Err(_) => Traffic {
-                    received: 0,
-                    transmitted: 0,
-                    rx_unit: None,
-                    tx_unit: None,
-                },
+            // new wifi data usage total as bytes
+            let total = match monitor::get_data() {
+                Ok(data) => data.total,
+                Err(_) => 0,
             };
-            // current wifi traffic values as bytes
-            let current_traffic = traffic.received + traffic.transmitted;
-            let data = json!(current_traffic);
+            let data = json!(total);
             let status = "success".to_string();
             let msg = "Reset network data usage total.".to_string();
             Json(build_json_response(status, Some(data), Some(msg)))
@@ -397,6 +901,206 @@ pub fn reset_data_total() -> Json<JsonResponse> {
     }
 }
 
+#[get("/api/v1/network/fallback")]
+pub fn return_fallback() -> Json<JsonResponse> {
+    match fallback::get_status() {
+        Ok(fallback) => {
+            let status = "success".to_string();
+            let data = json!(fallback);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve fallback status: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/fallback", data = "<settings>")]
+pub fn update_fallback(settings: Json<Settings>) -> Json<JsonResponse> {
+    match fallback::update_settings(settings.into_inner()) {
+        Ok(_) => {
+            debug!("WiFi fallback settings updated.");
+            let status = "success".to_string();
+            let msg = "Updated fallback settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update WiFi fallback settings: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update fallback settings: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/network/firewall")]
+pub fn return_firewall(reverter: State<Reverter>) -> Json<JsonResponse> {
+    match firewall::get_config() {
+        Ok(config) => {
+            let status = "success".to_string();
+            let data = json!({
+                "config": config,
+                "services": firewall::services(&config),
+                "ruleset": firewall::get_ruleset().ok(),
+                "pending": reverter.status("firewall"),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve firewall settings: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve firewall settings.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/firewall", data = "<config>")]
+pub fn update_firewall(
+    config: Json<FirewallConfig>,
+    reverter: State<Reverter>,
+) -> Json<JsonResponse> {
+    match firewall::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied firewall rules.");
+            let status = "success".to_string();
+            let data = json!({ "timeout": Reverter::timeout() });
+            let msg =
+                "Applied firewall rules. Confirm before the timeout or they will be reverted."
+                    .to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to apply firewall rules: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to apply firewall rules: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/firewall/confirm")]
+pub fn confirm_firewall(reverter: State<Reverter>) -> Json<JsonResponse> {
+    if reverter.confirm("firewall") {
+        let status = "success".to_string();
+        let msg = "Confirmed firewall rules.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    } else {
+        let status = "error".to_string();
+        let msg = "No unconfirmed firewall rules.".to_string();
+        Json(build_json_response(status, None, Some(msg)))
+    }
+}
+
+#[post("/api/v1/network/firewall/revert")]
+pub fn revert_firewall(reverter: State<Reverter>) -> Json<JsonResponse> {
+    match reverter.revert("firewall") {
+        Ok(_) => {
+            let status = "success".to_string();
+            let msg = "Reverted firewall rules.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to revert firewall rules: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to revert firewall rules: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/network/wifi/usage/cycle", data = "<cycle>")]
+pub fn update_wifi_cycle(cycle: Json<Cycle>) -> Json<JsonResponse> {
+    match monitor::update_cycle(cycle.into_inner()) {
+        Ok(_) => {
+            debug!("Data usage billing cycle updated.");
+            let status = "success".to_string();
+            let msg = "Updated billing cycle.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update data usage billing cycle: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update billing cycle: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/alerts")]
+pub fn return_alerts() -> Json<JsonResponse> {
+    match monitor::get_resource_alerts() {
+        Ok(alerts) => {
+            let status = "success".to_string();
+            let data = json!(alerts);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(_) => {
+            warn!("Failed to retrieve resource alerts.");
+            let status = "error".to_string();
+            let msg = "Failed to retrieve resource alerts.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/alerts", data = "<rule>")]
+pub fn add_alert_rule(rule: Json<Rule>) -> Json<JsonResponse> {
+    match monitor::add_rule(rule.into_inner()) {
+        Ok(rule) => {
+            debug!("Added resource alert rule.");
+            let status = "success".to_string();
+            let data = json!(rule);
+            let msg = "Added alert rule.".to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to add resource alert rule: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to add alert rule: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/alerts/modify", data = "<rule>")]
+pub fn modify_alert_rule(rule: Json<Rule>) -> Json<JsonResponse> {
+    match monitor::update_rule(rule.into_inner()) {
+        Ok(_) => {
+            debug!("Modified resource alert rule.");
+            let status = "success".to_string();
+            let msg = "Updated alert rule.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to modify resource alert rule: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update alert rule: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/alerts/delete", data = "<rule>")]
+pub fn delete_alert_rule(rule: Json<RuleId>) -> Json<JsonResponse> {
+    match monitor::delete_rule(rule.id) {
+        Ok(_) => {
+            debug!("Deleted resource alert rule.");
+            let status = "success".to_string();
+            let msg = "Deleted alert rule.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to delete resource alert rule: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to delete alert rule: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 // status route: useful for checking connectivity from web client
 #[get("/api/v1/ping")]
 pub fn ping_pong() -> Json<JsonResponse> {
@@ -489,6 +1193,78 @@ pub fn save_dns_configuration_endpoint(dns_form: Json<DnsForm>) -> Json<JsonResp
     }
 }
 
+#[get("/api/v1/dns/certificate")]
+pub fn return_certificate() -> Json<JsonResponse> {
+    match tls::get_status() {
+        Ok(tls_status) => {
+            let status = "success".to_string();
+            let data = json!(tls_status);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve certificate status: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve certificate status: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/dns/certificate")]
+pub fn update_certificate() -> Json<JsonResponse> {
+    match tls::update(true).and_then(|_| tls::get_status()) {
+        Ok(tls_status) => {
+            debug!("Certificate obtained.");
+            let status = "success".to_string();
+            let data = json!(tls_status);
+            let msg = "Certificate obtained.".to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to obtain certificate: {}", err);
+            let status = "error".to_string();
+            let msg = format!("{}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/dns/status")]
+pub fn return_dns_status() -> Json<JsonResponse> {
+    match dyndns::get_status() {
+        Ok(dns_status) => {
+            let status = "success".to_string();
+            let data = json!(dns_status);
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve dynamic DNS status: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve dynamic DNS status: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/dns/update")]
+pub fn update_dns() -> Json<JsonResponse> {
+    match dyndns::force_update().and_then(|_| dyndns::get_status()) {
+        Ok(dns_status) => {
+            debug!("Dynamic DNS updated.");
+            let status = "success".to_string();
+            let data = json!(dns_status);
+            let msg = "Dynamic DNS updated.".to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update dynamic DNS: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update dynamic DNS: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 #[post("/api/v1/settings/change_password", data = "<password_form>")]
 pub fn save_password_form_endpoint(password_form: Json<PasswordForm>) -> Json<JsonResponse> {
     let result = save_password_form(password_form.into_inner());
@@ -506,6 +1282,119 @@ pub fn save_password_form_endpoint(password_form: Json<PasswordForm>) -> Json<Js
     }
 }
 
+#[get("/api/v1/settings/hostname")]
+pub fn return_hostname() -> Json<JsonResponse> {
+    match hostname::get() {
+        Ok(name) => {
+            let status = "success".to_string();
+            let data = json!({
+                "hostname": name,
+                "mdns_name": hostname::mdns_name(),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve hostname: {}", err);
+            let status = "error".to_string();
+            let msg = "Failed to retrieve hostname.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/settings/hostname", data = "<hostname_form>")]
+pub fn update_hostname(hostname_form: Json<HostnameForm>) -> Json<JsonResponse> {
+    match hostname::set(&hostname_form.hostname) {
+        Ok(_) => {
+            debug!("Hostname updated.");
+            let status = "success".to_string();
+            let data = json!({ "mdns_name": hostname::mdns_name() });
+            let msg = "Hostname updated.".to_string();
+            Json(build_json_response(status, Some(data), Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to set hostname: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to set hostname: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[get("/api/v1/settings/datetime")]
+pub fn return_datetime() -> Json<JsonResponse> {
+    match datetime::get_status() {
+        Ok(time_status) => {
+            let status = "success".to_string();
+            let data = json!({
+                "status": time_status,
+                "timezones": datetime::timezones(),
+            });
+            Json(build_json_response(status, Some(data), None))
+        }
+        Err(err) => {
+            warn!("Failed to retrieve time status: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to retrieve time status: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/settings/datetime/timezone", data = "<timezone_form>")]
+pub fn update_timezone(timezone_form: Json<TimezoneForm>) -> Json<JsonResponse> {
+    match datetime::set_timezone(&timezone_form.timezone) {
+        Ok(_) => {
+            debug!("Timezone updated.");
+            let status = "success".to_string();
+            let msg = "Timezone updated.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to set timezone: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to set timezone: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/settings/datetime/ntp", data = "<ntp_form>")]
+pub fn update_ntp(ntp_form: Json<NtpForm>) -> Json<JsonResponse> {
+    match datetime::set_ntp(ntp_form.enabled) {
+        Ok(_) => {
+            debug!("NTP setting updated.");
+            let status = "success".to_string();
+            let msg = "Time synchronisation updated.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to update NTP setting: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to update time synchronisation: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
+#[post("/api/v1/settings/datetime/clock", data = "<clock_form>")]
+pub fn update_clock(clock_form: Json<ClockForm>) -> Json<JsonResponse> {
+    match datetime::set_clock(clock_form.timestamp) {
+        Ok(_) => {
+            debug!("Clock set.");
+            let status = "success".to_string();
+            let msg = "Clock set.".to_string();
+            Json(build_json_response(status, None, Some(msg)))
+        }
+        Err(err) => {
+            warn!("Failed to set clock: {}", err);
+            let status = "error".to_string();
+            let msg = format!("Failed to set clock: {}", err);
+            Json(build_json_response(status, None, Some(msg)))
+        }
+    }
+}
+
 /// this reset password route is used by a user who is not logged in
 /// and is specifically for users who have forgotten their password
 /// all routes under /public/* are excluded from nginx basic auth via the nginx config
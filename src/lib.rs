@@ -28,17 +28,36 @@
 // to replace code with the same code that is already there (possibly a bug)
 #![allow(clippy::nonstandard_macro_braces)]
 
+pub mod ap;
 pub mod common;
 pub mod context;
+pub mod datetime;
 pub mod device;
+pub mod diagnostics;
+pub mod dyndns;
 pub mod error;
+pub mod export;
+pub mod fallback;
+pub mod firewall;
 pub mod forms;
+pub mod hostname;
+pub mod ipconfig;
 pub mod json_api;
+pub mod metrics;
 pub mod monitor;
+pub mod qr;
+pub mod revert;
 pub mod routes;
+pub mod setup;
+pub mod signal;
+pub mod store;
 #[cfg(test)]
 mod tests;
+pub mod tls;
+pub mod wifi;
+pub mod wired;
 mod ws;
+pub mod yggdrasil;
 
 use std::{env, thread};
 
@@ -47,33 +66,81 @@ use log::{debug, error, info};
 use rocket::{catchers, routes};
 use rocket_contrib::templates::Template;
 
+use crate::diagnostics::Diagnostics;
 use crate::json_api::*;
+use crate::metrics::RequestMetrics;
+use crate::revert::Reverter;
 use crate::routes::*;
+use crate::signal::Survey;
 use crate::ws::*;
 
 pub type BoxError = Box<dyn std::error::Error>;
 
+/// Number of seconds given by an environment variable, or the default if the
+/// variable is unset or not a number. Used to configure the intervals and
+/// timeouts of the background tasks.
+pub fn interval(var: &str, default: u64) -> u64 {
+    env::var(var)
+        .ok()
+        .and_then(|secs| secs.parse::<u64>().ok())
+        .unwrap_or(default)
+}
+
 // create rocket instance & mount web & json routes (makes testing easier)
 fn rocket() -> rocket::Rocket {
+    // request counters are shared between the fairing and the metrics route
+    let request_metrics = RequestMetrics::default();
+
     rocket::ignite()
         .mount(
             "/",
             routes![
                 add_credentials,                 // WEB ROUTE
+                ap_clients,                      // WEB ROUTE
+                ap_clients_block,                // WEB ROUTE
+                ap_clients_kick,                 // WEB ROUTE
+                ap_clients_unblock,              // WEB ROUTE
+                network_diagnostics,             // WEB ROUTE
+                network_diagnostics_run,         // WEB ROUTE
+                setup_page,                      // WEB ROUTE
+                setup_step,                      // WEB ROUTE
+                setup_password,                  // WEB ROUTE
+                setup_wifi,                      // WEB ROUTE
+                setup_hostname,                  // WEB ROUTE
+                setup_dns,                       // WEB ROUTE
+                setup_complete,                  // WEB ROUTE
+                probe_generate_204,              // WEB ROUTE
+                probe_gen_204,                   // WEB ROUTE
+                probe_hotspot_detect,            // WEB ROUTE
+                probe_apple_success,             // WEB ROUTE
+                probe_connecttest,               // WEB ROUTE
+                probe_ncsi,                      // WEB ROUTE
+                probe_firefox,                   // WEB ROUTE
+                ap_settings,                     // WEB ROUTE
+                ap_settings_post,                // WEB ROUTE
+                ap_settings_confirm,             // WEB ROUTE
+                ap_settings_revert,              // WEB ROUTE
                 connect_wifi,                    // WEB ROUTE
                 disconnect_wifi,                 // WEB ROUTE
                 deploy_ap,                       // WEB ROUTE
                 deploy_client,                   // WEB ROUTE
                 device_stats,                    // WEB ROUTE
+                export_history,                  // WEB ROUTE
                 files,                           // WEB ROUTE
                 forget_wifi,                     // WEB ROUTE
                 help,                            // WEB ROUTE
+                ip_settings,                     // WEB ROUTE
+                ip_settings_post,                // WEB ROUTE
+                ip_settings_confirm,             // WEB ROUTE
+                ip_settings_revert,              // WEB ROUTE
                 index,                           // WEB ROUTE
                 login,                           // WEB ROUTE
+                prometheus_metrics,              // WEB ROUTE
                 logout,                          // WEB ROUTE
                 messages,                        // WEB ROUTE
                 network_home,                    // WEB ROUTE
                 network_add_ssid,                // WEB ROUTE
+                network_add_uri,                 // WEB ROUTE
                 network_add_wifi,                // WEB ROUTE
                 network_detail,                  // WEB ROUTE
                 peers,                           // WEB ROUTE
@@ -81,15 +148,40 @@ fn rocket() -> rocket::Rocket {
                 reboot_cmd,                      // WEB ROUTE
                 shutdown_cmd,                    // WEB ROUTE
                 shutdown_menu,                   // WEB ROUTE
+                wifi_autoconnect,                // WEB ROUTE
                 wifi_list,                       // WEB ROUTE
                 wifi_password,                   // WEB ROUTE
+                wifi_priority,                   // WEB ROUTE
+                wifi_priority_move,              // WEB ROUTE
                 wifi_set_password,               // WEB ROUTE
+                wifi_survey,                     // WEB ROUTE
+                wifi_survey_run,                 // WEB ROUTE
                 wifi_usage,                      // WEB ROUTE
                 wifi_usage_alerts,               // WEB ROUTE
                 wifi_usage_reset,                // WEB ROUTE
+                wifi_usage_cycle,                // WEB ROUTE
+                fallback_settings,               // WEB ROUTE
+                firewall_settings,               // WEB ROUTE
+                firewall_settings_post,          // WEB ROUTE
+                firewall_settings_confirm,       // WEB ROUTE
+                firewall_settings_revert,        // WEB ROUTE
+                wired_detail,                    // WEB ROUTE
+                yggdrasil_settings,              // WEB ROUTE
+                yggdrasil_settings_post,         // WEB ROUTE
+                yggdrasil_add_peer,              // WEB ROUTE
+                yggdrasil_remove_peer,           // WEB ROUTE
                 configure_dns,                   // WEB ROUTE
                 configure_dns_post,              // WEB ROUTE
+                renew_certificate,               // WEB ROUTE
+                dns_status,                      // WEB ROUTE
+                dns_update_post,                 // WEB ROUTE
                 change_password,                 // WEB ROUTE
+                hostname_settings,               // WEB ROUTE
+                hostname_settings_post,          // WEB ROUTE
+                datetime_settings,               // WEB ROUTE
+                datetime_timezone_post,          // WEB ROUTE
+                datetime_ntp_post,               // WEB ROUTE
+                datetime_clock_post,             // WEB ROUTE
                 reset_password,                  // WEB ROUTE
                 reset_password_post,             // WEB ROUTE
                 send_password_reset_page,        // WEB ROUTE
@@ -100,10 +192,22 @@ fn rocket() -> rocket::Rocket {
                 delete_admin_post,               // WEB ROUTE
                 activate_ap,                     // JSON API
                 activate_client,                 // JSON API
+                return_ap_config,                // JSON API
+                update_ap_config,                // JSON API
+                confirm_ap_config,               // JSON API
+                revert_ap_config,                // JSON API
+                return_ap_clients,               // JSON API
+                kick_ap_client,                  // JSON API
+                block_ap_client,                 // JSON API
+                unblock_ap_client,               // JSON API
                 add_wifi,                        // JSON API
+                parse_wifi_qr,                   // JSON API
                 connect_ap,                      // JSON API
                 disconnect_ap,                   // JSON API
                 forget_ap,                       // JSON API
+                return_saved_networks,           // JSON API
+                set_network_priority,            // JSON API
+                set_network_autoconnect,         // JSON API
                 modify_password,                 // JSON API
                 ping_pong,                       // JSON API
                 test_route,                      // JSON API
@@ -112,27 +216,110 @@ fn rocket() -> rocket::Rocket {
                 ping_stats,                      // JSON API
                 reset_data_total,                // JSON API
                 return_ip,                       // JSON API
+                return_ip_config,                // JSON API
+                return_diagnostics,              // JSON API
+                run_diagnostics,                 // JSON API
+                return_fallback,                 // JSON API
+                update_fallback,                 // JSON API
+                return_firewall,                 // JSON API
+                update_firewall,                 // JSON API
+                confirm_firewall,                // JSON API
+                revert_firewall,                 // JSON API
+                update_ip_config,                // JSON API
+                confirm_ip_config,               // JSON API
+                revert_ip_config,                // JSON API
                 return_rssi,                     // JSON API
+                return_rssi_history,             // JSON API
+                return_survey,                   // JSON API
+                run_survey,                      // JSON API
                 return_ssid,                     // JSON API
                 return_state,                    // JSON API
                 return_status,                   // JSON API
+                return_wired,                    // JSON API
+                return_yggdrasil,                // JSON API
+                update_yggdrasil,                // JSON API
+                add_yggdrasil_peer,              // JSON API
+                remove_yggdrasil_peer,           // JSON API
                 reboot_device,                   // JSON API
                 scan_networks,                   // JSON API
                 shutdown_device,                 // JSON API
                 update_wifi_alerts,              // JSON API
+                update_wifi_cycle,               // JSON API
                 save_dns_configuration_endpoint, // JSON API
+                return_certificate,              // JSON API
+                update_certificate,              // JSON API
+                return_dns_status,               // JSON API
+                update_dns,                      // JSON API
+                return_alerts,                   // JSON API
+                add_alert_rule,                  // JSON API
+                modify_alert_rule,               // JSON API
+                delete_alert_rule,               // JSON API
                 save_password_form_endpoint,     // JSON API
+                return_hostname,                 // JSON API
+                update_hostname,                 // JSON API
+                return_datetime,                 // JSON API
+                update_timezone,                 // JSON API
+                update_ntp,                      // JSON API
+                update_clock,                    // JSON API
                 reset_password_form_endpoint,    // JSON API
             ],
         )
         .register(catchers![not_found, internal_error])
-        .attach(Template::fairing())
+        .manage(request_metrics.clone())
+        .manage(Reverter::default())
+        .manage(Diagnostics::default())
+        .manage(Survey::default())
+        .attach(request_metrics)
+        .attach(Template::custom(|engines| {
+            // the local network name of the device (eg. in the logout link)
+            engines
+                .tera
+                .register_function("mdns_name", Box::new(hostname::mdns_name_fn));
+        }))
 }
 
 // launch the rocket server
 pub fn run() -> Result<(), BoxError> {
     info!("Starting up.");
 
+    // only resolve the captive-portal probe hosts to the access point while
+    // first-run setup is incomplete
+    setup::update_captive_dns();
+
+    // spawn a separate thread to evaluate data usage alerts
+    thread::spawn(|| {
+        info!("Launching data usage monitor.");
+        monitor::run();
+    });
+
+    // spawn a separate thread to fall back to the access point when the wifi
+    // client connection is lost
+    thread::spawn(|| {
+        info!("Launching WiFi fallback watchdog.");
+        fallback::run();
+    });
+
+    // spawn a separate thread to add the configured peers to the yggdrasil
+    // daemon whenever it restarts
+    thread::spawn(|| {
+        info!("Launching Yggdrasil peer sync.");
+        yggdrasil::run();
+    });
+
+    // spawn a separate thread to generate the self-signed certificate and to
+    // obtain and renew the acme certificate
+    thread::spawn(|| {
+        info!("Launching certificate manager.");
+        tls::run();
+    });
+
+    // spawn a separate thread to record the address published for the
+    // dynamic domain
+    thread::spawn(|| {
+        info!("Launching dynamic DNS monitor.");
+        dyndns::run();
+    });
+
     // spawn a separate thread for rocket to prevent blocking websockets
     thread::spawn(|| {
         info!("Launching Rocket server.");
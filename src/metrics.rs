@@ -0,0 +1,376 @@
+//! Collect metrics for the Prometheus text exposition format.
+//!
+//! Device metrics are retrieved from the microservices each time the metrics
+//! are scraped. Request counts and latencies for `peach-web` itself are
+//! recorded by the `RequestMetrics` fairing.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+
+use peach_lib::config_manager::load_peach_config;
+use peach_lib::dyndns_client;
+use peach_lib::network_client;
+use peach_lib::oled_client;
+use peach_lib::sbot_client;
+use peach_lib::stats_client;
+use peach_lib::stats_client::DiskUsage;
+
+use crate::monitor;
+
+/// Upper bounds (in seconds) of the request latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// Time at which a request was received (cached for each request)
+struct RequestStart(Instant);
+
+/// Request latency histogram for a single route
+#[derive(Default)]
+struct Latency {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    // request counts keyed by (method, route, status)
+    requests: BTreeMap<(String, String, u16), u64>,
+    // request latencies keyed by (method, route)
+    latencies: BTreeMap<(String, String), Latency>,
+}
+
+/// Fairing which records the number and latency of requests served. The same
+/// instance is placed in managed state so the counters can be exposed.
+#[derive(Clone, Default)]
+pub struct RequestMetrics {
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        let elapsed = start.0.elapsed().as_secs_f64();
+        let method = request.method().as_str().to_string();
+        // label by route (rather than uri) to keep the number of series bounded
+        let route = match request.route() {
+            Some(route) => route.uri.path().to_string(),
+            None => "unmatched".to_string(),
+        };
+        let status = response.status().code;
+
+        if let Ok(mut counters) = self.counters.lock() {
+            *counters
+                .requests
+                .entry((method.clone(), route.clone(), status))
+                .or_insert(0) += 1;
+            let latency = counters.latencies.entry((method, route)).or_default();
+            for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                if elapsed <= *bound {
+                    latency.buckets[i] += 1;
+                }
+            }
+            latency.count += 1;
+            latency.sum += elapsed;
+        }
+    }
+}
+
+impl RequestMetrics {
+    /// Write request counts and latencies in the exposition format
+    fn write(&self, out: &mut String) {
+        let counters = match self.counters.lock() {
+            Ok(counters) => counters,
+            Err(_) => return,
+        };
+
+        header(
+            out,
+            "peach_web_http_requests_total",
+            "counter",
+            "Number of HTTP requests served by peach-web.",
+        );
+        for ((method, route, status), count) in counters.requests.iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\",status=\"{}\"",
+                escape(method),
+                escape(route),
+                status
+            );
+            sample(out, "peach_web_http_requests_total", &labels, *count as f64);
+        }
+
+        let name = "peach_web_http_request_duration_seconds";
+        header(
+            out,
+            name,
+            "histogram",
+            "Latency of HTTP requests served by peach-web.",
+        );
+        for ((method, route), latency) in counters.latencies.iter() {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            for (i, bound) in LATENCY_BUCKETS.iter().enumerate() {
+                let bucket_labels = format!("{},le=\"{}\"", labels, bound);
+                let bucket = format!("{}_bucket", name);
+                sample(out, &bucket, &bucket_labels, latency.buckets[i] as f64);
+            }
+            let bucket_labels = format!("{},le=\"+Inf\"", labels);
+            let bucket = format!("{}_bucket", name);
+            sample(out, &bucket, &bucket_labels, latency.count as f64);
+            sample(out, &format!("{}_sum", name), &labels, latency.sum);
+            sample(
+                out,
+                &format!("{}_count", name),
+                &labels,
+                latency.count as f64,
+            );
+        }
+    }
+}
+
+/// Escape a label value (backslash, double-quote and line feed)
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// write the HELP and TYPE lines for a metric
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+// write a single sample, with labels if given
+fn sample(out: &mut String, name: &str, labels: &str, value: f64) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+// write a gauge with a single unlabelled sample
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, "gauge", help);
+    sample(out, name, "", value);
+}
+
+fn bool_value(up: bool) -> f64 {
+    if up {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+/// Gather device and `peach-web` metrics in the Prometheus text exposition
+/// format. Metrics which are unavailable (eg. because a microservice is
+/// offline) are omitted, with the exception of the up/down metrics.
+pub fn gather(request_metrics: &RequestMetrics) -> String {
+    let mut out = String::new();
+
+    if let Ok(cpu) = stats_client::cpu_stats_percent() {
+        let name = "peach_cpu_usage_percent";
+        header(&mut out, name, "gauge", "CPU usage percentage by mode.");
+        sample(&mut out, name, "mode=\"user\"", f64::from(cpu.user));
+        sample(&mut out, name, "mode=\"system\"", f64::from(cpu.system));
+        sample(&mut out, name, "mode=\"nice\"", f64::from(cpu.nice));
+        sample(&mut out, name, "mode=\"idle\"", f64::from(cpu.idle));
+    }
+
+    if let Ok(load) = stats_client::load_average() {
+        let name = "peach_load_average";
+        header(&mut out, name, "gauge", "System load average.");
+        sample(&mut out, name, "period=\"1m\"", f64::from(load.one));
+        sample(&mut out, name, "period=\"5m\"", f64::from(load.five));
+        sample(&mut out, name, "period=\"15m\"", f64::from(load.fifteen));
+    }
+
+    if let Ok(mem) = stats_client::mem_stats() {
+        // memory statistics are reported in kilobytes
+        let name = "peach_memory_bytes";
+        header(&mut out, name, "gauge", "Memory statistics in bytes.");
+        sample(&mut out, name, "state=\"total\"", (mem.total * 1024) as f64);
+        sample(&mut out, name, "state=\"used\"", (mem.used * 1024) as f64);
+        sample(&mut out, name, "state=\"free\"", (mem.free * 1024) as f64);
+    }
+
+    if let Ok(disks) = stats_client::disk_usage() {
+        let partitions: Vec<DiskUsage> = serde_json::from_str(&disks).unwrap_or_default();
+        header(
+            &mut out,
+            "peach_disk_size_bytes",
+            "gauge",
+            "Size of each mounted partition in bytes.",
+        );
+        for disk in &partitions {
+            let labels = format!("mountpoint=\"{}\"", escape(&disk.mountpoint));
+            let size = (disk.one_k_blocks * 1024) as f64;
+            sample(&mut out, "peach_disk_size_bytes", &labels, size);
+        }
+        header(
+            &mut out,
+            "peach_disk_free_bytes",
+            "gauge",
+            "Free space on each mounted partition in bytes.",
+        );
+        for disk in &partitions {
+            let labels = format!("mountpoint=\"{}\"", escape(&disk.mountpoint));
+            let free = (disk.one_k_blocks_free * 1024) as f64;
+            sample(&mut out, "peach_disk_free_bytes", &labels, free);
+        }
+        header(
+            &mut out,
+            "peach_disk_used_percent",
+            "gauge",
+            "Used space on each mounted partition as a percentage.",
+        );
+        for disk in &partitions {
+            let labels = format!("mountpoint=\"{}\"", escape(&disk.mountpoint));
+            let used = disk.used_percentage as f64;
+            sample(&mut out, "peach_disk_used_percent", &labels, used);
+        }
+    }
+
+    // uptime is reported in minutes
+    if let Some(mins) = stats_client::uptime()
+        .ok()
+        .and_then(|mins| mins.parse::<f64>().ok())
+    {
+        gauge(
+            &mut out,
+            "peach_uptime_seconds",
+            "Time since the device booted in seconds.",
+            mins * 60.0,
+        );
+    }
+
+    // the interfaces counted towards the data usage total
+    let traffic: Vec<_> = monitor::interface_traffic()
+        .into_iter()
+        .filter_map(|(iface, traffic)| traffic.map(|t| (iface, t)))
+        .collect();
+    header(
+        &mut out,
+        "peach_network_received_bytes_total",
+        "counter",
+        "Bytes received by each network interface since boot.",
+    );
+    for (iface, (received, _)) in &traffic {
+        let labels = format!("interface=\"{}\"", iface);
+        sample(
+            &mut out,
+            "peach_network_received_bytes_total",
+            &labels,
+            *received as f64,
+        );
+    }
+    header(
+        &mut out,
+        "peach_network_transmitted_bytes_total",
+        "counter",
+        "Bytes transmitted by each network interface since boot.",
+    );
+    for (iface, (_, transmitted)) in &traffic {
+        let labels = format!("interface=\"{}\"", iface);
+        sample(
+            &mut out,
+            "peach_network_transmitted_bytes_total",
+            &labels,
+            *transmitted as f64,
+        );
+    }
+
+    if let Some(rssi) = network_client::rssi("wlan0")
+        .ok()
+        .and_then(|rssi| rssi.parse::<f64>().ok())
+    {
+        let name = "peach_wifi_rssi_dbm";
+        header(
+            &mut out,
+            name,
+            "gauge",
+            "Signal strength of the WiFi connection.",
+        );
+        sample(&mut out, name, "interface=\"wlan0\"", rssi);
+    }
+
+    // microservice availability (same checks as the device status page)
+    let name = "peach_microservice_up";
+    header(
+        &mut out,
+        name,
+        "gauge",
+        "Whether each microservice responds to a ping (1) or not (0).",
+    );
+    let network_up = network_client::ping().is_ok();
+    let oled_up = oled_client::ping().is_ok();
+    let stats_up = stats_client::ping().is_ok();
+    sample(
+        &mut out,
+        name,
+        "service=\"peach-network\"",
+        bool_value(network_up),
+    );
+    sample(
+        &mut out,
+        name,
+        "service=\"peach-oled\"",
+        bool_value(oled_up),
+    );
+    sample(
+        &mut out,
+        name,
+        "service=\"peach-stats\"",
+        bool_value(stats_up),
+    );
+
+    let sbot_is_online = sbot_client::is_sbot_online().unwrap_or(false);
+    gauge(
+        &mut out,
+        "peach_sbot_online",
+        "Whether the sbot is running (1) or not (0).",
+        bool_value(sbot_is_online),
+    );
+
+    let dyndns_is_online = match load_peach_config() {
+        Ok(config) if config.dyn_enabled => dyndns_client::is_dns_updater_online().unwrap_or(false),
+        _ => false,
+    };
+    gauge(
+        &mut out,
+        "peach_dyndns_online",
+        "Whether the dynamic dns updater is online (1) or not (0).",
+        bool_value(dyndns_is_online),
+    );
+
+    if let Ok(data) = monitor::get_data() {
+        gauge(
+            &mut out,
+            "peach_data_usage_bytes",
+            "Data usage total of wlan0 and the wired interfaces (since the last reset) in bytes.",
+            data.total as f64,
+        );
+    }
+
+    request_metrics.write(&mut out);
+
+    out
+}
@@ -1,40 +1,241 @@
 // Monitor data transmission totals, set thresholds and check alert flags
 
-use std::convert::TryInto;
+use std::collections::HashMap;
+use std::{fs, thread, time::Duration};
 
-use nest::{Error, Store, Value};
+use chrono::{DateTime, Datelike, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{debug, info, warn};
+use nest::Value;
 use rocket::request::FromForm;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+
+use peach_lib::network_client;
+use peach_lib::oled_client;
+use peach_lib::stats_client;
+use peach_lib::stats_client::DiskUsage;
+
+use crate::error::PeachWebError;
+use crate::signal;
+use crate::store::{DataStore, Values};
+use crate::wifi;
+use crate::wired;
+
+/// Default interval between data usage evaluations (in seconds)
+const MONITOR_INTERVAL: u64 = 60;
+/// Minimum time into a billing cycle before usage is projected (in seconds)
+const PROJECTION_MIN_ELAPSED: i64 = 3600;
+/// Maximum number of cleared resource alerts to keep in the history
+const ALERT_HISTORY_LEN: usize = 100;
+/// Number of hourly traffic and device samples to keep in the history
+const HISTORY_LEN: usize = 24 * 90;
+/// Maximum number of previous billing cycles to keep in the history (a year
+/// of weekly cycles)
+const CYCLE_HISTORY_LEN: usize = 52;
+/// Store file holding the traffic total and counter samples
+const TRAFFIC_FILE: [&str; 2] = ["net", "traffic"];
+/// Store file holding the data usage alert flags
+const ALERT_FILE: [&str; 2] = ["net", "alert"];
+/// Store file holding the resource alert rules
+const RULES_FILE: [&str; 2] = ["device", "rules"];
+/// Store file holding the active and previous resource alerts
+const ALERTS_FILE: [&str; 2] = ["device", "alerts"];
+/// Metrics which resource alert rules may be defined for
+pub const METRICS: [&str; 5] = ["cpu", "memory", "disk", "temperature", "data"];
 
 /// Network traffic data total
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 pub struct Data {
     pub total: u64, // total traffic in bytes
 }
 
 impl Data {
     /// Retrieve network traffic data values from the store
-    fn get(store: &Store) -> Data {
-        // retrieve previous network traffic statistics
-        let data_stored = match store.get(&["net", "traffic", "total"]) {
-            Ok(total) => total,
-            // return 0 if no value exists
-            Err(_) => Value::Uint(u64::MIN),
-        };
+    fn get(store: &DataStore) -> Result<Data, PeachWebError> {
+        let total = store.get_u64(&["net", "traffic", "total"])?;
 
-        let mut data = Vec::new();
-        // retrieve u64 from Value type
-        if let Value::Uint(total) = data_stored {
-            data.push(total);
-        };
+        Ok(Data { total })
+    }
+}
+
+/// Traffic counter value (received and transmitted bytes) of a network
+/// interface, as recorded at the last evaluation
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct InterfaceSample {
+    pub iface: String,
+    pub bytes: u64,
+}
 
-        Data { total: data[0] }
+/// Retrieve the traffic counter values recorded at the last evaluation
+fn get_samples(store: &DataStore) -> Result<Vec<InterfaceSample>, PeachWebError> {
+    store.get_list(&["net", "traffic", "interfaces"])
+}
+
+/// Add the traffic transmitted since the last evaluation to the stored total,
+/// returning it. The counters are read while the store is locked, so that
+/// they are compared with the samples recorded by a concurrent reset.
+fn add_traffic(store: &DataStore) -> Result<u64, PeachWebError> {
+    store.update(&TRAFFIC_FILE, |values| {
+        let previous: Vec<InterfaceSample> = values.get_list("interfaces")?;
+        let (bytes, samples) = traffic_since(&previous, &read_counters());
+        let total = values.get_u64("total")? + bytes;
+        values.set("total", Value::Uint(total));
+        values.set_list("interfaces", &samples)?;
+        Ok(bytes)
+    })
+}
+
+/// Calculate the traffic transmitted since the last evaluation from the
+/// current traffic counter of each interface (`None` if it cannot be read),
+/// returning it along with the samples to record. Counters are compared per
+/// interface, so that an interface appearing or disappearing does not affect
+/// the others:
+///
+/// - a counter below its sample has been reset (the device rebooted or the
+///   interface was recreated), in which case all of its traffic is new
+/// - all of the traffic of an interface without a sample is new, unless no
+///   samples have been recorded yet (eg. after upgrading), in which case the
+///   counters are only recorded
+/// - the sample of a counter which cannot be read is kept until it can be
+///   read again, while the samples of interfaces which are no longer present
+///   are dropped
+pub fn traffic_since(
+    samples: &[InterfaceSample],
+    counters: &[(String, Option<u64>)],
+) -> (u64, Vec<InterfaceSample>) {
+    let mut bytes = 0;
+    let mut updated = Vec::new();
+    for (iface, counter) in counters {
+        let sample = samples
+            .iter()
+            .find(|sample| sample.iface == *iface)
+            .map(|sample| sample.bytes);
+        let recorded = match (*counter, sample) {
+            (Some(current), Some(sample)) if current >= sample => {
+                bytes += current - sample;
+                current
+            }
+            (Some(current), _) => {
+                if !samples.is_empty() {
+                    bytes += current;
+                }
+                current
+            }
+            (None, Some(sample)) => sample,
+            (None, None) => continue,
+        };
+        updated.push(InterfaceSample {
+            iface: iface.clone(),
+            bytes: recorded,
+        });
     }
+
+    (bytes, updated)
+}
+
+/// Retrieve the received and transmitted bytes of the interfaces whose
+/// traffic counts towards the data total: `wlan0` and any wired interfaces.
+/// The `wlan0` counters are `None` if they cannot be read (eg. while the
+/// interface is being reconfigured).
+pub fn interface_traffic() -> Vec<(String, Option<(u64, u64)>)> {
+    let wlan = match network_client::traffic("wlan0") {
+        Ok(t) => Some((t.received, t.transmitted)),
+        Err(e) => {
+            debug!("Failed to read wlan0 traffic counters: {}", e);
+            None
+        }
+    };
+    let mut traffic = vec![("wlan0".to_string(), wlan)];
+    traffic.extend(
+        wired::counters()
+            .into_iter()
+            .map(|(iface, rx, tx)| (iface, Some((rx, tx)))),
+    );
+    traffic
+}
+
+/// Retrieve the current traffic counters (received and transmitted bytes
+/// combined) of the interfaces counted towards the data total
+fn read_counters() -> Vec<(String, Option<u64>)> {
+    interface_traffic()
+        .into_iter()
+        .map(|(iface, traffic)| (iface, traffic.map(|(rx, tx)| rx + tx)))
+        .collect()
+}
+
+/// Traffic transmitted since the last evaluation which has not yet been added
+/// to the stored total
+fn unrecorded_traffic(store: &DataStore) -> Result<u64, PeachWebError> {
+    Ok(traffic_since(&get_samples(store)?, &read_counters()).0)
+}
+
+/// Traffic transmitted during one hour
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrafficSample {
+    pub timestamp: i64, // start of the hour (unix timestamp)
+    pub bytes: u64,     // traffic total in bytes
+}
+
+/// Device resource metrics, sampled once an hour
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DeviceSample {
+    pub timestamp: i64,           // time of the sample (unix timestamp)
+    pub cpu: Option<f64>,         // percent
+    pub memory: Option<f64>,      // percent
+    pub disk: Option<f64>,        // percent (root partition)
+    pub temperature: Option<f64>, // degrees celsius
+}
+
+// unix timestamp for the start of the hour containing the given time
+fn hour_of(timestamp: i64) -> i64 {
+    timestamp - timestamp.rem_euclid(3600)
+}
+
+/// Add traffic to the hourly traffic history, dropping the oldest samples
+fn record_traffic(store: &DataStore, bytes: u64, now: i64) -> Result<(), PeachWebError> {
+    store.update(&["history", "traffic"], |values| {
+        let mut samples: Vec<TrafficSample> = values.get_list("samples")?;
+        let hour = hour_of(now);
+        match samples.last_mut() {
+            Some(sample) if sample.timestamp == hour => sample.bytes += bytes,
+            _ => samples.push(TrafficSample {
+                timestamp: hour,
+                bytes,
+            }),
+        }
+        let excess = samples.len().saturating_sub(HISTORY_LEN);
+        values.set_list("samples", &samples[excess..])
+    })
+}
+
+/// Add a device sample to the history if none has been recorded in the
+/// current hour, dropping the oldest samples
+fn record_device(
+    store: &DataStore,
+    metrics: &HashMap<&'static str, f64>,
+    now: i64,
+) -> Result<(), PeachWebError> {
+    store.update(&["history", "device"], |values| {
+        let mut samples: Vec<DeviceSample> = values.get_list("samples")?;
+        if let Some(sample) = samples.last() {
+            if hour_of(sample.timestamp) == hour_of(now) {
+                return Ok(());
+            }
+        }
+        samples.push(DeviceSample {
+            timestamp: now,
+            cpu: metrics.get("cpu").copied(),
+            memory: metrics.get("memory").copied(),
+            disk: metrics.get("disk").copied(),
+            temperature: metrics.get("temperature").copied(),
+        });
+        let excess = samples.len().saturating_sub(HISTORY_LEN);
+        values.set_list("samples", &samples[excess..])
+    })
 }
 
 /// Network traffic notification thresholds and flags (user-defined)
-#[derive(Debug, Deserialize, Serialize, FromForm)]
+#[derive(Debug, Default, Deserialize, Serialize, FromForm)]
 pub struct Threshold {
     warn: u64,       // traffic warning threshold
     cut: u64,        // traffic cutoff threshold
@@ -44,155 +245,804 @@ pub struct Threshold {
 
 impl Threshold {
     /// Retrieve notification thresholds and flags from the store
-    fn get(store: &Store) -> Threshold {
-        let mut threshold = Vec::new();
-
-        let warn_val = store
-            .get(&["net", "notify", "warn"])
-            .unwrap_or(Value::Uint(0));
-        if let Value::Uint(val) = warn_val {
-            threshold.push(val);
-        };
+    fn get(store: &DataStore) -> Result<Threshold, PeachWebError> {
+        Ok(Threshold {
+            warn: store.get_u64(&["net", "notify", "warn"])?,
+            cut: store.get_u64(&["net", "notify", "cut"])?,
+            warn_flag: store.get_bool(&["net", "notify", "warn_flag"])?,
+            cut_flag: store.get_bool(&["net", "notify", "cut_flag"])?,
+        })
+    }
 
-        let cut_val = store
-            .get(&["net", "notify", "cut"])
-            .unwrap_or(Value::Uint(0));
-        if let Value::Uint(val) = cut_val {
-            threshold.push(val);
-        };
+    /// Store notification thresholds and flags from user data
+    fn set(self, store: &DataStore) -> Result<(), PeachWebError> {
+        store.set_values(
+            &["net", "notify"],
+            vec![
+                ("warn", Value::Uint(self.warn)),
+                ("cut", Value::Uint(self.cut)),
+                ("warn_flag", Value::Bool(self.warn_flag)),
+                ("cut_flag", Value::Bool(self.cut_flag)),
+            ],
+        )
+    }
+}
 
-        let mut flag = Vec::new();
+/// Data plan billing cycle (user-defined)
+#[derive(Debug, Deserialize, Serialize, FromForm)]
+pub struct Cycle {
+    pub period: String,   // "none", "monthly" or "weekly"
+    pub day: u32,         // day of the month (1-31) or week (1 = Monday)
+    pub timezone: String, // tz database name (eg. "Africa/Nairobi")
+}
 
-        let warn_flag = store
-            .get(&["net", "notify", "warn_flag"])
-            .unwrap_or(Value::Bool(false));
-        if let Value::Bool(state) = warn_flag {
-            flag.push(state);
+impl Default for Cycle {
+    fn default() -> Cycle {
+        Cycle {
+            period: "none".to_string(),
+            day: 1,
+            timezone: "UTC".to_string(),
         }
+    }
+}
+
+impl Cycle {
+    /// Retrieve the billing cycle from the store
+    fn get(store: &DataStore) -> Result<Cycle, PeachWebError> {
+        let day = store.get_u64(&["net", "notify", "cycle_day"])?;
 
-        let cut_flag = store
-            .get(&["net", "notify", "cut_flag"])
-            .unwrap_or(Value::Bool(false));
-        if let Value::Bool(state) = cut_flag {
-            flag.push(state);
+        Ok(Cycle {
+            period: store.get_string(&["net", "notify", "cycle_period"])?,
+            day: day as u32,
+            timezone: store.get_string(&["net", "notify", "cycle_tz"])?,
+        })
+    }
+
+    /// Store the billing cycle from user data
+    fn set(&self, store: &DataStore) -> Result<(), PeachWebError> {
+        store.set_values(
+            &["net", "notify"],
+            vec![
+                ("cycle_period", Value::String(self.period.clone())),
+                ("cycle_day", Value::Uint(u64::from(self.day))),
+                ("cycle_tz", Value::String(self.timezone.clone())),
+            ],
+        )
+    }
+
+    /// Ensure the billing cycle values are valid
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let msg = match self.period.as_str() {
+            "none" => None,
+            "monthly" if self.day < 1 || self.day > 31 => {
+                Some("Day of the month must be between 1 and 31".to_string())
+            }
+            "weekly" if self.day < 1 || self.day > 7 => {
+                Some("Day of the week must be between 1 (Monday) and 7 (Sunday)".to_string())
+            }
+            "monthly" | "weekly" => None,
+            _ => Some(format!("Unknown billing cycle period: {}", self.period)),
+        };
+        let msg = msg.or_else(|| match self.timezone.parse::<Tz>() {
+            Ok(_) => None,
+            Err(_) => Some(format!("Unknown timezone: {}", self.timezone)),
+        });
+
+        match msg {
+            Some(msg) => Err(PeachWebError::InvalidBillingCycle { msg }),
+            None => Ok(()),
         }
+    }
+
+    /// Calculate the start and end (unix timestamps) of the billing cycle
+    /// which contains the given time. Returns `None` if no cycle is set.
+    pub fn bounds(&self, now: DateTime<Utc>) -> Option<(i64, i64)> {
+        let tz: Tz = self.timezone.parse().unwrap_or(Tz::UTC);
+        let today = now.with_timezone(&tz).date().naive_local();
 
-        Threshold {
-            warn: threshold[0],
-            cut: threshold[1],
-            warn_flag: flag[0],
-            cut_flag: flag[1],
+        match self.period.as_str() {
+            "monthly" => {
+                let (year, month) = (today.year(), today.month());
+                let (start, end) = if today.day() >= day_of_month(year, month, self.day) {
+                    let (next_year, next_month) = next_month(year, month);
+                    (
+                        NaiveDate::from_ymd(year, month, day_of_month(year, month, self.day)),
+                        NaiveDate::from_ymd(
+                            next_year,
+                            next_month,
+                            day_of_month(next_year, next_month, self.day),
+                        ),
+                    )
+                } else {
+                    let (prev_year, prev_month) = previous_month(year, month);
+                    (
+                        NaiveDate::from_ymd(
+                            prev_year,
+                            prev_month,
+                            day_of_month(prev_year, prev_month, self.day),
+                        ),
+                        NaiveDate::from_ymd(year, month, day_of_month(year, month, self.day)),
+                    )
+                };
+                Some((midnight(&tz, start), midnight(&tz, end)))
+            }
+            "weekly" => {
+                let weekday = today.weekday().number_from_monday();
+                let days_back = (weekday + 7 - self.day) % 7;
+                let start = today - chrono::Duration::days(i64::from(days_back));
+                let end = start + chrono::Duration::days(7);
+                Some((midnight(&tz, start), midnight(&tz, end)))
+            }
+            _ => None,
         }
     }
 
-    /// Store notification flags from user data
-    fn set(self, store: &Store) {
-        store
-            .set(&["net", "notify", "warn"], &Value::Uint(self.warn))
-            .unwrap();
-        store
-            .set(&["net", "notify", "cut"], &Value::Uint(self.cut))
-            .unwrap();
-        store
-            .set(
-                &["net", "notify", "warn_flag"],
-                &Value::Bool(self.warn_flag),
-            )
-            .unwrap();
-        store
-            .set(&["net", "notify", "cut_flag"], &Value::Bool(self.cut_flag))
-            .unwrap();
+    /// Format a unix timestamp as a date in the billing cycle timezone
+    fn date(&self, timestamp: i64) -> String {
+        let tz: Tz = self.timezone.parse().unwrap_or(Tz::UTC);
+        tz.timestamp(timestamp, 0).format("%Y-%m-%d").to_string()
     }
 }
 
-/// Warning and cutoff network traffic alert flags (programatically-defined)
+/// Data usage total for a completed billing cycle
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CycleUsage {
+    pub start: i64,    // start of the cycle (unix timestamp)
+    pub end: i64,      // end of the cycle (unix timestamp)
+    pub label: String, // start and end dates in the cycle timezone
+    pub total: u64,    // traffic total in bytes
+}
+
+/// Data usage for the current billing cycle, including a projection of the
+/// total usage at the end of the cycle
 #[derive(Debug, Serialize)]
-pub struct Alert {
-    warn: bool,
-    cut: bool,
+pub struct CycleStatus {
+    pub start: String,          // start date of the current cycle
+    pub end: String,            // end date of the current cycle
+    pub total: u64,             // traffic total for the current cycle in bytes
+    pub projected: Option<u64>, // projected traffic total for the cycle in bytes
+    pub cap_projected: bool,    // projected total exceeds the cap (in MB)
+    pub cap: u64,               // cutoff (or warning) threshold in MB
+    pub previous: Option<CycleUsage>,
 }
 
-impl Alert {
-    /// Retrieve latest alert flags from the store
-    fn get(store: &Store) -> Alert {
-        let mut alert = Vec::new();
+/// Project the traffic total at the end of a cycle from the total so far,
+/// assuming usage continues at the same average rate. Returns `None` when too
+/// little of the cycle has elapsed to make a meaningful projection.
+pub fn project(total: u64, elapsed: i64, length: i64) -> Option<u64> {
+    if elapsed < PROJECTION_MIN_ELAPSED || length <= 0 {
+        None
+    } else {
+        let rate = total as f64 / elapsed as f64;
+        Some((rate * length as f64) as u64)
+    }
+}
+
+// the given day of the month, clamped to the number of days in the month
+fn day_of_month(year: i32, month: u32, day: u32) -> u32 {
+    let (next_year, next_month) = next_month(year, month);
+    let days_in_month = NaiveDate::from_ymd(next_year, next_month, 1).pred().day();
+    day.min(days_in_month)
+}
+
+fn next_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    }
+}
+
+fn previous_month(year: i32, month: u32) -> (i32, u32) {
+    if month == 1 {
+        (year - 1, 12)
+    } else {
+        (year, month - 1)
+    }
+}
 
-        let warn_flag = store
-            .get(&["net", "alert", "warn"])
-            .unwrap_or(Value::Bool(false));
-        if let Value::Bool(flag) = warn_flag {
-            alert.push(flag);
+// unix timestamp for the start of the given date in the given timezone
+fn midnight(tz: &Tz, date: NaiveDate) -> i64 {
+    let naive = date.and_hms(0, 0, 0);
+    match tz.from_local_datetime(&naive).earliest() {
+        Some(datetime) => datetime.timestamp(),
+        // midnight is skipped by a daylight saving transition; use utc
+        None => naive.timestamp(),
+    }
+}
+
+/// Retrieve the usage totals of previous billing cycles (most recent first)
+fn get_cycle_history(store: &DataStore) -> Result<Vec<CycleUsage>, PeachWebError> {
+    store.get_list(&["net", "cycle", "history"])
+}
+
+/// Device resource alert rule (user-defined)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub id: u64,
+    pub metric: String,     // "cpu", "memory", "disk", "temperature" or "data"
+    pub comparator: String, // "above" or "below"
+    pub threshold: f64,     // percent, degrees celsius or MB (data)
+    pub duration: u64,      // seconds the condition must hold before alerting
+}
+
+impl Rule {
+    /// Ensure the rule values are valid
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let msg = if !METRICS.contains(&self.metric.as_str()) {
+            Some(format!("Unknown metric: {}", self.metric))
+        } else if self.comparator != "above" && self.comparator != "below" {
+            Some("Comparator must be either above or below".to_string())
+        } else if !self.threshold.is_finite() {
+            Some("Threshold must be a number".to_string())
+        } else {
+            None
+        };
+
+        match msg {
+            Some(msg) => Err(PeachWebError::InvalidAlertRule { msg }),
+            None => Ok(()),
         }
+    }
 
-        let cut_flag = store
-            .get(&["net", "alert", "cut"])
-            .unwrap_or(Value::Bool(false));
-        if let Value::Bool(flag) = cut_flag {
-            alert.push(flag);
+    /// Check whether the given metric value breaches the rule threshold
+    pub fn breached(&self, value: f64) -> bool {
+        match self.comparator.as_str() {
+            "above" => value > self.threshold,
+            "below" => value < self.threshold,
+            _ => false,
         }
+    }
+}
+
+/// Device resource alert (programatically-defined)
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ResourceAlert {
+    pub rule: Rule,
+    pub value: f64,           // metric value when the alert was raised
+    pub raised: i64,          // unix timestamp
+    pub cleared: Option<i64>, // unix timestamp
+}
+
+/// Resource alert rules along with active and previous alerts
+#[derive(Debug, Serialize)]
+pub struct ResourceAlerts {
+    pub rules: Vec<Rule>,
+    pub active: Vec<ResourceAlert>,
+    pub history: Vec<ResourceAlert>, // most recent first
+}
+
+impl ResourceAlerts {
+    /// Retrieve rules and alerts from the store
+    fn get(store: &DataStore) -> Result<ResourceAlerts, PeachWebError> {
+        Ok(ResourceAlerts {
+            rules: store.get_list(&["device", "rules", "list"])?,
+            active: store.get_list(&["device", "alerts", "active"])?,
+            history: store.get_list(&["device", "alerts", "history"])?,
+        })
+    }
+}
+
+/// Change the active and previous resource alerts, keeping the most recent
+/// alerts in the history
+fn update_alerts<F>(store: &DataStore, change: F) -> Result<(), PeachWebError>
+where
+    F: FnOnce(&mut Vec<ResourceAlert>, &mut Vec<ResourceAlert>) -> Result<(), PeachWebError>,
+{
+    store.update(&ALERTS_FILE, |values| {
+        let mut active: Vec<ResourceAlert> = values.get_list("active")?;
+        let mut history: Vec<ResourceAlert> = values.get_list("history")?;
+        change(&mut active, &mut history)?;
+        history.truncate(ALERT_HISTORY_LEN);
+        values.set_list("active", &active)?;
+        values.set_list("history", &history)
+    })
+}
+
+/// Clear the active alert for the given rule (if any), moving it to the alert
+/// history
+fn clear(
+    active: &mut Vec<ResourceAlert>,
+    history: &mut Vec<ResourceAlert>,
+    rule_id: u64,
+    now: i64,
+) {
+    if let Some(index) = active.iter().position(|a| a.rule.id == rule_id) {
+        let mut alert = active.remove(index);
+        alert.cleared = Some(now);
+        history.insert(0, alert);
+    }
+}
 
-        Alert {
-            warn: alert[0],
-            cut: alert[1],
+/// Retrieve the current value of each resource metric. Metrics which are
+/// unavailable are omitted.
+fn get_metrics(store: &DataStore) -> Result<HashMap<&'static str, f64>, PeachWebError> {
+    let mut metrics = HashMap::new();
+
+    if let Ok(cpu) = stats_client::cpu_stats_percent() {
+        metrics.insert("cpu", f64::from(cpu.user + cpu.system + cpu.nice));
+    }
+    if let Ok(mem) = stats_client::mem_stats() {
+        if mem.total > 0 {
+            metrics.insert("memory", mem.used as f64 / mem.total as f64 * 100.0);
         }
     }
+    if let Ok(disks) = stats_client::disk_usage() {
+        let partitions: Vec<DiskUsage> = serde_json::from_str(&disks).unwrap_or_default();
+        // select only the root partition
+        if let Some(disk) = partitions.iter().find(|disk| disk.mountpoint == "/") {
+            metrics.insert("disk", disk.used_percentage as f64);
+        }
+    }
+    // temperature is reported in millidegrees celsius
+    if let Ok(temp) = fs::read_to_string("/sys/class/thermal/thermal_zone0/temp") {
+        if let Ok(millidegrees) = temp.trim().parse::<f64>() {
+            metrics.insert("temperature", millidegrees / 1000.0);
+        }
+    }
+    let data = Data::get(store)?.total + unrecorded_traffic(store)?;
+    metrics.insert("data", (data / 1024 / 1024) as f64);
+
+    Ok(metrics)
 }
 
-fn create_store() -> std::result::Result<Store, Error> {
-    // define the path
-    let path = xdg::BaseDirectories::new()
-        .unwrap()
-        .create_data_directory("peachcloud")
-        .unwrap();
+/// Evaluate the resource alert rules against current metric values. An alert
+/// is raised once a rule has been breached for its full duration and cleared
+/// as soon as the rule is no longer breached. `breaches` holds the time at
+/// which each rule was first observed to be breached.
+fn evaluate_rules(breaches: &mut HashMap<u64, i64>) -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    let metrics = get_metrics(&store)?;
+    let now = Utc::now().timestamp();
+    record_device(&store, &metrics, now)?;
+
+    update_alerts(&store, |active, history| {
+        // the rules are read with the store locked, so that rules added or
+        // deleted while the metrics were read are taken into account
+        let rules: Vec<Rule> = store.get_list(&["device", "rules", "list"])?;
+        for rule in rules {
+            let value = match metrics.get(rule.metric.as_str()) {
+                Some(value) => *value,
+                None => continue,
+            };
+            let raised = active.iter().any(|a| a.rule.id == rule.id);
 
-    // define the schema
-    let schema = json!({
-        "net": {
-            "traffic": "json",
-            "alert": "json",
-            "notify": "json",
+            if rule.breached(value) {
+                let since = *breaches.entry(rule.id).or_insert(now);
+                if !raised && now - since >= rule.duration as i64 {
+                    info!(
+                        "Resource alert raised: {} {} {} (value: {:.1})",
+                        rule.metric, rule.comparator, rule.threshold, value
+                    );
+                    active.push(ResourceAlert {
+                        rule,
+                        value,
+                        raised: now,
+                        cleared: None,
+                    });
+                }
+            } else {
+                breaches.remove(&rule.id);
+                if raised {
+                    info!(
+                        "Resource alert cleared: {} {}",
+                        rule.metric, rule.comparator
+                    );
+                    clear(active, history, rule.id, now);
+                }
+            }
         }
+        Ok(())
     })
-    .try_into()?;
+}
 
-    // create the data store
-    let store = Store::new(path, schema);
+/// Warning and cutoff network traffic alert flags (programatically-defined)
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Alert {
+    warn: bool,
+    cut: bool,
+    disabled: Vec<String>, // ssids of the networks disabled by the cutoff
+}
+
+impl Alert {
+    /// Retrieve latest alert flags from the store
+    fn get(store: &DataStore) -> Result<Alert, PeachWebError> {
+        Ok(Alert {
+            warn: store.get_bool(&["net", "alert", "warn"])?,
+            cut: store.get_bool(&["net", "alert", "cut"])?,
+            disabled: store.get_list(&["net", "alert", "disabled"])?,
+        })
+    }
+
+    /// Read alert flags from the values of the alert file
+    fn read(values: &Values) -> Result<Alert, PeachWebError> {
+        Ok(Alert {
+            warn: values.get_bool("warn")?,
+            cut: values.get_bool("cut")?,
+            disabled: values.get_list("disabled")?,
+        })
+    }
 
-    Ok(store)
+    /// Set alert flags and the networks disabled by the cutoff in the values
+    /// of the alert file
+    fn write(&self, values: &mut Values) -> Result<(), PeachWebError> {
+        values.set("warn", Value::Bool(self.warn));
+        values.set("cut", Value::Bool(self.cut));
+        values.set_list("disabled", &self.disabled)
+    }
 }
 
-pub fn get_alerts() -> std::result::Result<Alert, Error> {
-    let store = create_store()?;
-    let alerts = Alert::get(&store);
+pub fn get_alerts() -> Result<Alert, PeachWebError> {
+    let store = DataStore::open()?;
+    let alerts = Alert::get(&store)?;
 
     Ok(alerts)
 }
 
-pub fn get_data() -> std::result::Result<Data, Error> {
-    let store = create_store()?;
-    let data = Data::get(&store);
+// combined stored and current wifi traffic in bytes
+pub fn get_data() -> Result<Data, PeachWebError> {
+    let store = DataStore::open()?;
+    let stored = Data::get(&store)?;
+    // include traffic which has not yet been added to the stored total
+    let total = stored.total + unrecorded_traffic(&store)?;
 
-    Ok(data)
+    Ok(Data { total })
 }
 
-pub fn get_thresholds() -> std::result::Result<Threshold, Error> {
-    let store = create_store()?;
-    let thresholds = Threshold::get(&store);
+pub fn get_thresholds() -> Result<Threshold, PeachWebError> {
+    let store = DataStore::open()?;
+    let thresholds = Threshold::get(&store)?;
 
     Ok(thresholds)
 }
 
-// set stored traffic total to 0
-pub fn reset_data() -> std::result::Result<(), Error> {
-    let store = create_store()?;
-    store.set(&["net", "traffic", "total"], &Value::Uint(0))?;
+// set stored traffic total to 0, clear alerts and re-enable the wifi client
+// if it was disabled by the cutoff
+pub fn reset_data() -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    reset(&store)
+}
+
+fn reset(store: &DataStore) -> Result<(), PeachWebError> {
+    // traffic up to this point should not be counted towards the new total
+    store.update(&TRAFFIC_FILE, |values| {
+        let previous: Vec<InterfaceSample> = values.get_list("interfaces")?;
+        let (_, samples) = traffic_since(&previous, &read_counters());
+        values.set("total", Value::Uint(0));
+        values.set_list("interfaces", &samples)
+    })?;
+
+    let alert = store.update(&ALERT_FILE, |values| {
+        let alert = Alert::read(values)?;
+        Alert::default().write(values)?;
+        Ok(alert)
+    })?;
+    for ssid in alert.disabled {
+        reconnect(&ssid);
+    }
 
     Ok(())
 }
 
-pub fn update_store(threshold: Threshold) -> std::result::Result<(), Error> {
-    let store = create_store()?;
-    Threshold::set(threshold, &store);
+pub fn get_cycle() -> Result<Cycle, PeachWebError> {
+    let store = DataStore::open()?;
+    let cycle = Cycle::get(&store)?;
+
+    Ok(cycle)
+}
+
+pub fn get_cycles() -> Result<Vec<CycleUsage>, PeachWebError> {
+    let store = DataStore::open()?;
+    let history = get_cycle_history(&store)?;
+
+    Ok(history)
+}
+
+// usage for the current billing cycle (if a cycle is set)
+pub fn get_cycle_status() -> Result<Option<CycleStatus>, PeachWebError> {
+    let store = DataStore::open()?;
+    let cycle = Cycle::get(&store)?;
+    let now = Utc::now();
+
+    let (start, end) = match cycle.bounds(now) {
+        Some(bounds) => bounds,
+        None => return Ok(None),
+    };
+
+    let total = get_data()?.total;
+    let projected = project(total, now.timestamp() - start, end - start);
+    let threshold = Threshold::get(&store)?;
+    let cap = if threshold.cut > 0 {
+        threshold.cut
+    } else {
+        threshold.warn
+    };
+    let cap_projected = match projected {
+        Some(bytes) => cap > 0 && bytes / 1024 / 1024 >= cap,
+        None => false,
+    };
+    let previous = get_cycle_history(&store)?.into_iter().next();
+
+    Ok(Some(CycleStatus {
+        start: cycle.date(start),
+        end: cycle.date(end),
+        total,
+        projected,
+        cap_projected,
+        cap,
+        previous,
+    }))
+}
+
+// validate and store the billing cycle, tracking usage from the start of the
+// current cycle
+pub fn update_cycle(cycle: Cycle) -> Result<(), PeachWebError> {
+    cycle.validate()?;
+    let store = DataStore::open()?;
+    cycle.set(&store)?;
+    let start = match cycle.bounds(Utc::now()) {
+        Some((start, _)) => start,
+        None => 0,
+    };
+    store.set(&["net", "cycle", "start"], Value::Int(start))?;
 
     Ok(())
 }
+
+/// Archive the usage total and reset the data total if a new billing cycle
+/// has started since the last evaluation
+fn rollover(store: &DataStore) -> Result<(), PeachWebError> {
+    let cycle = Cycle::get(store)?;
+    let start = match cycle.bounds(Utc::now()) {
+        Some((start, _)) => start,
+        None => return Ok(()),
+    };
+
+    let archived = store.update(&["net", "cycle"], |values| {
+        let tracked = values.get_i64("start")?;
+        values.set("start", Value::Int(start));
+        if tracked == 0 || tracked >= start {
+            return Ok(false);
+        }
+        let total = Data::get(store)?.total;
+        info!(
+            "Billing cycle rollover: archiving usage total of {} bytes",
+            total
+        );
+        let mut history: Vec<CycleUsage> = values.get_list("history")?;
+        history.insert(
+            0,
+            CycleUsage {
+                start: tracked,
+                end: start,
+                label: format!("{} to {}", cycle.date(tracked), cycle.date(start)),
+                total,
+            },
+        );
+        history.truncate(CYCLE_HISTORY_LEN);
+        values.set_list("history", &history)?;
+        Ok(true)
+    })?;
+    if archived {
+        reset(store)?;
+    }
+
+    Ok(())
+}
+
+// hourly traffic totals within the given range (unix timestamps)
+pub fn get_traffic_history(from: i64, to: i64) -> Result<Vec<TrafficSample>, PeachWebError> {
+    let store = DataStore::open()?;
+    let samples: Vec<TrafficSample> = store.get_list(&["history", "traffic", "samples"])?;
+
+    Ok(samples
+        .into_iter()
+        .filter(|s| s.timestamp >= from && s.timestamp < to)
+        .collect())
+}
+
+// hourly device samples within the given range (unix timestamps)
+pub fn get_device_history(from: i64, to: i64) -> Result<Vec<DeviceSample>, PeachWebError> {
+    let store = DataStore::open()?;
+    let samples: Vec<DeviceSample> = store.get_list(&["history", "device", "samples"])?;
+
+    Ok(samples
+        .into_iter()
+        .filter(|s| s.timestamp >= from && s.timestamp < to)
+        .collect())
+}
+
+// active and previous resource alerts raised within the given range (unix
+// timestamps), oldest first
+pub fn get_alert_history(from: i64, to: i64) -> Result<Vec<ResourceAlert>, PeachWebError> {
+    let store = DataStore::open()?;
+    let alerts = ResourceAlerts::get(&store)?;
+    let mut raised: Vec<ResourceAlert> = alerts
+        .history
+        .into_iter()
+        .chain(alerts.active.into_iter())
+        .filter(|a| a.raised >= from && a.raised < to)
+        .collect();
+    raised.sort_by_key(|a| a.raised);
+
+    Ok(raised)
+}
+
+pub fn get_resource_alerts() -> Result<ResourceAlerts, PeachWebError> {
+    let store = DataStore::open()?;
+    let alerts = ResourceAlerts::get(&store)?;
+
+    Ok(alerts)
+}
+
+// validate and store a new resource alert rule, returning the rule (with id)
+pub fn add_rule(rule: Rule) -> Result<Rule, PeachWebError> {
+    rule.validate()?;
+    let store = DataStore::open()?;
+    // the id is allocated and the rule added in one update, so that
+    // concurrent requests get different ids
+    store.update(&RULES_FILE, |values| {
+        let id = values.get_u64("next_id")?;
+        let rule = Rule { id, ..rule };
+        let mut rules: Vec<Rule> = values.get_list("list")?;
+        rules.push(rule.clone());
+        values.set_list("list", &rules)?;
+        values.set("next_id", Value::Uint(id + 1));
+        Ok(rule)
+    })
+}
+
+// validate and replace an existing resource alert rule, clearing any alert
+// raised by the previous version of the rule
+pub fn update_rule(rule: Rule) -> Result<(), PeachWebError> {
+    rule.validate()?;
+    let store = DataStore::open()?;
+    let id = rule.id;
+    store.update(&RULES_FILE, |values| {
+        let mut rules: Vec<Rule> = values.get_list("list")?;
+        match rules.iter_mut().find(|r| r.id == id) {
+            Some(existing) => *existing = rule,
+            None => return Err(PeachWebError::AlertRuleNotFound { id }),
+        }
+        values.set_list("list", &rules)
+    })?;
+    update_alerts(&store, |active, history| {
+        clear(active, history, id, Utc::now().timestamp());
+        Ok(())
+    })
+}
+
+// remove a resource alert rule, clearing any alert raised by the rule
+pub fn delete_rule(id: u64) -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    store.update(&RULES_FILE, |values| {
+        let mut rules: Vec<Rule> = values.get_list("list")?;
+        let count = rules.len();
+        rules.retain(|r| r.id != id);
+        if rules.len() == count {
+            return Err(PeachWebError::AlertRuleNotFound { id });
+        }
+        values.set_list("list", &rules)
+    })?;
+    update_alerts(&store, |active, history| {
+        clear(active, history, id, Utc::now().timestamp());
+        Ok(())
+    })
+}
+
+pub fn update_store(threshold: Threshold) -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    threshold.set(&store)
+}
+
+/// Re-enable a network which was disabled by the data cutoff, without
+/// disabling the other saved networks (as selecting it would)
+fn reconnect(ssid: &str) {
+    match wifi::set_auto_connect(ssid, true) {
+        Ok(_) => info!("Re-enabled WiFi network {} after data usage reset", ssid),
+        Err(e) => warn!("Failed to re-enable WiFi network {}: {}", ssid, e),
+    }
+}
+
+/// Display an alert message on the OLED display
+fn notify(msg: &str, total_mb: u64) {
+    let usage = format!("Usage: {} MB", total_mb);
+    let result = oled_client::clear()
+        .and_then(|_| oled_client::write(0, 0, msg, "6x8"))
+        .and_then(|_| oled_client::write(0, 16, &usage, "6x8"))
+        .and_then(|_| oled_client::flush());
+    if result.is_err() {
+        warn!("Failed to display data usage alert via peach-oled");
+    }
+}
+
+/// Add new traffic to the stored total, compare the total to the user-defined
+/// thresholds and raise alerts accordingly. If the cutoff threshold has been
+/// reached (and the cutoff flag is set), the WiFi client is disabled and
+/// remains so until the data total is reset.
+fn evaluate() -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+
+    let bytes = add_traffic(&store)?;
+    if bytes > 0 {
+        record_traffic(&store, bytes, Utc::now().timestamp())?;
+    }
+
+    // start afresh if a new billing cycle has begun
+    rollover(&store)?;
+
+    let threshold = Threshold::get(&store)?;
+    store.update(&ALERT_FILE, |values| {
+        raise_alerts(&store, &threshold, values)
+    })
+}
+
+// raise the data usage alerts for the stored total, disabling the wifi client
+// once the cutoff is reached
+fn raise_alerts(
+    store: &DataStore,
+    threshold: &Threshold,
+    values: &mut Values,
+) -> Result<(), PeachWebError> {
+    let mut alert = Alert::read(values)?;
+    // the total is read with the store locked, so that alerts cleared by a
+    // concurrent reset are not raised again for the previous total.
+    // thresholds are defined in MB; a value of 0 means no threshold is set
+    let total_mb = Data::get(store)?.total / 1024 / 1024;
+
+    if threshold.warn_flag && threshold.warn > 0 && total_mb >= threshold.warn && !alert.warn {
+        info!("Data usage warning threshold reached: {} MB", total_mb);
+        alert.warn = true;
+        notify("Data warning reached", total_mb);
+    }
+
+    if threshold.cut_flag && threshold.cut > 0 && total_mb >= threshold.cut {
+        if !alert.cut {
+            info!("Data usage cutoff threshold reached: {} MB", total_mb);
+            alert.cut = true;
+            notify("Data cutoff: WiFi off", total_mb);
+        }
+        // disconnect from the current network (if connected); this also
+        // catches networks connected after the cutoff was first applied
+        if let Ok(ssid) = network_client::ssid("wlan0") {
+            match network_client::disable("wlan0", &ssid) {
+                Ok(_) => {
+                    info!("Disabled WiFi network {} due to data cutoff", ssid);
+                    if !alert.disabled.contains(&ssid) {
+                        alert.disabled.push(ssid);
+                    }
+                }
+                Err(_) => warn!("Failed to disable WiFi network {}", ssid),
+            }
+        }
+    }
+    alert.write(values)
+}
+
+/// Periodically evaluate data usage against the user-defined thresholds and
+/// device resources against the user-defined alert rules, and record the
+/// signal strength of the WiFi client connection.
+///
+/// The interval (in seconds) can be configured with the
+/// `PEACH_WEB_MONITOR_INTERVAL` environment variable.
+pub fn run() {
+    let interval = crate::interval("PEACH_WEB_MONITOR_INTERVAL", MONITOR_INTERVAL);
+    let mut breaches = HashMap::new();
+
+    loop {
+        debug!("Evaluating data usage alerts.");
+        if let Err(e) = evaluate() {
+            warn!("Failed to evaluate data usage alerts: {}", e);
+        }
+        debug!("Evaluating resource alert rules.");
+        if let Err(e) = evaluate_rules(&mut breaches) {
+            warn!("Failed to evaluate resource alert rules: {}", e);
+        }
+        debug!("Recording WiFi signal strength.");
+        if let Err(e) = DataStore::open().and_then(|store| signal::record(&store)) {
+            warn!("Failed to record WiFi signal strength: {}", e);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
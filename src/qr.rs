@@ -0,0 +1,162 @@
+//! WiFi network sharing via QR codes.
+//!
+//! Credentials are encoded in the `WIFI:` URI format understood by the camera
+//! apps of most phones, eg. `WIFI:T:WPA;S:PeachCloud;P:abundance;;`. The
+//! special characters `\`, `;`, `,`, `:` and `"` are escaped with a backslash.
+//! Only open, WPA-PSK and SAE networks can be shared: the credentials of a
+//! WPA-Enterprise network belong to a single user.
+//!
+//! QR codes are rendered on the server as SVG, so they can be embedded
+//! directly in templates. A `WIFI:` URI (eg. read by a QR code scanner on
+//! another device) can also be parsed into credentials for the add form.
+
+use qrcode::render::svg;
+use qrcode::QrCode;
+
+use crate::ap;
+use crate::device;
+use crate::error::PeachWebError;
+use crate::forms::WiFi;
+use crate::wifi;
+
+// escape the special characters of a `WIFI:` field value
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Build a `WIFI:` URI for the given credentials (of an open, WPA-PSK or SAE
+/// network)
+pub fn wifi_uri(wifi: &WiFi) -> Result<String, PeachWebError> {
+    let mut fields = Vec::new();
+    match wifi.security.as_deref() {
+        Some("open") => fields.push("T:nopass".to_string()),
+        Some("sae") => fields.push("T:SAE".to_string()),
+        Some("peap") | Some("ttls") => {
+            return Err(PeachWebError::InvalidWiFi {
+                msg: "WPA-Enterprise networks cannot be shared".to_string(),
+            })
+        }
+        _ => fields.push("T:WPA".to_string()),
+    }
+    fields.push(format!("S:{}", escape(&wifi.ssid)));
+    if wifi.security.as_deref() != Some("open") && !wifi.pass.is_empty() {
+        fields.push(format!("P:{}", escape(&wifi.pass)));
+    }
+    if wifi.hidden {
+        fields.push("H:true".to_string());
+    }
+
+    Ok(format!("WIFI:{};;", fields.join(";")))
+}
+
+// split the fields of a `WIFI:` URI on unescaped semicolons, removing escapes
+fn fields(body: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    field.push(escaped);
+                }
+            }
+            ';' => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if !field.is_empty() {
+        fields.push(field);
+    }
+    fields
+}
+
+/// Parse a `WIFI:` URI into credentials. The security type is mapped onto
+/// the values used by the add form ("wpa-psk", "sae", "open", "peap" or
+/// "ttls").
+pub fn parse_wifi_uri(uri: &str) -> Result<WiFi, PeachWebError> {
+    let invalid = |msg: &str| PeachWebError::InvalidWiFi {
+        msg: msg.to_string(),
+    };
+    let uri = uri.trim();
+    // the scheme is case-insensitive
+    if !uri
+        .get(..5)
+        .map_or(false, |s| s.eq_ignore_ascii_case("WIFI:"))
+    {
+        return Err(invalid("Not a WiFi QR code (expected WIFI:...)"));
+    }
+
+    let mut wifi = WiFi::default();
+    let mut kind = String::new();
+    let mut eap = String::new();
+    for field in fields(&uri[5..]) {
+        let mut parts = field.splitn(2, ':');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value.to_string()),
+            _ => continue,
+        };
+        match key {
+            "S" => wifi.ssid = value,
+            "P" => wifi.pass = value,
+            "T" => kind = value.to_uppercase(),
+            "H" => wifi.hidden = value.eq_ignore_ascii_case("true"),
+            "E" => eap = value.to_uppercase(),
+            "I" => wifi.identity = Some(value),
+            "A" => wifi.anonymous_identity = Some(value),
+            _ => (),
+        }
+    }
+    if wifi.ssid.is_empty() {
+        return Err(invalid("The WiFi QR code does not include an SSID"));
+    }
+
+    let security = match kind.as_str() {
+        "" if wifi.pass.is_empty() => "open",
+        "" | "WPA" | "WPA2" => "wpa-psk",
+        "NOPASS" => "open",
+        "SAE" | "WPA3" => "sae",
+        "WPA2-EAP" => match eap.as_str() {
+            "PEAP" => "peap",
+            "TTLS" => "ttls",
+            _ => return Err(invalid("Unsupported WPA-Enterprise method in WiFi QR code")),
+        },
+        "WEP" => return Err(invalid("WEP networks are not supported")),
+        _ => return Err(invalid("Unknown security type in WiFi QR code")),
+    };
+    wifi.security = Some(security.to_string());
+
+    Ok(wifi)
+}
+
+/// Render data as a QR code in SVG format
+pub fn svg(data: &str) -> Result<String, PeachWebError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let image = code
+        .render::<svg::Color>()
+        .min_dimensions(200, 200)
+        .dark_color(svg::Color("#000000"))
+        .light_color(svg::Color("#ffffff"))
+        .build();
+
+    Ok(image)
+}
+
+/// QR code for joining the access point (`ap0`)
+pub fn ap_svg() -> Result<String, PeachWebError> {
+    let conf = device::read_file(ap::HOSTAPD_CONF)?;
+    svg(&wifi_uri(&ap::credentials(&conf))?)
+}
+
+/// QR code for joining a saved WiFi network
+pub fn saved_network_svg(ssid: &str) -> Result<String, PeachWebError> {
+    let wifi = wifi::get_credentials(ssid)?;
+    svg(&wifi_uri(&wifi)?)
+}
@@ -0,0 +1,163 @@
+//! Confirm-or-revert guard for configuration changes which might cut off the
+//! admin's own connection to the device.
+//!
+//! A change is applied immediately and a revert action is scheduled. If the
+//! change is not confirmed before the timer expires (for example, because the
+//! admin can no longer reach the device), the revert action restores the
+//! previous configuration.
+//!
+//! Each kind of change (eg. "ap") has at most one pending revert. Applying
+//! another change of the same kind before confirming restarts the timer but
+//! keeps the original revert action, so the last confirmed configuration is
+//! always the one restored.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{thread, time::Duration};
+
+use chrono::Utc;
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::error::PeachWebError;
+
+/// Default time allowed to confirm a change (in seconds)
+const REVERT_TIMEOUT: u64 = 120;
+
+type RevertAction = Box<dyn FnOnce() -> Result<(), PeachWebError> + Send>;
+
+struct Pending {
+    description: String,
+    deadline: i64, // unix timestamp
+    generation: u64,
+    revert: RevertAction,
+}
+
+/// Status of an unconfirmed change
+#[derive(Debug, Serialize)]
+pub struct PendingStatus {
+    pub description: String,
+    pub remaining: i64, // seconds until the change is reverted
+}
+
+#[derive(Default)]
+struct Changes {
+    pending: HashMap<String, Pending>,
+    // incremented for every scheduled revert, to identify stale timers
+    generation: u64,
+}
+
+/// Shared register of unconfirmed changes (placed in managed state)
+#[derive(Clone, Default)]
+pub struct Reverter {
+    changes: Arc<Mutex<Changes>>,
+}
+
+impl Reverter {
+    // a panic while holding the lock leaves the register usable
+    fn lock(&self) -> MutexGuard<Changes> {
+        self.changes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Time allowed to confirm a change, configurable with the
+    /// `PEACH_WEB_REVERT_TIMEOUT` environment variable (in seconds)
+    pub fn timeout() -> u64 {
+        crate::interval("PEACH_WEB_REVERT_TIMEOUT", REVERT_TIMEOUT)
+    }
+
+    /// Schedule the revert action for a change which has just been applied.
+    /// The action runs in a background thread unless the change is confirmed
+    /// within the timeout.
+    pub fn schedule<F>(&self, kind: &str, description: &str, revert: F)
+    where
+        F: FnOnce() -> Result<(), PeachWebError> + Send + 'static,
+    {
+        let timeout = Reverter::timeout();
+        let deadline = Utc::now().timestamp() + timeout as i64;
+
+        let generation = {
+            let mut changes = self.lock();
+            changes.generation += 1;
+            let generation = changes.generation;
+            match changes.pending.get_mut(kind) {
+                // keep the original revert action (the last confirmed state)
+                Some(existing) => {
+                    existing.description = description.to_string();
+                    existing.deadline = deadline;
+                    existing.generation = generation;
+                }
+                None => {
+                    changes.pending.insert(
+                        kind.to_string(),
+                        Pending {
+                            description: description.to_string(),
+                            deadline,
+                            generation,
+                            revert: Box::new(revert),
+                        },
+                    );
+                }
+            }
+            generation
+        };
+
+        let reverter = self.clone();
+        let kind = kind.to_string();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(timeout));
+            reverter.expire(&kind, generation);
+        });
+    }
+
+    // run the revert action if the change has not been confirmed or
+    // superseded since the timer was started
+    fn expire(&self, kind: &str, generation: u64) {
+        let pending = {
+            let mut changes = self.lock();
+            match changes.pending.get(kind) {
+                Some(p) if p.generation == generation => changes.pending.remove(kind),
+                _ => None,
+            }
+        };
+        if let Some(pending) = pending {
+            info!("Reverting unconfirmed change: {}", pending.description);
+            if let Err(e) = (pending.revert)() {
+                warn!("Failed to revert {}: {}", pending.description, e);
+            }
+        }
+    }
+
+    /// Confirm a change, cancelling the revert. Returns `false` if there was no
+    /// unconfirmed change of the given kind.
+    pub fn confirm(&self, kind: &str) -> bool {
+        match self.lock().pending.remove(kind) {
+            Some(p) => {
+                info!("Confirmed change: {}", p.description);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Revert an unconfirmed change immediately
+    pub fn revert(&self, kind: &str) -> Result<(), PeachWebError> {
+        let pending = self.lock().pending.remove(kind);
+        match pending {
+            Some(pending) => {
+                info!("Reverting change: {}", pending.description);
+                (pending.revert)()
+            }
+            None => Err(PeachWebError::NoPendingChange {
+                kind: kind.to_string(),
+            }),
+        }
+    }
+
+    /// Retrieve the status of the unconfirmed change of the given kind (if any)
+    pub fn status(&self, kind: &str) -> Option<PendingStatus> {
+        self.lock().pending.get(kind).map(|p| PendingStatus {
+            description: p.description.clone(),
+            remaining: (p.deadline - Utc::now().timestamp()).max(0),
+        })
+    }
+}
@@ -11,78 +11,180 @@
 //! | GET    | /device                     | Device statistics                 |
 //! | GET    | /device/reboot              | Reboot device                     |
 //! | GET    | /device/shutdown            | Shutdown device                   |
+//! | GET    | /export/<kind>              | Download traffic, alert or device |
+//! |        |                             | history (CSV or JSON)             |
 //! | GET    | /help                       | Help and usage guidelines         |
+//! | GET    | /metrics                    | Prometheus metrics                |
 //! | GET    | /login                      | Login form                        |
 //! | POST   | /login                      | Login form submission             |
 //! | POST   | /logout                     | Logout authenticated user         |
 //! | GET    | /network                    | Network overview                  |
+//! | GET    | /network/ap                 | Access point settings form        |
+//! | POST   | /network/ap                 | Access point settings submission  |
 //! | GET    | /network/ap/activate        | Activate WiFi access point mode   |
+//! | GET    | /network/ap/clients         | Access point clients              |
+//! | POST   | /network/ap/clients/block   | Block access point client         |
+//! | POST   | /network/ap/clients/kick    | Disconnect access point client    |
+//! | POST   | /network/ap/clients/unblock | Unblock access point client       |
+//! | POST   | /network/ap/confirm         | Confirm access point settings     |
+//! | POST   | /network/ap/revert          | Revert access point settings      |
+//! | GET    | /network/diagnostics        | Connectivity diagnostics          |
+//! | POST   | /network/diagnostics        | Run connectivity diagnostics      |
+//! | GET    | /network/ip?<iface>         | IP settings form (wlan0 default)  |
+//! | POST   | /network/ip                 | IP settings submission            |
+//! | POST   | /network/ip/confirm         | Confirm IP settings               |
+//! | POST   | /network/ip/revert          | Revert IP settings                |
 //! | GET    | /network/wifi               | List of networks                  |
 //! | GET    | /network/wifi?<ssid>        | Details of single network         |
 //! | GET    | /network/wifi/activate      | Activate WiFi client mode         |
 //! | GET    | /network/wifi/add           | Add WiFi form                     |
 //! | POST   | /network/wifi/add           | WiFi form submission              |
 //! | GET    | /network/wifi/add?<ssid>    | Add WiFi form (SSID populated)    |
+//! | POST   | /network/wifi/add/uri       | Add WiFi form (from WiFi QR code) |
 //! | POST   | /network/wifi/connect       | Connect to WiFi access point      |
 //! | POST   | /network/wifi/disconnect    | Disconnect from WiFi access point |
 //! | POST   | /network/wifi/forget        | Remove WiFi                       |
+//! | POST   | /network/wifi/autoconnect   | Enable / disable auto-connect     |
 //! | GET    | /network/wifi/modify?<ssid> | Modify WiFi password form         |
 //! | POST   | /network/wifi/modify        | Modify network password           |
+//! | GET    | /network/wifi/priority      | Saved network priority order      |
+//! | POST   | /network/wifi/priority      | Move network up / down in order   |
+//! | GET    | /network/wifi/survey        | WiFi site survey                  |
+//! | POST   | /network/wifi/survey        | Run WiFi site survey              |
 //! | GET    | /network/wifi/usage         | WiFi data usage form              |
 //! | POST   | /network/wifi/usage         | WiFi data usage form submission   |
 //! | GET    | /network/wifi/usage/reset   | Reset stored data usage total     |
+//! | POST   | /network/wifi/usage/cycle   | Billing cycle form submission     |
+//! | POST   | /network/fallback           | Access point fallback settings    |
+//! | GET    | /network/firewall           | Firewall settings form            |
+//! | POST   | /network/firewall           | Firewall settings submission      |
+//! | POST   | /network/firewall/confirm   | Confirm firewall rules            |
+//! | POST   | /network/firewall/revert    | Revert firewall rules             |
+//! | GET    | /network/wired?<iface>      | Details of wired interface        |
+//! | GET    | /network/yggdrasil          | Yggdrasil status and peers        |
+//! | POST   | /network/yggdrasil          | Enable / disable Yggdrasil        |
+//! | POST   | /network/yggdrasil/add      | Add Yggdrasil peer                |
+//! | POST   | /network/yggdrasil/remove   | Remove Yggdrasil peer             |
 //! | GET    | /messages                   | Private Scuttlebutt messages      |
 //! | GET    | /peers                      | Scuttlebutt peers overview        |
 //! | GET    | /profile                    | Scuttlebutt user profile          |
 //! | GET    | /shutdown                   | Shutdown menu                     |
 //! | GET    | /network/dns                | View DNS configurations           |
 //! | POST   | /network/dns                | Modify DNS configurations         |
+//! | POST   | /network/dns/certificate    | Request HTTPS certificate now     |
+//! | GET    | /network/dns/status         | Dynamic DNS status and history    |
+//! | POST   | /network/dns/update         | Push dynamic DNS update now       |
 //! | GET    | /settings/change_password   | View password settings form       |
 //! | POST   | /settings/change_password   | Change admin password             |
+//! | GET    | /settings/hostname          | Hostname form                     |
+//! | POST   | /settings/hostname          | Set hostname and mDNS name        |
+//! | GET    | /settings/datetime          | Date, time and timezone settings  |
+//! | POST   | /settings/datetime/timezone | Set timezone                      |
+//! | POST   | /settings/datetime/ntp      | Enable / disable NTP              |
+//! | POST   | /settings/datetime/clock    | Set clock (eg. from browser time) |
 //! | GET    | /reset_password             | Change password using temp pass   |
 //! | POST   | /reset_password             | Rhange password using temp pass   |
 //! | GET    | /send_password_reset        | Send new password reset link      |
 //! | POST   | /send_password_reset        | Send new password reset link      |
+//! | GET    | /setup                      | First-run setup                   |
+//! | GET    | /setup/<step>               | First-run setup wizard step       |
+//! | POST   | /setup/password             | Set admin password (setup)        |
+//! | POST   | /setup/wifi                 | Add WiFi credentials (setup)      |
+//! | POST   | /setup/hostname             | Set hostname (setup)              |
+//! | POST   | /setup/dns                  | Configure dynamic DNS (setup)     |
+//! | POST   | /setup/complete             | Mark first-run setup complete     |
+//!
+//! CAPTIVE-PORTAL PROBES
+//!
+//! These answer the connectivity checks of common operating systems. While
+//! the access point is active and first-run setup is incomplete, they
+//! redirect to `/setup`; otherwise they give the expected response.
+//!
+//! | Method | URL                         | Description                       |
+//! | ------ | --------------------------- | --------------------------------- |
+//! | GET    | /generate_204               | Android / ChromeOS                |
+//! | GET    | /gen_204                    | Android / ChromeOS                |
+//! | GET    | /hotspot-detect.html        | Apple                             |
+//! | GET    | /library/test/success.html  | Apple                             |
+//! | GET    | /connecttest.txt            | Windows                           |
+//! | GET    | /ncsi.txt                   | Windows                           |
+//! | GET    | /success.txt                | Firefox                           |
 
 use std::path::{Path, PathBuf};
 
+use chrono::Utc;
 use log::{debug, info, warn};
 use percent_encoding::percent_decode;
-use rocket::http::RawStr;
+use rocket::http::{ContentType, RawStr, Status};
 use rocket::request::{FlashMessage, Form};
+use rocket::response::content::Content;
 use rocket::response::{Flash, NamedFile, Redirect};
-use rocket::{catch, get, post, uri};
+use rocket::{catch, get, post, uri, State};
 use rocket_contrib::templates::Template;
 
 use peach_lib::config_manager;
 use peach_lib::network_client;
 use peach_lib::password_utils;
 
+use crate::ap;
+use crate::ap::ApConfig;
 use crate::common::{
     save_add_admin_form, save_dns_configuration, save_password_form, save_reset_password_form,
+    save_setup_password_form,
 };
 use crate::context::{
-    AddAdminContext, ChangePasswordContext, ConfigureAdminContext, ConfigureDNSContext,
-    DeviceContext, ErrorContext, HelpContext, HomeContext, LoginContext, MessageContext,
-    NetworkAddContext, NetworkAlertContext, NetworkContext, NetworkDetailContext,
-    NetworkListContext, PeerContext, ProfileContext, ResetPasswordContext,
-    SendPasswordResetContext, ShutdownContext,
+    AddAdminContext, ApClientsContext, ApContext, ChangePasswordContext, ConfigureAdminContext,
+    ConfigureDNSContext, DateTimeContext, DeviceContext, DiagnosticsContext, DnsStatusContext,
+    ErrorContext, FirewallContext, HelpContext, HomeContext, HostnameContext, IpContext,
+    LoginContext, MessageContext, NetworkAddContext, NetworkAlertContext, NetworkContext,
+    NetworkDetailContext, NetworkListContext, NetworkPriorityContext, PeerContext, ProfileContext,
+    ResetPasswordContext, SendPasswordResetContext, SetupContext, ShutdownContext, SurveyContext,
+    WiredContext, YggdrasilContext,
 };
+use crate::datetime;
+use crate::datetime::{ClockForm, NtpForm, TimezoneForm};
 use crate::device;
+use crate::diagnostics::{Diagnostics, Targets};
+use crate::dyndns;
+use crate::error::PeachWebError;
+use crate::export;
+use crate::export::{Download, Format, Range};
+use crate::fallback;
+use crate::fallback::Settings;
+use crate::firewall;
+use crate::firewall::FirewallConfig;
 use crate::forms::{
-    AddAdminForm, DeleteAdminForm, DnsForm, PasswordForm, ResetPasswordForm, Ssid, WiFi,
+    AddAdminForm, AutoConnect, DeleteAdminForm, DnsForm, HostnameForm, Iface, MacAddress,
+    NetworkMove, PasswordForm, PeerUri, ResetPasswordForm, SetupPasswordForm, Ssid, WiFi, WifiUri,
 };
+use crate::hostname;
+use crate::ipconfig;
+use crate::ipconfig::IpConfig;
+use crate::metrics;
+use crate::metrics::RequestMetrics;
 use crate::monitor;
-use crate::monitor::Threshold;
+use crate::monitor::{Cycle, Threshold};
+use crate::qr;
+use crate::revert::Reverter;
+use crate::setup;
+use crate::signal;
+use crate::signal::{Survey, SurveyOptions};
+use crate::tls;
+use crate::wifi;
+use crate::yggdrasil;
 
 #[get("/")]
-pub fn index() -> Template {
+pub fn index() -> Result<Template, Redirect> {
+    // send the owner of a new device to the setup wizard
+    if setup::is_needed() {
+        return Err(Redirect::to(uri!(setup_page)));
+    }
     let context = HomeContext {
         flash_name: None,
         flash_msg: None,
         title: None,
     };
-    Template::render("index", &context)
+    Ok(Template::render("index", &context))
 }
 
 #[get("/device")]
@@ -117,6 +219,38 @@ pub fn shutdown_cmd() -> Flash<Redirect> {
     }
 }
 
+#[get("/export/<kind>?<format>&<from>&<to>")]
+pub fn export_history(
+    kind: String,
+    format: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Download, Flash<Redirect>> {
+    // report errors on the page the export was requested from
+    let url = match kind.as_str() {
+        "traffic" => "/network/wifi/usage",
+        _ => "/device",
+    };
+    let today = Utc::now().naive_utc().date();
+    let result = Format::parse(format.as_deref()).and_then(|format| {
+        let range = Range::parse(from.as_deref(), to.as_deref(), today)?;
+        export::export(&kind, format, range)
+    });
+    match result {
+        Ok(download) => {
+            debug!("Exported {} history as {}.", kind, download.filename);
+            Ok(download)
+        }
+        Err(err) => {
+            warn!("Failed to export {} history: {}", kind, err);
+            Err(Flash::error(
+                Redirect::to(url),
+                format!("Failed to export history: {}", err),
+            ))
+        }
+    }
+}
+
 #[get("/help")]
 pub fn help(flash: Option<FlashMessage>) -> Template {
     let mut context = HelpContext::build();
@@ -131,6 +265,234 @@ pub fn help(flash: Option<FlashMessage>) -> Template {
     Template::render("help", &context)
 }
 
+#[get("/setup")]
+pub fn setup_page(flash: Option<FlashMessage>) -> Result<Template, Redirect> {
+    // the first-run page is only available until setup is complete
+    if setup::is_complete() {
+        return Err(Redirect::to("/"));
+    }
+    let mut context = SetupContext::build();
+    context.title = Some("Welcome to PeachCloud".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    Ok(Template::render("setup", &context))
+}
+
+#[get("/setup/<step>")]
+pub fn setup_step(step: String, flash: Option<FlashMessage>) -> Result<Template, Redirect> {
+    if setup::is_complete() {
+        return Err(Redirect::to("/"));
+    }
+    // unknown steps return to the start of the wizard
+    if step != "finish" && !setup::STEPS.contains(&step.as_str()) {
+        return Err(Redirect::to(uri!(setup_page)));
+    }
+    // once the device is configured, only the finish step remains
+    if step != "finish" && !setup::is_needed() {
+        return Err(Redirect::to("/setup/finish"));
+    }
+    let mut context = SetupContext::build_step(&step);
+    context.back = Some("/setup".to_string());
+    context.title = Some("Setup".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    Ok(Template::render("setup", &context))
+}
+
+// redirect to the step after `step` on success, or back to `step` on error
+fn setup_redirect(
+    step: &str,
+    result: Result<(), PeachWebError>,
+    success: &str,
+    failure: &str,
+) -> Flash<Redirect> {
+    let next = setup::next_step(step).unwrap_or("finish");
+    match result {
+        Ok(_) => Flash::success(Redirect::to(format!("/setup/{}", next)), success),
+        Err(err) => {
+            warn!("Setup step {} failed: {}", step, err);
+            Flash::error(
+                Redirect::to(format!("/setup/{}", step)),
+                format!("{}: {}", failure, err),
+            )
+        }
+    }
+}
+
+// the wizard forms are not authenticated, so they may only be used until the
+// device has an admin password or SSB admin (whatever the setup state says)
+fn setup_guard() -> Result<(), Flash<Redirect>> {
+    if setup::is_needed() {
+        Ok(())
+    } else {
+        Err(Flash::error(Redirect::to("/"), "Setup is already complete"))
+    }
+}
+
+#[post("/setup/password", data = "<password_form>")]
+pub fn setup_password(password_form: Form<SetupPasswordForm>) -> Flash<Redirect> {
+    if let Err(redirect) = setup_guard() {
+        return redirect;
+    }
+    let password_form = password_form.into_inner();
+    let ssb_id = password_form
+        .ssb_id
+        .clone()
+        .filter(|ssb_id| !ssb_id.trim().is_empty());
+    if let Err(err) = save_setup_password_form(password_form) {
+        return setup_redirect(
+            "password",
+            Err(err),
+            "Admin password set",
+            "Failed to set admin password",
+        );
+    }
+    // setting the password closes the wizard forms, so the admin is added in
+    // the same step
+    let finish = Redirect::to("/setup/finish");
+    match ssb_id {
+        Some(ssb_id) => match save_add_admin_form(AddAdminForm { ssb_id }) {
+            Ok(_) => Flash::success(finish, "Admin password set and admin added"),
+            Err(err) => {
+                warn!("Failed to add admin during setup: {}", err);
+                Flash::error(
+                    finish,
+                    format!("Admin password set, but failed to add admin: {}", err),
+                )
+            }
+        },
+        None => Flash::success(finish, "Admin password set"),
+    }
+}
+
+#[post("/setup/wifi", data = "<wifi>")]
+pub fn setup_wifi(wifi: Form<WiFi>) -> Flash<Redirect> {
+    if let Err(redirect) = setup_guard() {
+        return redirect;
+    }
+    let result = wifi::add(&wifi.into_inner());
+    setup_redirect(
+        "wifi",
+        result,
+        "WiFi credentials added",
+        "Failed to add WiFi credentials",
+    )
+}
+
+#[post("/setup/hostname", data = "<hostname_form>")]
+pub fn setup_hostname(hostname_form: Form<HostnameForm>) -> Flash<Redirect> {
+    if let Err(redirect) = setup_guard() {
+        return redirect;
+    }
+    let result = hostname::set(&hostname_form.hostname);
+    setup_redirect(
+        "hostname",
+        result,
+        "Hostname updated",
+        "Failed to set hostname",
+    )
+}
+
+#[post("/setup/dns", data = "<dns>")]
+pub fn setup_dns(dns: Form<DnsForm>) -> Flash<Redirect> {
+    if let Err(redirect) = setup_guard() {
+        return redirect;
+    }
+    let result = save_dns_configuration(dns.into_inner());
+    setup_redirect(
+        "dns",
+        result,
+        "DNS configuration saved",
+        "Failed to save DNS configuration",
+    )
+}
+
+#[post("/setup/complete")]
+pub fn setup_complete() -> Flash<Redirect> {
+    // setup cannot be completed (ending the captive portal and closing the
+    // wizard) before the device has an admin password or SSB admin
+    if !setup::is_configured_now() {
+        return Flash::error(
+            Redirect::to("/setup/password"),
+            "Set an admin password before finishing setup",
+        );
+    }
+    match setup::mark_complete() {
+        Ok(_) => Flash::success(Redirect::to("/"), "Setup complete"),
+        Err(err) => {
+            warn!("Failed to mark setup complete: {}", err);
+            Flash::error(
+                Redirect::to(uri!(setup_page)),
+                format!("Failed to complete setup: {}", err),
+            )
+        }
+    }
+}
+
+// answer a captive-portal probe with the expected response, unless the
+// first-run page should be shown instead
+fn captive_probe<T>(response: T) -> Result<T, Redirect> {
+    if setup::captive_portal_active() {
+        debug!("Redirecting captive-portal probe to the first-run page");
+        // a temporary (302) redirect is what operating systems look for
+        Err(Redirect::found(setup::setup_url()))
+    } else {
+        Ok(response)
+    }
+}
+
+// page expected by apple devices
+const APPLE_SUCCESS: &str = "<HTML><HEAD><TITLE>Success</TITLE></HEAD><BODY>Success</BODY></HTML>";
+
+#[get("/generate_204")]
+pub fn probe_generate_204() -> Result<Status, Redirect> {
+    captive_probe(Status::NoContent)
+}
+
+#[get("/gen_204")]
+pub fn probe_gen_204() -> Result<Status, Redirect> {
+    captive_probe(Status::NoContent)
+}
+
+#[get("/hotspot-detect.html")]
+pub fn probe_hotspot_detect() -> Result<Content<&'static str>, Redirect> {
+    captive_probe(Content(ContentType::HTML, APPLE_SUCCESS))
+}
+
+#[get("/library/test/success.html")]
+pub fn probe_apple_success() -> Result<Content<&'static str>, Redirect> {
+    captive_probe(Content(ContentType::HTML, APPLE_SUCCESS))
+}
+
+#[get("/connecttest.txt")]
+pub fn probe_connecttest() -> Result<Content<&'static str>, Redirect> {
+    captive_probe(Content(ContentType::Plain, "Microsoft Connect Test"))
+}
+
+#[get("/ncsi.txt")]
+pub fn probe_ncsi() -> Result<Content<&'static str>, Redirect> {
+    captive_probe(Content(ContentType::Plain, "Microsoft NCSI"))
+}
+
+#[get("/success.txt")]
+pub fn probe_firefox() -> Result<Content<&'static str>, Redirect> {
+    captive_probe(Content(ContentType::Plain, "success\n"))
+}
+
+// metrics in the prometheus text exposition format
+#[get("/metrics")]
+pub fn prometheus_metrics(request_metrics: State<RequestMetrics>) -> Content<String> {
+    Content(ContentType::Plain, metrics::gather(&request_metrics))
+}
+
 #[get("/login")]
 pub fn login(flash: Option<FlashMessage>) -> Template {
     let mut context = LoginContext::build();
@@ -179,10 +541,269 @@ pub fn network_home(flash: Option<FlashMessage>) -> Template {
     Template::render("network_card", &context)
 }
 
+#[get("/network/ap")]
+pub fn ap_settings(flash: Option<FlashMessage>, reverter: State<Reverter>) -> Template {
+    let mut context = ApContext::build(&reverter);
+    context.back = Some("/network".to_string());
+    context.title = Some("Access Point Settings".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_ap", &context)
+}
+
+#[post("/network/ap", data = "<config>")]
+pub fn ap_settings_post(config: Form<ApConfig>, reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ap_settings);
+    match ap::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied access point settings.");
+            let msg = format!(
+                "Applied access point settings: reconnect and confirm within {} seconds or they will be reverted",
+                Reverter::timeout()
+            );
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to apply access point settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to apply access point settings: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/ap/confirm")]
+pub fn ap_settings_confirm(reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ap_settings);
+    if reverter.confirm("ap") {
+        Flash::success(Redirect::to(url), "Confirmed access point settings")
+    } else {
+        Flash::error(
+            Redirect::to(url),
+            "No unconfirmed access point settings (they may already have been reverted)",
+        )
+    }
+}
+
+#[post("/network/ap/revert")]
+pub fn ap_settings_revert(reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ap_settings);
+    match reverter.revert("ap") {
+        Ok(_) => Flash::success(Redirect::to(url), "Reverted access point settings"),
+        Err(err) => {
+            warn!("Failed to revert access point settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to revert access point settings: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/network/diagnostics")]
+pub fn network_diagnostics(
+    flash: Option<FlashMessage>,
+    diagnostics: State<Diagnostics>,
+) -> Template {
+    let mut context = DiagnosticsContext::build(&diagnostics);
+    context.back = Some("/network".to_string());
+    context.title = Some("Network Diagnostics".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_diagnostics", &context)
+}
+
+#[post("/network/diagnostics", data = "<targets>")]
+pub fn network_diagnostics_run(
+    targets: Form<Targets>,
+    diagnostics: State<Diagnostics>,
+) -> Flash<Redirect> {
+    let url = uri!(network_diagnostics);
+    match diagnostics.start(targets.into_inner()) {
+        Ok(_) => Flash::success(Redirect::to(url), "Running diagnostics"),
+        Err(err) => Flash::error(
+            Redirect::to(url),
+            format!("Failed to run diagnostics: {}", err),
+        ),
+    }
+}
+
+#[get("/network/wifi/survey")]
+pub fn wifi_survey(flash: Option<FlashMessage>, survey: State<Survey>) -> Template {
+    let mut context = SurveyContext::build(&survey);
+    context.back = Some("/network/wifi".to_string());
+    context.title = Some("Site Survey".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_survey", &context)
+}
+
+#[post("/network/wifi/survey", data = "<options>")]
+pub fn wifi_survey_run(options: Form<SurveyOptions>, survey: State<Survey>) -> Flash<Redirect> {
+    let url = uri!(wifi_survey);
+    match survey.start(options.into_inner()) {
+        Ok(_) => Flash::success(Redirect::to(url), "Running site survey"),
+        Err(err) => Flash::error(
+            Redirect::to(url),
+            format!("Failed to run site survey: {}", err),
+        ),
+    }
+}
+
+#[get("/network/ap/clients")]
+pub fn ap_clients(flash: Option<FlashMessage>) -> Template {
+    let mut context = ApClientsContext::build();
+    context.back = Some("/network".to_string());
+    context.title = Some("Access Point Clients".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_ap_clients", &context)
+}
+
+#[post("/network/ap/clients/kick", data = "<client>")]
+pub fn ap_clients_kick(client: Form<MacAddress>) -> Flash<Redirect> {
+    let url = uri!(ap_clients);
+    match ap::kick(&client.mac) {
+        Ok(_) => Flash::success(Redirect::to(url), "Disconnected client"),
+        Err(err) => {
+            warn!("Failed to disconnect access point client: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to disconnect client: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/ap/clients/block", data = "<client>")]
+pub fn ap_clients_block(client: Form<MacAddress>) -> Flash<Redirect> {
+    let url = uri!(ap_clients);
+    match ap::block(&client.mac) {
+        Ok(_) => Flash::success(Redirect::to(url), "Blocked client"),
+        Err(err) => {
+            warn!("Failed to block access point client: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to block client: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/ap/clients/unblock", data = "<client>")]
+pub fn ap_clients_unblock(client: Form<MacAddress>) -> Flash<Redirect> {
+    let url = uri!(ap_clients);
+    match ap::unblock(&client.mac) {
+        Ok(_) => Flash::success(Redirect::to(url), "Unblocked client"),
+        Err(err) => {
+            warn!("Failed to unblock access point client: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to unblock client: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/network/ip?<iface>")]
+pub fn ip_settings(
+    iface: Option<String>,
+    flash: Option<FlashMessage>,
+    reverter: State<Reverter>,
+) -> Template {
+    let iface = iface.unwrap_or_else(|| "wlan0".to_string());
+    let mut context = IpContext::build(&iface, &reverter);
+    context.back = Some("/network".to_string());
+    context.title = Some("IP Settings".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_ip", &context)
+}
+
+#[post("/network/ip", data = "<config>")]
+pub fn ip_settings_post(config: Form<IpConfig>, reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ip_settings: &config.iface);
+    match ipconfig::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied IP settings.");
+            let msg = format!(
+                "Applied IP settings: reconnect and confirm within {} seconds or they will be reverted",
+                Reverter::timeout()
+            );
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to apply IP settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to apply IP settings: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/ip/confirm", data = "<iface>")]
+pub fn ip_settings_confirm(iface: Form<Iface>, reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ip_settings: &iface.iface);
+    if reverter.confirm(&format!("ip-{}", iface.iface)) {
+        Flash::success(Redirect::to(url), "Confirmed IP settings")
+    } else {
+        Flash::error(
+            Redirect::to(url),
+            "No unconfirmed IP settings (they may already have been reverted)",
+        )
+    }
+}
+
+#[post("/network/ip/revert", data = "<iface>")]
+pub fn ip_settings_revert(iface: Form<Iface>, reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(ip_settings: &iface.iface);
+    match reverter.revert(&format!("ip-{}", iface.iface)) {
+        Ok(_) => Flash::success(Redirect::to(url), "Reverted IP settings"),
+        Err(err) => {
+            warn!("Failed to revert IP settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to revert IP settings: {}", err),
+            )
+        }
+    }
+}
+
 #[get("/network/ap/activate")]
 pub fn deploy_ap() -> Flash<Redirect> {
     // activate the wireless access point
     debug!("Activating WiFi access point.");
+    // the access point was chosen by the user, so client mode is not retried
+    if let Err(err) = fallback::cancel() {
+        warn!("Failed to cancel WiFi fallback: {}", err);
+    }
     match network_client::activate_ap() {
         Ok(_) => Flash::success(Redirect::to("/network"), "Activated WiFi access point"),
         Err(_) => Flash::error(
@@ -217,6 +838,10 @@ pub fn network_detail(ssid: &RawStr, flash: Option<FlashMessage>) -> Template {
     // decode ssid from url
     let decoded_ssid = percent_decode(ssid.as_bytes()).decode_utf8().unwrap();
     context.selected = Some(decoded_ssid.to_string());
+    // qr code for sharing the network (only available for saved networks)
+    context.qr_code = qr::saved_network_svg(&decoded_ssid).ok();
+    // signal strength recorded while connected to the network
+    context.rssi_history = signal::history_sparkline(&decoded_ssid);
     // check to see if there is a flash message to display
     if let Some(flash) = flash {
         // add flash message contents to the context object
@@ -271,6 +896,25 @@ pub fn network_add_ssid(ssid: &RawStr, flash: Option<FlashMessage>) -> Template
     Template::render("network_add", &context)
 }
 
+#[post("/network/wifi/add/uri", data = "<qr>")]
+pub fn network_add_uri(qr: Form<WifiUri>) -> Template {
+    let mut context = NetworkAddContext::build();
+    context.back = Some("/network".to_string());
+    context.title = Some("Add WiFi Network".to_string());
+    // prefill the form with the credentials read from the qr code
+    match qr::parse_wifi_uri(&qr.uri) {
+        Ok(wifi) => {
+            context.selected = Some(wifi.ssid.clone());
+            context.wifi = Some(wifi);
+        }
+        Err(err) => {
+            context.flash_name = Some("error".to_string());
+            context.flash_msg = Some(format!("Invalid WiFi QR code: {}", err));
+        }
+    }
+    Template::render("network_add", &context)
+}
+
 #[post("/network/wifi/add", data = "<wifi>")]
 pub fn add_credentials(wifi: Form<WiFi>) -> Template {
     // check if the credentials already exist for this access point
@@ -290,7 +934,7 @@ pub fn add_credentials(wifi: Form<WiFi>) -> Template {
     };
 
     // if credentials not found, generate and write wifi config to wpa_supplicant
-    match network_client::add(&wifi.ssid, &wifi.pass) {
+    match wifi::add(&wifi) {
         Ok(_) => {
             debug!("Added WiFi credentials.");
             // force reread of wpa_supplicant.conf file with new credentials
@@ -305,12 +949,12 @@ pub fn add_credentials(wifi: Form<WiFi>) -> Template {
             context.title = Some("Add WiFi Network".to_string());
             Template::render("network_add", &context)
         }
-        Err(_) => {
-            debug!("Failed to add WiFi credentials.");
+        Err(err) => {
+            debug!("Failed to add WiFi credentials: {}", err);
             let mut context = NetworkAddContext::build();
             context.back = Some("/network".to_string());
             context.flash_name = Some("error".to_string());
-            context.flash_msg = Some("Failed to add WiFi credentials".to_string());
+            context.flash_msg = Some(format!("Failed to add WiFi credentials: {}", err));
             context.title = Some("Add WiFi Network".to_string());
             Template::render("network_add", &context)
         }
@@ -353,6 +997,187 @@ pub fn wifi_usage_alerts(thresholds: Form<Threshold>) -> Flash<Redirect> {
     }
 }
 
+#[post("/network/fallback", data = "<settings>")]
+pub fn fallback_settings(settings: Form<Settings>) -> Flash<Redirect> {
+    let url = uri!(network_home);
+    match fallback::update_settings(settings.into_inner()) {
+        Ok(_) => {
+            debug!("WiFi fallback settings updated.");
+            Flash::success(Redirect::to(url), "Updated fallback settings")
+        }
+        Err(err) => {
+            warn!("Failed to update WiFi fallback settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update fallback settings: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/network/firewall")]
+pub fn firewall_settings(flash: Option<FlashMessage>, reverter: State<Reverter>) -> Template {
+    let mut context = FirewallContext::build(&reverter);
+    context.back = Some("/network".to_string());
+    context.title = Some("Firewall".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_firewall", &context)
+}
+
+#[post("/network/firewall", data = "<config>")]
+pub fn firewall_settings_post(
+    config: Form<FirewallConfig>,
+    reverter: State<Reverter>,
+) -> Flash<Redirect> {
+    let url = uri!(firewall_settings);
+    match firewall::apply(config.into_inner(), &reverter) {
+        Ok(_) => {
+            debug!("Applied firewall rules.");
+            let msg = format!(
+                "Applied firewall rules: confirm within {} seconds or they will be reverted",
+                Reverter::timeout()
+            );
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to apply firewall rules: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to apply firewall rules: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/firewall/confirm")]
+pub fn firewall_settings_confirm(reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(firewall_settings);
+    if reverter.confirm("firewall") {
+        Flash::success(Redirect::to(url), "Confirmed firewall rules")
+    } else {
+        Flash::error(
+            Redirect::to(url),
+            "No unconfirmed firewall rules (they may already have been reverted)",
+        )
+    }
+}
+
+#[post("/network/firewall/revert")]
+pub fn firewall_settings_revert(reverter: State<Reverter>) -> Flash<Redirect> {
+    let url = uri!(firewall_settings);
+    match reverter.revert("firewall") {
+        Ok(_) => Flash::success(Redirect::to(url), "Reverted firewall rules"),
+        Err(err) => {
+            warn!("Failed to revert firewall rules: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to revert firewall rules: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/wifi/usage/cycle", data = "<cycle>")]
+pub fn wifi_usage_cycle(cycle: Form<Cycle>) -> Flash<Redirect> {
+    let url = uri!(wifi_usage);
+    match monitor::update_cycle(cycle.into_inner()) {
+        Ok(_) => {
+            debug!("Data usage billing cycle updated.");
+            Flash::success(Redirect::to(url), "Updated billing cycle")
+        }
+        Err(err) => {
+            warn!("Failed to update data usage billing cycle: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update billing cycle: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/network/wired?<iface>")]
+pub fn wired_detail(iface: &RawStr, flash: Option<FlashMessage>) -> Template {
+    let mut context = WiredContext::build(iface.as_str());
+    context.back = Some("/network".to_string());
+    context.title = Some("Wired Network".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_wired", &context)
+}
+
+#[get("/network/yggdrasil")]
+pub fn yggdrasil_settings(flash: Option<FlashMessage>) -> Template {
+    let mut context = YggdrasilContext::build();
+    context.back = Some("/network".to_string());
+    context.title = Some("Yggdrasil".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_yggdrasil", &context)
+}
+
+#[post("/network/yggdrasil", data = "<settings>")]
+pub fn yggdrasil_settings_post(settings: Form<yggdrasil::Settings>) -> Flash<Redirect> {
+    let url = uri!(yggdrasil_settings);
+    let enabled = settings.enabled;
+    match yggdrasil::update_settings(settings.into_inner()) {
+        Ok(_) if enabled => Flash::success(Redirect::to(url), "Enabled Yggdrasil"),
+        Ok(_) => Flash::success(Redirect::to(url), "Disabled Yggdrasil"),
+        Err(err) => {
+            warn!("Failed to update Yggdrasil settings: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update Yggdrasil settings: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/yggdrasil/add", data = "<peer>")]
+pub fn yggdrasil_add_peer(peer: Form<PeerUri>) -> Flash<Redirect> {
+    let url = uri!(yggdrasil_settings);
+    match yggdrasil::add_peer(&peer.uri) {
+        Ok(_) => {
+            debug!("Added Yggdrasil peer {}.", peer.uri);
+            Flash::success(Redirect::to(url), "Added peer")
+        }
+        Err(err) => {
+            warn!("Failed to add Yggdrasil peer: {}", err);
+            Flash::error(Redirect::to(url), format!("Failed to add peer: {}", err))
+        }
+    }
+}
+
+#[post("/network/yggdrasil/remove", data = "<peer>")]
+pub fn yggdrasil_remove_peer(peer: Form<PeerUri>) -> Flash<Redirect> {
+    let url = uri!(yggdrasil_settings);
+    match yggdrasil::remove_peer(&peer.uri) {
+        Ok(_) => {
+            debug!("Removed Yggdrasil peer {}.", peer.uri);
+            Flash::success(Redirect::to(url), "Removed peer")
+        }
+        Err(err) => {
+            warn!("Failed to remove Yggdrasil peer: {}", err);
+            Flash::error(Redirect::to(url), format!("Failed to remove peer: {}", err))
+        }
+    }
+}
+
 #[get("/network/dns")]
 pub fn configure_dns(flash: Option<FlashMessage>) -> Template {
     let mut context = ConfigureDNSContext::build();
@@ -393,6 +1218,162 @@ pub fn configure_dns_post(dns: Form<DnsForm>) -> Template {
     }
 }
 
+#[post("/network/dns/certificate")]
+pub fn renew_certificate() -> Flash<Redirect> {
+    let url = uri!(configure_dns);
+    match tls::update(true) {
+        Ok(_) => {
+            debug!("Certificate obtained.");
+            Flash::success(Redirect::to(url), "Certificate obtained")
+        }
+        Err(err) => {
+            warn!("Failed to obtain certificate: {}", err);
+            Flash::error(Redirect::to(url), format!("{}", err))
+        }
+    }
+}
+
+#[get("/network/dns/status")]
+pub fn dns_status(flash: Option<FlashMessage>) -> Template {
+    let mut context = DnsStatusContext::build();
+    context.back = Some("/network/dns".to_string());
+    context.title = Some("Dynamic DNS Status".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("dns_status", &context)
+}
+
+#[post("/network/dns/update")]
+pub fn dns_update_post() -> Flash<Redirect> {
+    let url = uri!(dns_status);
+    match dyndns::force_update() {
+        Ok(_) => {
+            debug!("Dynamic DNS updated.");
+            Flash::success(Redirect::to(url), "Dynamic DNS updated")
+        }
+        Err(err) => {
+            warn!("Failed to update dynamic DNS: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update dynamic DNS: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/settings/hostname")]
+pub fn hostname_settings(flash: Option<FlashMessage>) -> Template {
+    let mut context = HostnameContext::build();
+    context.back = Some("/network".to_string());
+    context.title = Some("Hostname".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("hostname", &context)
+}
+
+#[post("/settings/hostname", data = "<hostname_form>")]
+pub fn hostname_settings_post(hostname_form: Form<HostnameForm>) -> Flash<Redirect> {
+    let url = uri!(hostname_settings);
+    match hostname::set(&hostname_form.hostname) {
+        Ok(_) => {
+            debug!("Hostname updated.");
+            let msg = format!(
+                "Hostname updated: the device is now reachable at {}",
+                hostname::mdns_name()
+            );
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to set hostname: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to set hostname: {}", err),
+            )
+        }
+    }
+}
+
+#[get("/settings/datetime")]
+pub fn datetime_settings(flash: Option<FlashMessage>) -> Template {
+    let mut context = DateTimeContext::build();
+    context.back = Some("/network".to_string());
+    context.title = Some("Date & Time".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("datetime", &context)
+}
+
+#[post("/settings/datetime/timezone", data = "<timezone_form>")]
+pub fn datetime_timezone_post(timezone_form: Form<TimezoneForm>) -> Flash<Redirect> {
+    let url = uri!(datetime_settings);
+    match datetime::set_timezone(&timezone_form.timezone) {
+        Ok(_) => {
+            debug!("Timezone updated.");
+            Flash::success(Redirect::to(url), "Timezone updated")
+        }
+        Err(err) => {
+            warn!("Failed to set timezone: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to set timezone: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/settings/datetime/ntp", data = "<ntp_form>")]
+pub fn datetime_ntp_post(ntp_form: Form<NtpForm>) -> Flash<Redirect> {
+    let url = uri!(datetime_settings);
+    match datetime::set_ntp(ntp_form.enabled) {
+        Ok(_) => {
+            debug!("NTP setting updated.");
+            let msg = if ntp_form.enabled {
+                "Time synchronisation enabled"
+            } else {
+                "Time synchronisation disabled"
+            };
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to update NTP setting: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update time synchronisation: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/settings/datetime/clock", data = "<clock_form>")]
+pub fn datetime_clock_post(clock_form: Form<ClockForm>) -> Flash<Redirect> {
+    let url = uri!(datetime_settings);
+    match datetime::set_clock(clock_form.timestamp) {
+        Ok(_) => {
+            debug!("Clock set.");
+            Flash::success(Redirect::to(url), "Clock set")
+        }
+        Err(err) => {
+            warn!("Failed to set clock: {}", err);
+            Flash::error(Redirect::to(url), format!("Failed to set clock: {}", err))
+        }
+    }
+}
+
 /// this change password route is used by a user who is already logged in
 #[get("/settings/change_password")]
 pub fn change_password(flash: Option<FlashMessage>) -> Template {
@@ -623,6 +1604,59 @@ pub fn forget_wifi(network: Form<Ssid>) -> Flash<Redirect> {
     }
 }
 
+#[get("/network/wifi/priority")]
+pub fn wifi_priority(flash: Option<FlashMessage>) -> Template {
+    let mut context = NetworkPriorityContext::build();
+    context.back = Some("/network/wifi".to_string());
+    context.title = Some("WiFi Network Priority".to_string());
+    // check to see if there is a flash message to display
+    if let Some(flash) = flash {
+        // add flash message contents to the context object
+        context.flash_name = Some(flash.name().to_string());
+        context.flash_msg = Some(flash.msg().to_string());
+    };
+    // template_dir is set in Rocket.toml
+    Template::render("network_priority", &context)
+}
+
+#[post("/network/wifi/priority", data = "<network>")]
+pub fn wifi_priority_move(network: Form<NetworkMove>) -> Flash<Redirect> {
+    let url = uri!(wifi_priority);
+    let up = network.direction == "up";
+    match wifi::move_network(&network.ssid, up) {
+        Ok(_) => Flash::success(Redirect::to(url), "Updated network priority"),
+        Err(err) => {
+            warn!("Failed to update network priority: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update network priority: {}", err),
+            )
+        }
+    }
+}
+
+#[post("/network/wifi/autoconnect", data = "<network>")]
+pub fn wifi_autoconnect(network: Form<AutoConnect>) -> Flash<Redirect> {
+    let url = uri!(wifi_priority);
+    match wifi::set_auto_connect(&network.ssid, network.enabled) {
+        Ok(_) => {
+            let msg = if network.enabled {
+                "Enabled auto-connect"
+            } else {
+                "Disabled auto-connect"
+            };
+            Flash::success(Redirect::to(url), msg)
+        }
+        Err(err) => {
+            warn!("Failed to update network auto-connect: {}", err);
+            Flash::error(
+                Redirect::to(url),
+                format!("Failed to update auto-connect: {}", err),
+            )
+        }
+    }
+}
+
 #[get("/network/wifi/modify?<ssid>")]
 pub fn wifi_password(ssid: &RawStr, flash: Option<FlashMessage>) -> Template {
     // decode ssid from url
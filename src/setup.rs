@@ -0,0 +1,164 @@
+//! First-run setup state and wizard steps.
+//!
+//! A new device is set up once the owner has completed the first-run setup
+//! wizard, which is recorded in the data store. Devices which were configured
+//! before first-run setup existed (ie. with an admin password or SSB admin in
+//! `peach_config` when the store was upgraded) are treated as set up.
+//!
+//! The wizard walks through WiFi client credentials, the hostname, (optional)
+//! dynamic DNS and the admin password, along with an (optional) admin SSB ID.
+//! Every step except the password can be skipped. The wizard forms are not
+//! authenticated, so they can only be used until the device has an admin
+//! password or SSB admin, which is why the password comes last; setup can only
+//! be completed after that.
+//!
+//! While the access point is active and setup is incomplete, peach-web acts
+//! as a captive portal: the connectivity probes of phones and laptops are
+//! redirected to the first-run page, so the setup UI opens automatically
+//! when joining the access point. The probe hosts are only resolved to the
+//! access point (by dnsmasq) until setup is complete; afterwards they resolve
+//! normally, so that devices on the access point can tell whether it has
+//! internet access.
+
+use std::fs;
+
+use log::{info, warn};
+use nest::Value;
+
+use peach_lib::config_manager::{load_peach_config, PeachConfig};
+use peach_lib::network_client;
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::store::DataStore;
+
+/// Path of the setup-complete flag within the data store
+const COMPLETE_PATH: [&str; 2] = ["setup", "complete"];
+/// Path of the dnsmasq configuration resolving the probe hosts to the access
+/// point
+const CAPTIVE_DNS_CONF: &str = "/etc/dnsmasq.d/peach-captive-portal.conf";
+/// Hosts of the connectivity probes of common operating systems
+const PROBE_HOSTS: [&str; 6] = [
+    "connectivitycheck.gstatic.com",
+    "clients3.google.com",
+    "captive.apple.com",
+    "www.msftconnecttest.com",
+    "www.msftncsi.com",
+    "detectportal.firefox.com",
+];
+
+/// Steps of the setup wizard, in order
+pub const STEPS: [&str; 4] = ["wifi", "hostname", "dns", "password"];
+
+/// Whether `peach_config` has an admin password or SSB admin
+pub fn is_configured(config: &PeachConfig) -> bool {
+    !config.admin_password_hash.is_empty() || !config.ssb_admin_ids.is_empty()
+}
+
+/// Whether first-run setup is complete, according to the given data store
+pub fn is_complete_in(store: &DataStore) -> Result<bool, PeachWebError> {
+    store.get_bool(&COMPLETE_PATH)
+}
+
+/// The step following the given one ("finish" after the last step), or
+/// `None` if the step is unknown
+pub fn next_step(step: &str) -> Option<&'static str> {
+    let index = STEPS.iter().position(|s| *s == step)?;
+    Some(STEPS.get(index + 1).copied().unwrap_or("finish"))
+}
+
+/// Whether `peach_config` currently has an admin password or SSB admin (also
+/// used to seed the setup state when the data store is upgraded, for devices
+/// configured before first-run setup existed)
+pub fn is_configured_now() -> bool {
+    load_peach_config().map_or(false, |config| is_configured(&config))
+}
+
+/// Whether first-run setup is complete. Setup is treated as incomplete if
+/// its state cannot be read, which keeps the captive portal up; the wizard
+/// forms stay closed as long as `peach_config` has an admin password or SSB
+/// admin (see `is_needed`).
+pub fn is_complete() -> bool {
+    match DataStore::open().and_then(|store| is_complete_in(&store)) {
+        Ok(complete) => complete,
+        Err(e) => {
+            warn!("Failed to read the setup state: {}", e);
+            false
+        }
+    }
+}
+
+/// Whether visitors to the home page should be sent to the setup wizard, and
+/// whether the wizard forms may be used: setup is incomplete and
+/// `peach_config` has neither an admin password nor an SSB admin
+pub fn is_needed() -> bool {
+    !is_complete() && load_peach_config().map_or(false, |config| !is_configured(&config))
+}
+
+/// Record the completion of first-run setup in the given data store
+pub fn mark_complete_in(store: &DataStore) -> Result<(), PeachWebError> {
+    store.set_values(&["setup"], vec![("complete", Value::Bool(true))])
+}
+
+/// Record the completion of first-run setup, and stop resolving the probe
+/// hosts to the access point
+pub fn mark_complete() -> Result<(), PeachWebError> {
+    info!("First-run setup complete");
+    mark_complete_in(&DataStore::open()?)?;
+    update_captive_dns();
+
+    Ok(())
+}
+
+/// Contents of the dnsmasq configuration for the probe hosts: while the
+/// captive portal applies, they are resolved to the access point address
+pub fn captive_dns_conf(captive: bool) -> String {
+    let mut conf = String::from("# managed by peach-web\n");
+    if captive {
+        conf.push_str("# resolve the captive-portal probe hosts to the access point until\n");
+        conf.push_str("# first-run setup is complete\n");
+        for host in PROBE_HOSTS.iter() {
+            conf.push_str(&format!("interface-name={},ap0\n", host));
+        }
+    }
+    conf
+}
+
+// write the dnsmasq configuration for the probe hosts, restarting dnsmasq if
+// it changed
+fn write_captive_dns(captive: bool) -> Result<(), PeachWebError> {
+    let conf = captive_dns_conf(captive);
+    if fs::read_to_string(CAPTIVE_DNS_CONF).map_or(false, |current| current == conf) {
+        return Ok(());
+    }
+    device::write_file(CAPTIVE_DNS_CONF, &conf)?;
+    device::run_privileged("systemctl", &["try-restart", "dnsmasq"])?;
+
+    Ok(())
+}
+
+/// Resolve the probe hosts to the access point while setup is incomplete,
+/// and normally once it is complete (called at startup and when setup is
+/// completed)
+pub fn update_captive_dns() {
+    let captive = !is_complete();
+    if let Err(e) = write_captive_dns(captive) {
+        warn!("Failed to update the captive-portal DNS: {}", e);
+    }
+}
+
+/// Whether connectivity probes should be redirected to the first-run page:
+/// the access point is active and setup is incomplete
+pub fn captive_portal_active() -> bool {
+    let ap_up = network_client::state("ap0").map_or(false, |state| state == "up");
+    ap_up && !is_complete()
+}
+
+/// URL of the first-run page, using the access point address so that it can
+/// be opened by devices which do not resolve `peach.local`
+pub fn setup_url() -> String {
+    match network_client::ip("ap0") {
+        Ok(ip) => format!("http://{}/setup", ip),
+        Err(_) => "/setup".to_string(),
+    }
+}
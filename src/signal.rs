@@ -0,0 +1,318 @@
+//! WiFi signal strength history and site survey.
+//!
+//! The signal strength (RSSI) of the WiFi client connection is sampled by the
+//! data usage monitor and kept in the data store, so that changes over time
+//! can be shown as a sparkline on the network detail page.
+//!
+//! A site survey scans repeatedly for access points and tabulates the
+//! channel, band, security and minimum / average / maximum signal of every
+//! access point seen, to help with choosing where to mount the device. It
+//! runs in a background thread; its progress is polled, as for diagnostics.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::{thread, time::Duration};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+
+use peach_lib::network_client;
+
+use crate::error::PeachWebError;
+use crate::store::DataStore;
+use crate::wifi;
+use crate::wifi::ScanResult;
+
+/// Number of RSSI samples to keep (one day at the default monitor interval)
+const RSSI_HISTORY_LEN: usize = 24 * 60;
+/// Number of the most recent samples shown in a sparkline
+const SPARKLINE_LEN: usize = 120;
+/// Signal strength range of the sparkline (dBm)
+const SPARKLINE_MIN: i32 = -100;
+const SPARKLINE_MAX: i32 = -30;
+/// Path of the RSSI samples within the data store
+const SAMPLES_PATH: [&str; 3] = ["history", "rssi", "samples"];
+
+/// Convert a signal strength in dBm to a quality percentage: -100 dBm or
+/// weaker is 0% and -50 dBm or stronger is 100%
+pub fn quality(dbm: i32) -> u8 {
+    (2 * (dbm + 100)).max(0).min(100) as u8
+}
+
+/// Signal quality (%) of the WiFi client connection, if connected
+pub fn current_quality() -> Option<u8> {
+    network_client::rssi("wlan0")
+        .ok()
+        .and_then(|rssi| rssi.trim().parse::<i32>().ok())
+        .map(quality)
+}
+
+/// Channel number of a frequency (MHz)
+pub fn channel(frequency: u32) -> Option<u32> {
+    match frequency {
+        2484 => Some(14),
+        2412..=2472 => Some((frequency - 2407) / 5),
+        5160..=5885 => Some((frequency - 5000) / 5),
+        5955..=7115 => Some((frequency - 5950) / 5),
+        _ => None,
+    }
+}
+
+/// Band of a frequency (MHz)
+pub fn band(frequency: u32) -> &'static str {
+    match frequency {
+        0..=2999 => "2.4 GHz",
+        3000..=5924 => "5 GHz",
+        _ => "6 GHz",
+    }
+}
+
+/// Signal strength sample of the WiFi client connection
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RssiSample {
+    pub timestamp: i64,
+    pub ssid: String,
+    pub rssi: i32, // dBm
+}
+
+/// Add a sample to the RSSI history in the given store, dropping the oldest
+/// samples beyond the history length
+pub fn record_in(store: &DataStore, sample: RssiSample) -> Result<(), PeachWebError> {
+    store.update(&["history", "rssi"], |values| {
+        let mut samples: Vec<RssiSample> = values.get_list("samples")?;
+        samples.push(sample);
+        let excess = samples.len().saturating_sub(RSSI_HISTORY_LEN);
+        values.set_list("samples", &samples[excess..])
+    })
+}
+
+/// Record the signal strength of the WiFi client connection (if connected)
+pub fn record(store: &DataStore) -> Result<(), PeachWebError> {
+    let rssi = network_client::rssi("wlan0")
+        .ok()
+        .and_then(|rssi| rssi.trim().parse::<i32>().ok());
+    let ssid = network_client::ssid("wlan0").ok();
+    if let (Some(rssi), Some(ssid)) = (rssi, ssid) {
+        let sample = RssiSample {
+            timestamp: Utc::now().timestamp(),
+            ssid,
+            rssi,
+        };
+        record_in(store, sample)?;
+    }
+
+    Ok(())
+}
+
+/// Retrieve the recorded RSSI samples (oldest first), optionally only those
+/// of the given network
+pub fn get_history(ssid: Option<&str>) -> Result<Vec<RssiSample>, PeachWebError> {
+    let store = DataStore::open()?;
+    let samples: Vec<RssiSample> = store.get_list(&SAMPLES_PATH)?;
+
+    Ok(samples
+        .into_iter()
+        .filter(|sample| ssid.map_or(true, |ssid| sample.ssid == ssid))
+        .collect())
+}
+
+/// Render signal strengths (dBm) as an SVG sparkline. At least two values
+/// are needed to draw a line.
+pub fn sparkline(values: &[i32]) -> Option<String> {
+    let (width, height) = (240, 48);
+    if values.len() < 2 {
+        return None;
+    }
+    let range = (SPARKLINE_MAX - SPARKLINE_MIN) as f64;
+    let step = width as f64 / (values.len() - 1) as f64;
+    let points: Vec<String> = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let clamped = (*value).max(SPARKLINE_MIN).min(SPARKLINE_MAX);
+            let y = height as f64 * (1.0 - (clamped - SPARKLINE_MIN) as f64 / range);
+            format!("{:.1},{:.1}", i as f64 * step, y)
+        })
+        .collect();
+
+    Some(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\"><polyline fill=\"none\" stroke=\"#2c7be5\" stroke-width=\"2\" points=\"{p}\"/></svg>",
+        w = width,
+        h = height,
+        p = points.join(" ")
+    ))
+}
+
+/// Sparkline of the most recent signal strength samples of a network
+pub fn history_sparkline(ssid: &str) -> Option<String> {
+    let samples = get_history(Some(ssid)).ok()?;
+    let start = samples.len().saturating_sub(SPARKLINE_LEN);
+    let values: Vec<i32> = samples[start..].iter().map(|sample| sample.rssi).collect();
+    sparkline(&values)
+}
+
+/// Site survey options
+#[derive(Clone, Debug, Deserialize, Serialize, FromForm)]
+pub struct SurveyOptions {
+    pub rounds: u32,   // number of scans
+    pub interval: u64, // seconds between scans
+}
+
+impl Default for SurveyOptions {
+    fn default() -> SurveyOptions {
+        SurveyOptions {
+            rounds: 10,
+            interval: 5,
+        }
+    }
+}
+
+impl SurveyOptions {
+    /// Ensure the number of scans and the interval are within range
+    pub fn validate(&self) -> Result<(), PeachWebError> {
+        let msg = if self.rounds < 1 || self.rounds > 60 {
+            Some("Number of scans must be between 1 and 60")
+        } else if self.interval < 2 || self.interval > 60 {
+            Some("Interval between scans must be between 2 and 60 seconds")
+        } else {
+            None
+        };
+
+        match msg {
+            Some(msg) => Err(PeachWebError::InvalidSurvey {
+                msg: msg.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Signal statistics of an access point seen during a site survey
+#[derive(Clone, Debug, Serialize)]
+pub struct SurveyEntry {
+    pub bssid: String,
+    pub ssid: String,
+    pub frequency: u32,
+    pub channel: Option<u32>,
+    pub band: String,
+    pub security: String,
+    pub min: i32,    // dBm
+    pub avg: i32,    // dBm
+    pub max: i32,    // dBm
+    pub quality: u8, // average signal quality (%)
+    pub seen: u32,   // number of scans the access point was found in
+    #[serde(skip)]
+    total: i64,
+}
+
+/// Add the results of a scan to the survey entries (keyed by BSSID)
+pub fn aggregate(entries: &mut HashMap<String, SurveyEntry>, results: &[ScanResult]) {
+    for result in results {
+        let entry = entries
+            .entry(result.bssid.clone())
+            .or_insert_with(|| SurveyEntry {
+                bssid: result.bssid.clone(),
+                ssid: result.ssid.clone(),
+                frequency: result.frequency,
+                channel: channel(result.frequency),
+                band: band(result.frequency).to_string(),
+                security: wifi::security_label(&result.flags).to_string(),
+                min: result.signal,
+                avg: result.signal,
+                max: result.signal,
+                quality: quality(result.signal),
+                seen: 0,
+                total: 0,
+            });
+        entry.min = entry.min.min(result.signal);
+        entry.max = entry.max.max(result.signal);
+        entry.seen += 1;
+        entry.total += i64::from(result.signal);
+        entry.avg = (entry.total as f64 / f64::from(entry.seen)).round() as i32;
+        entry.quality = quality(entry.avg);
+    }
+}
+
+/// Progress and results of the latest site survey
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SurveyReport {
+    pub running: bool,
+    pub started: Option<i64>, // unix timestamp
+    pub rounds: u32,
+    pub completed: u32,
+    pub entries: Vec<SurveyEntry>, // strongest average signal first
+    pub error: Option<String>,
+}
+
+/// Shared site survey state (placed in managed state)
+#[derive(Clone, Default)]
+pub struct Survey {
+    report: Arc<Mutex<SurveyReport>>,
+}
+
+impl Survey {
+    // a panic while holding the lock leaves the report usable
+    fn lock(&self) -> MutexGuard<SurveyReport> {
+        self.report.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Progress and results of the latest survey
+    pub fn report(&self) -> SurveyReport {
+        self.lock().clone()
+    }
+
+    /// Start a site survey in a background thread. Only one survey may be in
+    /// progress at a time.
+    pub fn start(&self, options: SurveyOptions) -> Result<(), PeachWebError> {
+        options.validate()?;
+        {
+            let mut report = self.lock();
+            if report.running {
+                return Err(PeachWebError::SurveyRunning);
+            }
+            *report = SurveyReport {
+                running: true,
+                started: Some(Utc::now().timestamp()),
+                rounds: options.rounds,
+                ..SurveyReport::default()
+            };
+        }
+
+        info!("Running WiFi site survey");
+        let survey = self.clone();
+        thread::spawn(move || {
+            let mut entries = HashMap::new();
+            for round in 0..options.rounds {
+                if let Err(e) = wifi::scan() {
+                    warn!("Failed to request a WiFi scan: {}", e);
+                }
+                // give wpa_supplicant time to complete the scan
+                thread::sleep(Duration::from_secs(options.interval));
+                match wifi::scan_results() {
+                    Ok(results) => {
+                        debug!(
+                            "Site survey scan {}: {} access points",
+                            round + 1,
+                            results.len()
+                        );
+                        aggregate(&mut entries, &results);
+                    }
+                    Err(e) => {
+                        warn!("Failed to retrieve WiFi scan results: {}", e);
+                        survey.lock().error = Some(format!("Failed to scan: {}", e));
+                    }
+                }
+                let mut sorted: Vec<SurveyEntry> = entries.values().cloned().collect();
+                sorted.sort_by(|a, b| b.avg.cmp(&a.avg).then_with(|| a.ssid.cmp(&b.ssid)));
+                let mut report = survey.lock();
+                report.entries = sorted;
+                report.completed = round + 1;
+            }
+            survey.lock().running = false;
+        });
+
+        Ok(())
+    }
+}
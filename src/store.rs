@@ -0,0 +1,641 @@
+//! Typed access to the `peach-web` data store.
+//!
+//! Data usage totals, thresholds, billing cycles, resource alerts, the
+//! traffic, device and signal strength history, the first-run setup state,
+//! the WiFi fallback settings, the configured Yggdrasil peers, the state of
+//! the ACME certificate requests and the dynamic DNS history are kept in a
+//! `nest` store (JSON files in the `peachcloud` XDG data directory, or the
+//! directory given by the `PEACH_WEB_DATA_DIR` environment variable).
+//!
+//! Default values are written once, by the schema migrations, when the store
+//! is first opened. Reading a value which is missing or has the wrong type
+//! therefore returns a `PeachWebError` instead of silently falling back to a
+//! default.
+//!
+//! The store is written by the request handlers and several background tasks.
+//! Writes are serialised by a process-wide lock, since updating a value reads
+//! its file and writes it back, and each file is written to a temporary file
+//! which is then renamed over it (by `nest`), so that a reader never sees a
+//! partly written file. Changes which depend on the stored values (eg.
+//! appending to a list or incrementing a counter) are made with
+//! `DataStore::update`, which holds the lock from the read to the write.
+
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::{env, fs};
+
+use nest::{Store, Value};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::error::PeachWebError;
+use crate::setup;
+
+/// Current version of the data store schema
+pub const SCHEMA_VERSION: u64 = 12;
+
+/// Environment variable used to override the data store directory
+const DATA_DIR_VAR: &str = "PEACH_WEB_DATA_DIR";
+
+/// Schema migrations, in order. Migration `n` upgrades a store from version
+/// `n` to version `n + 1`.
+const MIGRATIONS: [fn(&DataStore) -> Result<(), PeachWebError>; SCHEMA_VERSION as usize] = [
+    add_defaults,
+    add_history,
+    add_interface_samples,
+    add_setup,
+    add_fallback,
+    add_rssi_history,
+    add_mesh,
+    add_tls,
+    add_dyndns,
+    add_dyndns_last_update,
+    add_dyndns_last_failed_run,
+    add_disabled_networks,
+];
+
+/// Path of the schema version within the store
+const VERSION_PATH: [&str; 2] = ["meta", "version"];
+
+/// Lock held while writing to the store (shared by all `DataStore` instances)
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+// a panic while holding the lock leaves the store usable
+fn write_lock() -> MutexGuard<'static, ()> {
+    WRITE_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Data store with typed getters and setters
+pub struct DataStore {
+    store: Store,
+    path: PathBuf,
+}
+
+impl DataStore {
+    /// Open the data store in the configured data directory, creating the
+    /// directory and migrating the schema if necessary
+    pub fn open() -> Result<DataStore, PeachWebError> {
+        let path = match env::var_os(DATA_DIR_VAR) {
+            Some(dir) => PathBuf::from(dir),
+            None => xdg::BaseDirectories::new()
+                .map_err(|source| PeachWebError::StoreXdg { source })?
+                .get_data_home()
+                .join("peachcloud"),
+        };
+
+        DataStore::open_at(&path)
+    }
+
+    /// Open the data store in the given directory, creating the directory and
+    /// migrating the schema if necessary
+    pub fn open_at(path: &Path) -> Result<DataStore, PeachWebError> {
+        fs::create_dir_all(path).map_err(|source| PeachWebError::StoreDir {
+            path: path.display().to_string(),
+            source,
+        })?;
+
+        let schema = json!({
+            "meta": "json",
+            "net": {
+                "traffic": "json",
+                "alert": "json",
+                "notify": "json",
+                "cycle": "json",
+                "fallback": "json",
+            },
+            "device": {
+                "rules": "json",
+                "alerts": "json",
+            },
+            "history": {
+                "traffic": "json",
+                "device": "json",
+                "rssi": "json",
+            },
+            "setup": "json",
+            "mesh": {
+                "yggdrasil": "json",
+            },
+            "tls": {
+                "acme": "json",
+            },
+            "dns": {
+                "dyndns": "json",
+            },
+        })
+        .try_into()?;
+
+        let store = DataStore {
+            store: Store::new(path.to_path_buf(), schema),
+            path: path.to_path_buf(),
+        };
+        store.migrate()?;
+
+        Ok(store)
+    }
+
+    /// Retrieve the schema version of the store. A store without a version
+    /// predates versioning (or is new) and is treated as version 0.
+    pub fn version(&self) -> Result<u64, PeachWebError> {
+        match self.get_u64(&VERSION_PATH) {
+            Ok(version) => Ok(version),
+            Err(PeachWebError::StoreValueInvalid { path, expected }) => {
+                Err(PeachWebError::StoreValueInvalid { path, expected })
+            }
+            Err(_) => Ok(0),
+        }
+    }
+
+    /// Apply any migrations required to bring the store up to the current
+    /// schema version
+    fn migrate(&self) -> Result<(), PeachWebError> {
+        let version = self.version()?;
+        if version > SCHEMA_VERSION {
+            return Err(PeachWebError::StoreVersion {
+                version,
+                supported: SCHEMA_VERSION,
+            });
+        }
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(self)?;
+            self.set(&VERSION_PATH, Value::Uint(index as u64 + 1))?;
+        }
+
+        Ok(())
+    }
+
+    // retrieve a raw value, treating null as missing
+    fn get(&self, path: &[&str]) -> Result<Value, PeachWebError> {
+        match self.store.get(path) {
+            Ok(Value::Null) => Err(PeachWebError::StoreValueMissing {
+                path: path.join("/"),
+            }),
+            Ok(value) => Ok(value),
+            Err(source) => Err(PeachWebError::StoreAccess {
+                path: path.join("/"),
+                source,
+            }),
+        }
+    }
+
+    // check whether a (non-null) value exists at the given path
+    fn contains(&self, path: &[&str]) -> bool {
+        self.get(path).is_ok()
+    }
+
+    // check whether the file at the given path has been written
+    fn has_file(&self, file: &[&str]) -> bool {
+        let (name, dirs) = match file.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        let dir = dirs
+            .iter()
+            .fold(self.path.clone(), |dir, name| dir.join(name));
+        fs::read_dir(dir).map_or(false, |entries| {
+            entries
+                .filter_map(Result::ok)
+                .any(|entry| entry.path().file_stem() == Some(OsStr::new(name)))
+        })
+    }
+
+    pub fn get_u64(&self, path: &[&str]) -> Result<u64, PeachWebError> {
+        to_u64(path, self.get(path)?)
+    }
+
+    pub fn get_i64(&self, path: &[&str]) -> Result<i64, PeachWebError> {
+        to_i64(path, self.get(path)?)
+    }
+
+    pub fn get_bool(&self, path: &[&str]) -> Result<bool, PeachWebError> {
+        to_bool(path, self.get(path)?)
+    }
+
+    pub fn get_string(&self, path: &[&str]) -> Result<String, PeachWebError> {
+        to_string(path, self.get(path)?)
+    }
+
+    /// Retrieve a list of records. Lists are stored as serialized JSON strings.
+    pub fn get_list<T: DeserializeOwned>(&self, path: &[&str]) -> Result<Vec<T>, PeachWebError> {
+        to_list(path, self.get(path)?)
+    }
+
+    /// Store a single value
+    pub fn set(&self, path: &[&str], value: Value) -> Result<(), PeachWebError> {
+        let _lock = write_lock();
+        self.write(path, value)
+    }
+
+    // store a value (the write lock must be held)
+    fn write(&self, path: &[&str], value: Value) -> Result<(), PeachWebError> {
+        self.store
+            .set(path, &value)
+            .map_err(|source| PeachWebError::StoreAccess {
+                path: path.join("/"),
+                source,
+            })
+    }
+
+    /// Store a list of records as a serialized JSON string
+    pub fn set_list<T: Serialize>(&self, path: &[&str], list: &[T]) -> Result<(), PeachWebError> {
+        self.set(path, from_list(path, list)?)
+    }
+
+    /// Store several values in the same file with a single write, so that
+    /// either all or none of the values are updated. Other values in the file
+    /// are preserved; if the file cannot be read, nothing is written.
+    pub fn set_values(
+        &self,
+        file: &[&str],
+        values: Vec<(&str, Value)>,
+    ) -> Result<(), PeachWebError> {
+        self.update(file, |stored| {
+            for (key, value) in values {
+                stored.set(key, value);
+            }
+            Ok(())
+        })
+    }
+
+    /// Read the values of a file, change them and write them back, holding
+    /// the write lock throughout so that no concurrent change is lost. The
+    /// file is only written if a value changed, and nothing is written if
+    /// `change` fails or the file cannot be read. `change` may read other
+    /// values of the store but must not write to it.
+    pub fn update<T, F>(&self, file: &[&str], change: F) -> Result<T, PeachWebError>
+    where
+        F: FnOnce(&mut Values) -> Result<T, PeachWebError>,
+    {
+        let _lock = write_lock();
+        let object = match self.store.get(file) {
+            Ok(Value::Object(object)) => Value::Object(object),
+            // the file has not been written yet
+            Ok(Value::Null) => Value::Object(Default::default()),
+            Err(_) if !self.has_file(file) => Value::Object(Default::default()),
+            Err(source) => {
+                return Err(PeachWebError::StoreAccess {
+                    path: file.join("/"),
+                    source,
+                })
+            }
+            Ok(_) => return Err(invalid(file, "an object")),
+        };
+        let mut values = Values {
+            file: file.iter().map(|name| name.to_string()).collect(),
+            object,
+            changed: false,
+        };
+        let result = change(&mut values)?;
+        if values.changed {
+            self.write(file, values.object)?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Values of a single file of the store, as read by `DataStore::update`
+pub struct Values {
+    file: Vec<String>,
+    object: Value,
+    changed: bool,
+}
+
+impl Values {
+    // path of a value within the store
+    fn path<'a>(&'a self, key: &'a str) -> Vec<&'a str> {
+        let mut path: Vec<&str> = self.file.iter().map(String::as_str).collect();
+        path.push(key);
+        path
+    }
+
+    // retrieve a raw value, treating null as missing
+    fn get(&self, key: &str) -> Result<Value, PeachWebError> {
+        let value = match &self.object {
+            Value::Object(object) => object.get(key).cloned(),
+            _ => None,
+        };
+        match value {
+            Some(Value::Null) | None => Err(PeachWebError::StoreValueMissing {
+                path: self.path(key).join("/"),
+            }),
+            Some(value) => Ok(value),
+        }
+    }
+
+    pub fn get_u64(&self, key: &str) -> Result<u64, PeachWebError> {
+        to_u64(&self.path(key), self.get(key)?)
+    }
+
+    pub fn get_i64(&self, key: &str) -> Result<i64, PeachWebError> {
+        to_i64(&self.path(key), self.get(key)?)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<bool, PeachWebError> {
+        to_bool(&self.path(key), self.get(key)?)
+    }
+
+    pub fn get_string(&self, key: &str) -> Result<String, PeachWebError> {
+        to_string(&self.path(key), self.get(key)?)
+    }
+
+    /// Retrieve a list of records
+    pub fn get_list<T: DeserializeOwned>(&self, key: &str) -> Result<Vec<T>, PeachWebError> {
+        to_list(&self.path(key), self.get(key)?)
+    }
+
+    /// Set a value, to be written once the update is done
+    pub fn set(&mut self, key: &str, value: Value) {
+        if let Value::Object(object) = &mut self.object {
+            if object.get(key) != Some(&value) {
+                object.insert(key.to_string(), value);
+                self.changed = true;
+            }
+        }
+    }
+
+    /// Set a list of records, to be written once the update is done
+    pub fn set_list<T: Serialize>(&mut self, key: &str, list: &[T]) -> Result<(), PeachWebError> {
+        let value = from_list(&self.path(key), list)?;
+        self.set(key, value);
+        Ok(())
+    }
+
+    /// Remove a value, to be written once the update is done
+    pub fn remove(&mut self, key: &str) {
+        if let Value::Object(object) = &mut self.object {
+            if object.shift_remove(key).is_some() {
+                self.changed = true;
+            }
+        }
+    }
+}
+
+fn to_u64(path: &[&str], value: Value) -> Result<u64, PeachWebError> {
+    match value {
+        Value::Uint(value) => Ok(value),
+        _ => Err(invalid(path, "an unsigned integer")),
+    }
+}
+
+fn to_i64(path: &[&str], value: Value) -> Result<i64, PeachWebError> {
+    match value {
+        Value::Int(value) => Ok(value),
+        // serialized non-negative integers are read back as unsigned
+        Value::Uint(value) => value.try_into().map_err(|_| invalid(path, "an integer")),
+        _ => Err(invalid(path, "an integer")),
+    }
+}
+
+fn to_bool(path: &[&str], value: Value) -> Result<bool, PeachWebError> {
+    match value {
+        Value::Bool(value) => Ok(value),
+        _ => Err(invalid(path, "a boolean")),
+    }
+}
+
+fn to_string(path: &[&str], value: Value) -> Result<String, PeachWebError> {
+    match value {
+        Value::String(value) => Ok(value),
+        _ => Err(invalid(path, "a string")),
+    }
+}
+
+// lists are stored as serialized JSON strings
+fn to_list<T: DeserializeOwned>(path: &[&str], value: Value) -> Result<Vec<T>, PeachWebError> {
+    let list = to_string(path, value)?;
+    serde_json::from_str(&list).map_err(|_| invalid(path, "a list of records"))
+}
+
+fn from_list<T: Serialize>(path: &[&str], list: &[T]) -> Result<Value, PeachWebError> {
+    serde_json::to_string(list)
+        .map(Value::String)
+        .map_err(|_| invalid(path, "a list of records"))
+}
+
+// build an error for a value which has the wrong type
+fn invalid(path: &[&str], expected: &str) -> PeachWebError {
+    PeachWebError::StoreValueInvalid {
+        path: path.join("/"),
+        expected: expected.to_string(),
+    }
+}
+
+/// Migration to version 1: write default values for anything which was not
+/// stored by earlier (unversioned) releases. Existing values are kept.
+fn add_defaults(store: &DataStore) -> Result<(), PeachWebError> {
+    let empty_list = || Value::String("[]".to_string());
+    let defaults = vec![
+        (
+            ["net", "traffic"],
+            vec![("total", Value::Uint(0)), ("sample", Value::Uint(0))],
+        ),
+        (
+            ["net", "notify"],
+            vec![
+                ("warn", Value::Uint(0)),
+                ("cut", Value::Uint(0)),
+                ("warn_flag", Value::Bool(false)),
+                ("cut_flag", Value::Bool(false)),
+                ("cycle_period", Value::String("none".to_string())),
+                ("cycle_day", Value::Uint(1)),
+                ("cycle_tz", Value::String("UTC".to_string())),
+            ],
+        ),
+        (
+            ["net", "alert"],
+            vec![
+                ("warn", Value::Bool(false)),
+                ("cut", Value::Bool(false)),
+                ("ssid", Value::String(String::new())),
+            ],
+        ),
+        (
+            ["net", "cycle"],
+            vec![("start", Value::Int(0)), ("history", empty_list())],
+        ),
+        (
+            ["device", "rules"],
+            vec![("list", empty_list()), ("next_id", Value::Uint(1))],
+        ),
+        (
+            ["device", "alerts"],
+            vec![("active", empty_list()), ("history", empty_list())],
+        ),
+    ];
+
+    for (file, values) in defaults {
+        let missing: Vec<(&str, Value)> = values
+            .into_iter()
+            .filter(|(key, _)| !store.contains(&[file[0], file[1], key]))
+            .collect();
+        if !missing.is_empty() {
+            store.set_values(&file, missing)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Migration to version 2: add empty traffic and device history lists
+fn add_history(store: &DataStore) -> Result<(), PeachWebError> {
+    for file in &[["history", "traffic"], ["history", "device"]] {
+        if !store.contains(&[file[0], file[1], "samples"]) {
+            store.set_values(file, vec![("samples", Value::String("[]".to_string()))])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Migration to version 3: add an empty list of per-interface traffic
+/// counter samples, which replaces the single (combined) counter sample. The
+/// counters are recorded, without being counted, at the next evaluation.
+fn add_interface_samples(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["net", "traffic", "interfaces"]) {
+        store.set_values(
+            &["net", "traffic"],
+            vec![("interfaces", Value::String("[]".to_string()))],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 4: add the first-run setup state. Devices which were
+/// configured by earlier releases do not need to run first-run setup.
+fn add_setup(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["setup", "complete"]) {
+        let complete = setup::is_configured_now();
+        store.set_values(&["setup"], vec![("complete", Value::Bool(complete))])?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 5: add the WiFi fallback settings (disabled, with a
+/// 5 minute grace period and a 15 minute retry interval) and watchdog state
+fn add_fallback(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["net", "fallback", "enabled"]) {
+        store.set_values(
+            &["net", "fallback"],
+            vec![
+                ("enabled", Value::Bool(false)),
+                ("grace", Value::Uint(5)),
+                ("retry", Value::Uint(15)),
+                ("active", Value::Bool(false)),
+                ("failing_since", Value::Int(0)),
+                ("last_retry", Value::Int(0)),
+                ("events", Value::String("[]".to_string())),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 6: add an empty signal strength history
+fn add_rssi_history(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["history", "rssi", "samples"]) {
+        store.set_values(
+            &["history", "rssi"],
+            vec![("samples", Value::String("[]".to_string()))],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 7: add an empty list of configured Yggdrasil peers
+fn add_mesh(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["mesh", "yggdrasil", "peers"]) {
+        store.set_values(
+            &["mesh", "yggdrasil"],
+            vec![("peers", Value::String("[]".to_string()))],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 8: add the state of the ACME certificate requests
+fn add_tls(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["tls", "acme", "last_attempt"]) {
+        store.set_values(
+            &["tls", "acme"],
+            vec![
+                ("domain", Value::String(String::new())),
+                ("last_attempt", Value::Int(0)),
+                ("last_success", Value::Int(0)),
+                ("last_error", Value::String(String::new())),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 9: add an empty dynamic DNS address and failure
+/// history
+fn add_dyndns(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["dns", "dyndns", "history"]) {
+        store.set_values(
+            &["dns", "dyndns"],
+            vec![
+                ("online", Value::Bool(false)),
+                ("history", Value::String("[]".to_string())),
+                ("failures", Value::String("[]".to_string())),
+            ],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 10: add the time of the last successful dynamic DNS
+/// update seen, so that the address is recorded after each update
+fn add_dyndns_last_update(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["dns", "dyndns", "last_update"]) {
+        store.set_values(&["dns", "dyndns"], vec![("last_update", Value::Int(0))])?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 11: add the exit time of the last failed run of the
+/// dynamic DNS updater recorded, so that each failed run is recorded once
+fn add_dyndns_last_failed_run(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["dns", "dyndns", "last_failed_run"]) {
+        store.set_values(
+            &["dns", "dyndns"],
+            vec![("last_failed_run", Value::String(String::new()))],
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Migration to version 12: replace the network disabled by the data cutoff
+/// with a list of disabled networks, since the cutoff disables each network
+/// connected while it applies
+fn add_disabled_networks(store: &DataStore) -> Result<(), PeachWebError> {
+    if !store.contains(&["net", "alert", "disabled"]) {
+        store.update(&["net", "alert"], |values| {
+            let ssid = values.get_string("ssid").unwrap_or_default();
+            let disabled: Vec<String> = if ssid.is_empty() {
+                Vec::new()
+            } else {
+                vec![ssid]
+            };
+            values.set_list("disabled", &disabled)?;
+            values.remove("ssid");
+            Ok(())
+        })?;
+    }
+
+    Ok(())
+}
@@ -1,18 +1,64 @@
+use std::collections::HashMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::{env, fs, process, thread};
 
+use chrono::{NaiveDate, TimeZone, Utc};
+use nest::Value;
+use regex::Regex;
 use rocket::http::{ContentType, Status};
 use rocket::local::Client;
 
-use super::rocket;
+use super::{interval, rocket};
+use crate::ap;
+use crate::ap::ApConfig;
+use crate::datetime;
+use crate::diagnostics;
+use crate::diagnostics::{CheckResult, Targets};
+use crate::dyndns;
+use crate::dyndns::{Failure, IpChange};
+use crate::error::PeachWebError;
+use crate::export::{csv_field, Format, Range};
+use crate::fallback;
+use crate::fallback::{Action, Mode};
+use crate::firewall;
+use crate::firewall::FirewallConfig;
+use crate::forms::WiFi;
+use crate::hostname;
+use crate::ipconfig;
+use crate::ipconfig::IpConfig;
 use crate::json_api::build_json_response;
+use crate::metrics::escape;
+use crate::monitor::{project, traffic_since, Cycle, InterfaceSample, Rule, TrafficSample};
+use crate::qr;
+use crate::setup;
+use crate::signal;
+use crate::signal::RssiSample;
+use crate::store::{DataStore, SCHEMA_VERSION};
+use crate::tls;
+use crate::tls::{AcmeState, CertInfo};
+use crate::wifi;
+use crate::wifi::Security;
+use crate::wired;
+use crate::yggdrasil;
+use crate::yggdrasil::{Admin, PeerStatus};
+
+// helper function to create a client whose routes use a temporary data
+// store, rather than the one of the user running the tests
+fn test_client() -> Client {
+    let dir = env::temp_dir().join(format!("peach-web-routes-{}", process::id()));
+    env::set_var("PEACH_WEB_DATA_DIR", dir);
+    Client::new(rocket()).expect("valid rocket instance")
+}
 
 // helper function to test correct retrieval and content of a file
 fn test_query_file<T>(path: &str, file: T, status: Status)
 where
     T: Into<Option<&'static str>>,
 {
-    let client = Client::new(rocket()).unwrap();
+    let client = test_client();
     let mut response = client.get(path).dispatch();
     assert_eq!(response.status(), status);
 
@@ -23,6 +69,13 @@ where
     }
 }
 
+// helper function to return an empty data store directory, unique to a test
+fn test_store_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("peach-web-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
 // helper function to return the content of a file, given a path
 fn read_file_content(path: &str) -> Vec<u8> {
     let mut fp = File::open(&path).expect(&format!("Can't open {}", path));
@@ -37,7 +90,7 @@ fn read_file_content(path: &str) -> Vec<u8> {
 
 #[test]
 fn index_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -52,7 +105,7 @@ fn index_html() {
 
 #[test]
 fn network_card_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/network").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -70,7 +123,7 @@ fn network_card_html() {
 
 #[test]
 fn network_list_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/network/wifi").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -82,7 +135,7 @@ fn network_list_html() {
 // TODO: needs further testing once template has been refactored
 #[test]
 fn network_detail_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client.get("/network/wifi?ssid=Home").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -92,7 +145,7 @@ fn network_detail_html() {
 
 #[test]
 fn network_add_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/network/wifi/add").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -106,7 +159,7 @@ fn network_add_html() {
 
 #[test]
 fn network_add_ssid_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/network/wifi/add?ssid=Home").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -120,7 +173,7 @@ fn network_add_ssid_html() {
 
 #[test]
 fn device_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/device").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -133,7 +186,7 @@ fn device_html() {
 
 #[test]
 fn help_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/help").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -143,7 +196,7 @@ fn help_html() {
 
 #[test]
 fn login_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/login").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -153,7 +206,7 @@ fn login_html() {
 
 #[test]
 fn messages_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/messages").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -163,7 +216,7 @@ fn messages_html() {
 
 #[test]
 fn peers_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/peers").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -173,7 +226,7 @@ fn peers_html() {
 
 #[test]
 fn profile_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/profile").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -183,7 +236,7 @@ fn profile_html() {
 
 #[test]
 fn shutdown_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/shutdown").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
@@ -191,22 +244,110 @@ fn shutdown_html() {
     assert!(body.contains("Shutdown Device"));
 }
 
+#[test]
+fn network_ap_html() {
+    let client = test_client();
+    let mut response = client.get("/network/ap").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Access Point Settings"));
+    assert!(body.contains("Reconnect"));
+}
+
+#[test]
+fn network_ip_html() {
+    let client = test_client();
+    let mut response = client.get("/network/ip?iface=wlan0").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("IP Settings"));
+    assert!(body.contains("Reconnect"));
+}
+
+#[test]
+fn wired_detail_unknown_interface() {
+    let client = test_client();
+    let mut response = client.get("/network/wired?iface=eth9").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Wired Network"));
+    assert!(body.contains("Wired interface not found."));
+}
+
+#[test]
+fn network_priority_html() {
+    let client = test_client();
+    let mut response = client.get("/network/wifi/priority").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("WiFi Network Priority"));
+    assert!(body.contains("Saved networks are unavailable."));
+}
+
 #[test]
 fn network_usage_html() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client.get("/network/wifi/usage").dispatch();
     assert_eq!(response.status(), Status::Ok);
     assert_eq!(response.content_type(), Some(ContentType::HTML));
     let body = response.body_string().unwrap();
     assert!(body.contains("Network Data Usage"));
     assert!(body.contains("WARNING THRESHOLD"));
+    assert!(body.contains("BILLING CYCLE"));
     assert!(body.contains("Update"));
     assert!(body.contains("Cancel"));
 }
 
+#[test]
+fn export_traffic_csv() {
+    let client = test_client();
+    let mut response = client
+        .get("/export/traffic?format=csv&from=2021-01-01&to=2021-01-31")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let disposition = response.headers().get_one("Content-Disposition");
+    assert_eq!(
+        disposition,
+        Some("attachment; filename=\"peachcloud-traffic-2021-01-01-to-2021-01-31.csv\"")
+    );
+    let body = response.body_string().unwrap();
+    assert!(body.starts_with("hour_utc,bytes\r\n"));
+}
+
+#[test]
+fn export_invalid_format() {
+    let client = test_client();
+    let response = client.get("/export/device?format=xls").dispatch();
+    assert_eq!(response.status(), Status::SeeOther);
+    assert_eq!(response.headers().get_one("Location"), Some("/device"));
+}
+
+#[test]
+fn prometheus_metrics() {
+    let client = test_client();
+    // make a request so there are request metrics to report
+    client.get("/help").dispatch();
+    let mut response = client.get("/metrics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::Plain));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("# TYPE peach_microservice_up gauge"));
+    assert!(body.contains("peach_microservice_up{service=\"peach-network\"} 0"));
+    assert!(body.contains(
+        "peach_web_http_requests_total{method=\"GET\",route=\"/help\",status=\"200\"} 1"
+    ));
+    assert!(body.contains(
+        "peach_web_http_request_duration_seconds_count{method=\"GET\",route=\"/help\"} 1"
+    ));
+}
+
 #[test]
 fn add_credentials() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client
         .post("/network/wifi/add")
         .header(ContentType::Form)
@@ -218,7 +359,7 @@ fn add_credentials() {
 
 #[test]
 fn forget_wifi() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client
         .post("/network/wifi/forget")
         .header(ContentType::Form)
@@ -230,7 +371,7 @@ fn forget_wifi() {
 
 #[test]
 fn modify_password() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client
         .post("/network/wifi/modify")
         .header(ContentType::Form)
@@ -242,7 +383,7 @@ fn modify_password() {
 
 #[test]
 fn deploy_ap() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client.get("/network/ap/activate").dispatch();
     // check for 303 status (redirect)
     assert_eq!(response.status(), Status::SeeOther);
@@ -251,7 +392,7 @@ fn deploy_ap() {
 
 #[test]
 fn deploy_client() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client.get("/network/wifi/activate").dispatch();
     // check for 303 status (redirect)
     assert_eq!(response.status(), Status::SeeOther);
@@ -262,7 +403,7 @@ fn deploy_client() {
 
 #[test]
 fn activate_ap() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client
         .post("/api/v1/network/activate_ap")
         .header(ContentType::JSON)
@@ -273,7 +414,7 @@ fn activate_ap() {
 
 #[test]
 fn activate_client() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let response = client
         .post("/api/v1/network/activate_client")
         .header(ContentType::JSON)
@@ -284,7 +425,7 @@ fn activate_client() {
 
 #[test]
 fn return_ip() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/ip")
         .header(ContentType::JSON)
@@ -298,7 +439,7 @@ fn return_ip() {
 
 #[test]
 fn return_rssi() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/rssi")
         .header(ContentType::JSON)
@@ -311,7 +452,7 @@ fn return_rssi() {
 
 #[test]
 fn return_ssid() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/ssid")
         .header(ContentType::JSON)
@@ -324,7 +465,7 @@ fn return_ssid() {
 
 #[test]
 fn return_state() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/state")
         .header(ContentType::JSON)
@@ -339,7 +480,7 @@ fn return_state() {
 
 #[test]
 fn return_status() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/status")
         .header(ContentType::JSON)
@@ -352,7 +493,7 @@ fn return_status() {
 
 #[test]
 fn scan_networks() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/network/wifi")
         .header(ContentType::JSON)
@@ -365,7 +506,7 @@ fn scan_networks() {
 
 #[test]
 fn add_wifi() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .post("/api/v1/network/wifi")
         .header(ContentType::JSON)
@@ -379,7 +520,7 @@ fn add_wifi() {
 
 #[test]
 fn remove_wifi() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .post("/api/v1/network/wifi/forget")
         .header(ContentType::JSON)
@@ -393,7 +534,7 @@ fn remove_wifi() {
 
 #[test]
 fn new_password() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .post("/api/v1/network/wifi/modify")
         .header(ContentType::JSON)
@@ -405,9 +546,263 @@ fn new_password() {
     assert!(body.contains("Failed to update WiFi password."));
 }
 
+#[test]
+fn return_alerts() {
+    let client = test_client();
+    let mut response = client
+        .get("/api/v1/alerts")
+        .header(ContentType::JSON)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("rules"));
+}
+
+#[test]
+fn add_invalid_alert_rule() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/alerts")
+        .header(ContentType::JSON)
+        .body(r#"{ "metric": "humidity", "comparator": "above", "threshold": 80, "duration": 60 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::JSON));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Unknown metric: humidity"));
+}
+
+#[test]
+fn update_invalid_ap_config() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/ap")
+        .header(ContentType::JSON)
+        .body(r#"{ "ssid": "peach", "pass": "short", "channel": 6, "country": "NZ" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("error"));
+    assert!(body.contains("Passphrase must be"));
+}
+
+#[test]
+fn confirm_ap_config_without_change() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/ap/confirm")
+        .header(ContentType::JSON)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("No unconfirmed access point settings."));
+}
+
+#[test]
+fn update_ip_config_unknown_interface() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/ip/config")
+        .header(ContentType::JSON)
+        .body(r#"{ "iface": "ap0", "method": "dhcp" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to apply IP settings: Unknown interface"));
+}
+
+#[test]
+fn kick_ap_client_invalid_mac() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/ap/clients/kick")
+        .header(ContentType::JSON)
+        .body(r#"{ "mac": "not-a-mac" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Invalid MAC address: not-a-mac"));
+}
+
+#[test]
+fn network_add_from_wifi_qr_code() {
+    let client = test_client();
+    let mut response = client
+        .post("/network/wifi/add/uri")
+        .header(ContentType::Form)
+        .body("uri=WIFI%3AT%3AWPA%3BS%3APeachCloud%3BP%3Aabundance%3B%3B")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("value=\"PeachCloud\""));
+    assert!(body.contains("value=\"abundance\""));
+
+    let mut response = client
+        .post("/network/wifi/add/uri")
+        .header(ContentType::Form)
+        .body("uri=PeachCloud")
+        .dispatch();
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Invalid WiFi QR code"));
+}
+
+#[test]
+fn network_diagnostics_html() {
+    let client = test_client();
+    let mut response = client.get("/network/diagnostics").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Run Diagnostics"));
+    assert!(body.contains("connectivitycheck.gstatic.com"));
+}
+
+#[test]
+fn run_diagnostics_invalid_target() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/diagnostics")
+        .header(ContentType::JSON)
+        .body(r#"{ "trace": "-f 1.1.1.1" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to run diagnostics: Invalid host: -f 1.1.1.1"));
+}
+
+#[test]
+fn wifi_survey_html() {
+    let client = test_client();
+    let mut response = client.get("/network/wifi/survey").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Run Survey"));
+}
+
+#[test]
+fn run_survey_invalid_options() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/wifi/survey")
+        .header(ContentType::JSON)
+        .body(r#"{ "rounds": 0, "interval": 5 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to run site survey: Number of scans must be between 1 and 60"));
+}
+
+#[test]
+fn hostname_html() {
+    let client = test_client();
+    let mut response = client.get("/settings/hostname").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    // the mdns name is shown in the form and used in the logout link
+    let mdns_name = hostname::mdns_name();
+    assert!(body.contains(&format!("Reachable at {}", mdns_name)));
+    assert!(body.contains(&format!("http://user:logout@{}/", mdns_name)));
+}
+
+#[test]
+fn datetime_html() {
+    let client = test_client();
+    let mut response = client.get("/settings/datetime").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("/js/datetime.js"));
+}
+
+#[test]
+fn update_clock_invalid_timestamp() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/settings/datetime/clock")
+        .header(ContentType::JSON)
+        .body(r#"{ "timestamp": 0 }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to set clock: Time must be between 2021 and 2100"));
+}
+
+#[test]
+fn firewall_html() {
+    let client = test_client();
+    let mut response = client.get("/network/firewall").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Access point only"));
+}
+
+#[test]
+fn update_firewall_invalid_access() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/firewall")
+        .header(ContentType::JSON)
+        .body(r#"{ "enabled": true, "ssh": "bogus0", "web": "ap", "sbot": "deny" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to apply firewall rules: Invalid access for SSH: bogus0"));
+}
+
+#[test]
+fn dns_status_html() {
+    let client = test_client();
+    let response = client.get("/network/dns/status").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+}
+
+#[test]
+fn yggdrasil_html() {
+    let client = test_client();
+    let mut response = client.get("/network/yggdrasil").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.content_type(), Some(ContentType::HTML));
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Yggdrasil connects this device"));
+}
+
+#[test]
+fn add_yggdrasil_peer_invalid_uri() {
+    let client = test_client();
+    let mut response = client
+        .post("/api/v1/network/yggdrasil/add")
+        .header(ContentType::JSON)
+        .body(r#"{ "uri": "http://peer.example.org:443" }"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.body_string().unwrap();
+    assert!(body.contains("Failed to add peer: Peer URI scheme must be"));
+}
+
+#[test]
+fn captive_portal_probes() {
+    // the access point is not active, so probes get the expected responses
+    let client = test_client();
+    let response = client.get("/generate_204").dispatch();
+    assert_eq!(response.status(), Status::NoContent);
+    let mut response = client.get("/hotspot-detect.html").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert!(response.body_string().unwrap().contains("Success"));
+    let mut response = client.get("/connecttest.txt").dispatch();
+    assert_eq!(
+        response.body_string(),
+        Some("Microsoft Connect Test".to_string())
+    );
+}
+
 #[test]
 fn ping_pong() {
-    let client = Client::new(rocket()).expect("valid rocket instance");
+    let client = test_client();
     let mut response = client
         .get("/api/v1/ping")
         .header(ContentType::JSON)
@@ -430,6 +825,1611 @@ fn test_build_json_response() {
     assert_eq!(json.msg, None);
 }
 
+#[test]
+fn test_monthly_cycle_bounds() {
+    let cycle = Cycle {
+        period: "monthly".to_string(),
+        day: 15,
+        timezone: "UTC".to_string(),
+    };
+    let now = Utc.ymd(2021, 3, 20).and_hms(12, 0, 0);
+    let start = Utc.ymd(2021, 3, 15).and_hms(0, 0, 0).timestamp();
+    let end = Utc.ymd(2021, 4, 15).and_hms(0, 0, 0).timestamp();
+    assert_eq!(cycle.bounds(now), Some((start, end)));
+
+    // before the start day, the cycle began in the previous month
+    let now = Utc.ymd(2021, 1, 2).and_hms(12, 0, 0);
+    let start = Utc.ymd(2020, 12, 15).and_hms(0, 0, 0).timestamp();
+    let end = Utc.ymd(2021, 1, 15).and_hms(0, 0, 0).timestamp();
+    assert_eq!(cycle.bounds(now), Some((start, end)));
+}
+
+#[test]
+fn test_monthly_cycle_bounds_short_month() {
+    let cycle = Cycle {
+        period: "monthly".to_string(),
+        day: 31,
+        timezone: "UTC".to_string(),
+    };
+    let now = Utc.ymd(2021, 3, 1).and_hms(12, 0, 0);
+    let start = Utc.ymd(2021, 2, 28).and_hms(0, 0, 0).timestamp();
+    let end = Utc.ymd(2021, 3, 31).and_hms(0, 0, 0).timestamp();
+    assert_eq!(cycle.bounds(now), Some((start, end)));
+}
+
+#[test]
+fn test_weekly_cycle_bounds_timezone() {
+    let cycle = Cycle {
+        period: "weekly".to_string(),
+        day: 1,
+        timezone: "Africa/Nairobi".to_string(),
+    };
+    // sunday 22:00 utc is monday 01:00 in nairobi (utc+3)
+    let now = Utc.ymd(2021, 6, 6).and_hms(22, 0, 0);
+    let start = Utc.ymd(2021, 6, 6).and_hms(21, 0, 0).timestamp();
+    let end = Utc.ymd(2021, 6, 13).and_hms(21, 0, 0).timestamp();
+    assert_eq!(cycle.bounds(now), Some((start, end)));
+}
+
+#[test]
+fn test_cycle_validation() {
+    let mut cycle = Cycle {
+        period: "none".to_string(),
+        day: 1,
+        timezone: "UTC".to_string(),
+    };
+    assert_eq!(cycle.bounds(Utc::now()), None);
+    assert!(cycle.validate().is_ok());
+    cycle.period = "weekly".to_string();
+    cycle.day = 8;
+    assert!(cycle.validate().is_err());
+    cycle.day = 7;
+    cycle.timezone = "Mars/Olympus_Mons".to_string();
+    assert!(cycle.validate().is_err());
+}
+
+#[test]
+fn test_usage_projection() {
+    // too early in the cycle to project
+    assert_eq!(project(1024, 60, 86_400), None);
+    // half way through the cycle
+    assert_eq!(project(1024, 43_200, 86_400), Some(2048));
+}
+
+#[test]
+fn test_traffic_since() {
+    let sample = |iface: &str, bytes: u64| InterfaceSample {
+        iface: iface.to_string(),
+        bytes,
+    };
+    let counter = |iface: &str, bytes: Option<u64>| (iface.to_string(), bytes);
+
+    // counters are only recorded until samples exist
+    let (bytes, samples) = traffic_since(&[], &[counter("wlan0", Some(500))]);
+    assert_eq!(bytes, 0);
+    assert_eq!(samples, vec![sample("wlan0", 500)]);
+
+    let samples = vec![sample("wlan0", 1000), sample("eth0", 2000)];
+    let (bytes, updated) = traffic_since(
+        &samples,
+        &[counter("wlan0", Some(1500)), counter("eth0", Some(2100))],
+    );
+    assert_eq!(bytes, 600);
+    assert_eq!(updated, vec![sample("wlan0", 1500), sample("eth0", 2100)]);
+
+    // an unreadable counter keeps its sample and a removed interface is
+    // dropped, without affecting the other interfaces
+    let (bytes, updated) = traffic_since(&samples, &[counter("wlan0", None)]);
+    assert_eq!(bytes, 0);
+    assert_eq!(updated, vec![sample("wlan0", 1000)]);
+    let (bytes, _) = traffic_since(&updated, &[counter("wlan0", Some(1200))]);
+    assert_eq!(bytes, 200);
+
+    // a reset counter and a new interface are counted in full
+    let (bytes, _) = traffic_since(
+        &samples,
+        &[
+            counter("wlan0", Some(300)),
+            counter("eth0", Some(2000)),
+            counter("eth1", Some(50)),
+        ],
+    );
+    assert_eq!(bytes, 350);
+}
+
+#[test]
+fn test_metrics_label_escape() {
+    assert_eq!(escape("/network/wifi"), "/network/wifi");
+    assert_eq!(escape("say \"hi\"\n"), "say \\\"hi\\\"\\n");
+}
+
+#[test]
+fn test_alert_rule() {
+    let rule = Rule {
+        id: 1,
+        metric: "temperature".to_string(),
+        comparator: "above".to_string(),
+        threshold: 70.0,
+        duration: 300,
+    };
+    assert!(rule.validate().is_ok());
+    assert!(rule.breached(75.5));
+    assert!(!rule.breached(70.0));
+
+    let rule = Rule {
+        comparator: "below".to_string(),
+        ..rule
+    };
+    assert!(rule.breached(20.0));
+    assert!(!rule.breached(80.0));
+
+    let rule = Rule {
+        comparator: "equals".to_string(),
+        ..rule
+    };
+    assert!(rule.validate().is_err());
+}
+
+#[test]
+fn test_store_defaults() {
+    let dir = test_store_dir("store-defaults");
+    let store = DataStore::open_at(&dir).unwrap();
+    assert_eq!(store.version().unwrap(), SCHEMA_VERSION);
+    assert_eq!(store.get_u64(&["net", "traffic", "total"]).unwrap(), 0);
+    let interfaces: Vec<InterfaceSample> =
+        store.get_list(&["net", "traffic", "interfaces"]).unwrap();
+    assert!(interfaces.is_empty());
+    assert!(!store.get_bool(&["net", "notify", "cut_flag"]).unwrap());
+    assert!(!store.get_bool(&["net", "fallback", "enabled"]).unwrap());
+    assert_eq!(
+        store
+            .get_string(&["net", "notify", "cycle_period"])
+            .unwrap(),
+        "none"
+    );
+    let rules: Vec<Rule> = store.get_list(&["device", "rules", "list"]).unwrap();
+    assert!(rules.is_empty());
+    let samples: Vec<TrafficSample> = store.get_list(&["history", "traffic", "samples"]).unwrap();
+    assert!(samples.is_empty());
+    let peers: Vec<String> = store.get_list(&["mesh", "yggdrasil", "peers"]).unwrap();
+    assert!(peers.is_empty());
+    assert_eq!(store.get_i64(&["tls", "acme", "last_attempt"]).unwrap(), 0);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_setup_state() {
+    let dir = test_store_dir("setup-state");
+    let store = DataStore::open_at(&dir).unwrap();
+    // a new store on an unconfigured device starts with setup incomplete
+    assert!(!setup::is_complete_in(&store).unwrap());
+    setup::mark_complete_in(&store).unwrap();
+    assert!(setup::is_complete_in(&store).unwrap());
+    // the flag is kept when the store is reopened
+    let store = DataStore::open_at(&dir).unwrap();
+    assert!(setup::is_complete_in(&store).unwrap());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_setup_state_unreadable() {
+    let dir = test_store_dir("setup-unreadable");
+    let store = DataStore::open_at(&dir).unwrap();
+    let file = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.file_stem().map_or(false, |stem| stem == "setup"))
+        .unwrap();
+    fs::write(&file, "{\"complete\": tr").unwrap();
+    // an unreadable flag is reported, rather than taken as complete
+    assert!(setup::is_complete_in(&store).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_fallback_decide() {
+    let settings = fallback::Settings {
+        enabled: true,
+        ..fallback::Settings::default()
+    };
+    let mut state = fallback::State::default();
+    let down = Mode::Client { connected: false };
+    let up = Mode::Client { connected: true };
+    // the outage starts with the first failed check
+    assert_eq!(
+        fallback::decide(&settings, &mut state, down, 1000),
+        Action::None
+    );
+    assert_eq!(state.failing_since, 1000);
+    // no fallback within the grace period (5 minutes)
+    assert_eq!(
+        fallback::decide(&settings, &mut state, down, 1299),
+        Action::None
+    );
+    assert_eq!(
+        fallback::decide(&settings, &mut state, down, 1300),
+        Action::ActivateAp
+    );
+    assert!(state.active);
+    // client mode is retried after the retry interval (15 minutes)
+    let ap = Mode::AccessPoint;
+    assert_eq!(
+        fallback::decide(&settings, &mut state, ap, 2199),
+        Action::None
+    );
+    assert_eq!(
+        fallback::decide(&settings, &mut state, ap, 2200),
+        Action::RetryClient
+    );
+    // a failed retry falls back again after the grace period
+    assert_eq!(
+        fallback::decide(&settings, &mut state, down, 2300),
+        Action::None
+    );
+    assert_eq!(
+        fallback::decide(&settings, &mut state, down, 2500),
+        Action::ActivateAp
+    );
+    assert_eq!(
+        fallback::decide(&settings, &mut state, ap, 3400),
+        Action::RetryClient
+    );
+    assert_eq!(
+        fallback::decide(&settings, &mut state, up, 3430),
+        Action::Restored
+    );
+    assert_eq!(state, fallback::State::default());
+    // an inconclusive check ends the outage
+    fallback::decide(&settings, &mut state, down, 4000);
+    assert_eq!(
+        fallback::decide(&settings, &mut state, Mode::Unknown, 4400),
+        Action::None
+    );
+    assert_eq!(state.failing_since, 0);
+    // an access point deployed by the user is left alone
+    assert_eq!(
+        fallback::decide(&settings, &mut state, ap, 9999),
+        Action::None
+    );
+    // nothing happens when the watchdog is disabled
+    let disabled = fallback::Settings::default();
+    state.failing_since = 1;
+    assert_eq!(
+        fallback::decide(&disabled, &mut state, down, 9999),
+        Action::None
+    );
+    assert_eq!(state, fallback::State::default());
+}
+
+#[test]
+fn test_fallback_settings_validate() {
+    assert!(fallback::Settings::default().validate().is_ok());
+    let settings = |grace, retry| fallback::Settings {
+        enabled: true,
+        grace,
+        retry,
+    };
+    assert!(settings(0, 15).validate().is_err());
+    assert!(settings(61, 15).validate().is_err());
+    assert!(settings(5, 4).validate().is_err());
+    assert!(settings(5, 1441).validate().is_err());
+    assert!(settings(60, 1440).validate().is_ok());
+}
+
+#[test]
+fn test_firewall_validate() {
+    assert!(FirewallConfig::default().validate().is_ok());
+    let config = |ssh: &str| FirewallConfig {
+        enabled: true,
+        ssh: ssh.to_string(),
+        web: "ap".to_string(),
+        sbot: "deny".to_string(),
+    };
+    assert!(config("everywhere").validate().is_ok());
+    assert!(config("wlan0").validate().is_ok());
+    assert!(config("ap0").validate().is_err());
+    assert!(config("eth0; drop").validate().is_err());
+}
+
+#[test]
+fn test_firewall_render() {
+    let config = FirewallConfig {
+        enabled: true,
+        ssh: "wlan0".to_string(),
+        web: "ap".to_string(),
+        sbot: "deny".to_string(),
+    };
+    let rules = firewall::render(&config);
+    assert!(rules.contains("policy drop;"));
+    assert!(rules.contains("        iifname \"wlan0\" tcp dport { 22 } accept\n"));
+    assert!(rules.contains("        iifname \"ap0\" tcp dport { 80, 443 } accept\n"));
+    assert!(!rules.contains("8008"));
+    // the settings are recovered from the rules file
+    assert_eq!(firewall::parse(&rules), Some(config));
+
+    // a disabled firewall only removes the table
+    let rules = firewall::render(&FirewallConfig::default());
+    assert!(rules.ends_with("table inet peach_web\ndelete table inet peach_web\n"));
+    assert_eq!(firewall::parse(&rules), Some(FirewallConfig::default()));
+    assert_eq!(firewall::parse("table inet filter {}"), None);
+}
+
+#[test]
+fn test_signal_quality() {
+    assert_eq!(signal::quality(-110), 0);
+    assert_eq!(signal::quality(-100), 0);
+    assert_eq!(signal::quality(-75), 50);
+    assert_eq!(signal::quality(-50), 100);
+    // strong signals no longer exceed 100%
+    assert_eq!(signal::quality(-30), 100);
+}
+
+#[test]
+fn test_signal_channel_and_band() {
+    assert_eq!(signal::channel(2412), Some(1));
+    assert_eq!(signal::channel(2437), Some(6));
+    assert_eq!(signal::channel(2484), Some(14));
+    assert_eq!(signal::channel(5180), Some(36));
+    assert_eq!(signal::channel(5745), Some(149));
+    assert_eq!(signal::channel(5955), Some(1));
+    assert_eq!(signal::channel(1000), None);
+    assert_eq!(signal::band(2437), "2.4 GHz");
+    assert_eq!(signal::band(5180), "5 GHz");
+    assert_eq!(signal::band(5955), "6 GHz");
+}
+
+#[test]
+fn test_site_survey() {
+    let scan = |output: &str| wifi::parse_scan_results(output);
+    let first = scan(
+        "bssid / frequency / signal level / flags / ssid\n\
+         aa:bb:cc:dd:ee:01\t2437\t-60\t[WPA2-PSK-CCMP][ESS]\tPeachCloud\n\
+         aa:bb:cc:dd:ee:02\t5180\t-71\t[WPA2-SAE-CCMP][ESS]\tUpstairs\n\
+         aa:bb:cc:dd:ee:03\t2412\t-80\t[ESS]\t\n",
+    );
+    assert_eq!(first.len(), 3);
+    assert_eq!(first[0].ssid, "PeachCloud");
+    assert_eq!(first[2].ssid, "");
+    let second = scan("aa:bb:cc:dd:ee:01\t2437\t-50\t[WPA2-PSK-CCMP][ESS]\tPeachCloud\n");
+
+    let mut entries = HashMap::new();
+    signal::aggregate(&mut entries, &first);
+    signal::aggregate(&mut entries, &second);
+    let entry = &entries["aa:bb:cc:dd:ee:01"];
+    assert_eq!((entry.min, entry.avg, entry.max), (-60, -55, -50));
+    assert_eq!(entry.seen, 2);
+    assert_eq!(entry.channel, Some(6));
+    assert_eq!(entry.security, "WPA2");
+    assert_eq!(entries["aa:bb:cc:dd:ee:02"].security, "WPA3");
+    assert_eq!(entries["aa:bb:cc:dd:ee:02"].band, "5 GHz");
+    assert_eq!(entries["aa:bb:cc:dd:ee:03"].security, "Open");
+    assert_eq!(entries["aa:bb:cc:dd:ee:03"].seen, 1);
+}
+
+#[test]
+fn test_rssi_history() {
+    let dir = test_store_dir("rssi-history");
+    let store = DataStore::open_at(&dir).unwrap();
+    for (timestamp, rssi) in &[(60, -60), (120, -65)] {
+        let sample = RssiSample {
+            timestamp: *timestamp,
+            ssid: "PeachCloud".to_string(),
+            rssi: *rssi,
+        };
+        signal::record_in(&store, sample).unwrap();
+    }
+    let samples: Vec<RssiSample> = store.get_list(&["history", "rssi", "samples"]).unwrap();
+    assert_eq!(samples.len(), 2);
+    assert_eq!(samples[1].rssi, -65);
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(signal::sparkline(&[-60]).is_none());
+    let svg = signal::sparkline(&[-100, -30]).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains("points=\"0.0,48.0 240.0,0.0\""));
+}
+
+#[test]
+fn test_setup_captive_dns_conf() {
+    let captive = setup::captive_dns_conf(true);
+    assert!(captive.contains("interface-name=captive.apple.com,ap0\n"));
+    assert!(captive.contains("interface-name=connectivitycheck.gstatic.com,ap0\n"));
+    // once setup is complete the probe hosts are not overridden
+    assert!(!setup::captive_dns_conf(false).contains("interface-name"));
+}
+
+#[test]
+fn test_setup_steps() {
+    assert_eq!(setup::next_step("wifi"), Some("hostname"));
+    assert_eq!(setup::next_step("dns"), Some("password"));
+    assert_eq!(setup::next_step("password"), Some("finish"));
+    assert_eq!(setup::next_step("finish"), None);
+    assert_eq!(setup::next_step("unknown"), None);
+}
+
+#[test]
+fn test_validate_hostname() {
+    assert_eq!(hostname::validate(" Peach-01 ").unwrap(), "peach-01");
+    assert!(hostname::validate("").is_err());
+    assert!(hostname::validate("-peach").is_err());
+    assert!(hostname::validate("peach-").is_err());
+    assert!(hostname::validate("peach.local").is_err());
+    assert!(hostname::validate("peach cloud").is_err());
+    assert!(hostname::validate(&"a".repeat(64)).is_err());
+}
+
+// helper function to run a stand-in yggdrasil admin socket, answering each
+// request with a canned response
+fn yggdrasil_stand_in(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("peach-web-{}-{}.sock", name, process::id()));
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            let mut request = String::new();
+            BufReader::new(&stream).read_line(&mut request).unwrap();
+            let request: serde_json::Value = serde_json::from_str(&request).unwrap();
+            let response = match request["request"].as_str().unwrap() {
+                "getself" => serde_json::json!({
+                    "status": "success",
+                    "response": {
+                        "build_version": "0.5.5",
+                        "key": "3ad4b1b0",
+                        "address": "200:8a56:9c9e::1",
+                        "subnet": "300:8a56:9c9e::/64",
+                    },
+                }),
+                "getpeers" => serde_json::json!({
+                    "status": "success",
+                    "response": {
+                        "peers": [{
+                            "remote": "tls://peer.example.org:443",
+                            "up": true,
+                            "inbound": false,
+                            "address": "201:1::1",
+                            "key": "c0ffee",
+                            "uptime": 42.5,
+                            "bytes_recvd": 1024,
+                            "bytes_sent": 2048,
+                        }],
+                    },
+                }),
+                _ => serde_json::json!({
+                    "status": "error",
+                    "error": "peer already configured",
+                }),
+            };
+            writeln!(&stream, "{}", response).unwrap();
+        }
+    });
+    path
+}
+
+#[test]
+fn test_yggdrasil_admin() {
+    let path = yggdrasil_stand_in("yggdrasil-admin");
+    let admin = Admin::at(&path);
+    let node = admin.get_self().unwrap();
+    assert_eq!(node.address, "200:8a56:9c9e::1");
+    assert_eq!(node.key, "3ad4b1b0");
+    let peers = admin.get_peers().unwrap();
+    assert_eq!(peers.len(), 1);
+    assert!(peers[0].up);
+    assert_eq!(peers[0].bytes_sent, 2048);
+    // errors reported by the daemon are passed on
+    match admin.add_peer("tls://peer.example.org:443") {
+        Err(PeachWebError::YggdrasilRequest { request, msg }) => {
+            assert_eq!(request, "addpeer");
+            assert_eq!(msg, "peer already configured");
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    let _ = fs::remove_file(&path);
+
+    // a missing socket is reported as such
+    match Admin::at(&path).get_self() {
+        Err(PeachWebError::YggdrasilSocket { .. }) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_yggdrasil_validate_peer() {
+    assert!(yggdrasil::validate_peer("tls://peer.example.org:443").is_ok());
+    assert!(yggdrasil::validate_peer("tcp://[2001:db8::1]:9001").is_ok());
+    assert!(yggdrasil::validate_peer("quic://192.0.2.1:9002?key=c0ffee").is_ok());
+    assert!(yggdrasil::validate_peer("socks://localhost:9050/peer.example.org:443").is_ok());
+    assert!(yggdrasil::validate_peer("peer.example.org:443").is_err());
+    assert!(yggdrasil::validate_peer("http://peer.example.org:443").is_err());
+    assert!(yggdrasil::validate_peer("tls://peer.example.org").is_err());
+    assert!(yggdrasil::validate_peer("tls://:443").is_err());
+    assert!(yggdrasil::validate_peer("tls://peer.example.org:0").is_err());
+    assert!(yggdrasil::validate_peer("tls://peer example:443").is_err());
+}
+
+#[test]
+fn test_yggdrasil_merge_peers() {
+    let configured = vec![
+        "tls://peer.example.org:443".to_string(),
+        "tcp://192.0.2.1:9001".to_string(),
+    ];
+    let statuses = vec![
+        PeerStatus {
+            remote: "tls://peer.example.org:443".to_string(),
+            up: true,
+            ..PeerStatus::default()
+        },
+        PeerStatus {
+            remote: "tcp://[fe80::1%wlan0]:40000".to_string(),
+            inbound: true,
+            ..PeerStatus::default()
+        },
+    ];
+    let peers = yggdrasil::merge(&configured, statuses);
+    assert_eq!(peers.len(), 3);
+    assert!(peers[0].configured && peers[0].status.as_ref().unwrap().up);
+    assert!(peers[1].configured && peers[1].status.is_none());
+    assert!(!peers[2].configured && peers[2].status.as_ref().unwrap().inbound);
+}
+
+#[test]
+fn test_update_hosts() {
+    let hosts = "127.0.0.1\tlocalhost\n::1\t\tlocalhost ip6-localhost\n127.0.1.1\tpeach\n";
+    assert_eq!(
+        hostname::update_hosts(hosts, "peach-kitchen"),
+        "127.0.0.1\tlocalhost\n::1\t\tlocalhost ip6-localhost\n127.0.1.1\tpeach-kitchen\n"
+    );
+    // the entry is added if missing
+    assert_eq!(
+        hostname::update_hosts("127.0.0.1\tlocalhost", "peach"),
+        "127.0.0.1\tlocalhost\n127.0.1.1\tpeach\n"
+    );
+}
+
+#[test]
+fn test_update_avahi() {
+    // a commented-out option is replaced, other sections are left untouched
+    let conf = "[server]\n#host-name=foo\nuse-ipv4=yes\n\n[wide-area]\nenable-wide-area=yes\n";
+    assert_eq!(
+        hostname::update_avahi(conf, "peach-kitchen"),
+        "[server]\nhost-name=peach-kitchen\nuse-ipv4=yes\n\n[wide-area]\nenable-wide-area=yes\n"
+    );
+    // the option is added to the end of the server section if missing
+    let conf = "[server]\nuse-ipv4=yes\n[publish]\npublish-addresses=yes\n";
+    assert_eq!(
+        hostname::update_avahi(conf, "peach"),
+        "[server]\nuse-ipv4=yes\nhost-name=peach\n[publish]\npublish-addresses=yes\n"
+    );
+    // as is the server section
+    assert_eq!(
+        hostname::update_avahi("[publish]\npublish-addresses=yes\n", "peach"),
+        "[publish]\npublish-addresses=yes\n[server]\nhost-name=peach\n"
+    );
+    // a host-name option in another section is not the server host name
+    assert_eq!(
+        hostname::update_avahi("[server]\n[reflector]\nhost-name=x\n", "peach"),
+        "[server]\nhost-name=peach\n[reflector]\nhost-name=x\n"
+    );
+}
+
+#[test]
+fn test_render_server_name() {
+    let snippet = hostname::render_server_name("peach-kitchen");
+    assert!(snippet.starts_with("server_name peach-kitchen.local www.peach-kitchen.local;\n"));
+    assert!(snippet.contains("http://peach-kitchen.local/send_password_reset/"));
+}
+
+#[test]
+fn test_datetime_parse_show() {
+    let output = "Timezone=Pacific/Auckland\n\
+                  LocalRTC=no\n\
+                  CanNTP=yes\n\
+                  NTP=yes\n\
+                  NTPSynchronized=no\n\
+                  TimeUSec=Tue 2021-06-01 14:30:00 NZST\n";
+    let status = datetime::parse_show(output);
+    assert_eq!(status.timezone, "Pacific/Auckland");
+    assert!(status.ntp_available);
+    assert!(status.ntp_enabled);
+    assert!(!status.ntp_synchronized);
+}
+
+#[test]
+fn test_datetime_validate() {
+    assert!(datetime::validate_timezone("Europe/Berlin").is_ok());
+    assert!(datetime::validate_timezone("UTC").is_ok());
+    assert!(datetime::validate_timezone("Mars/Olympus_Mons").is_err());
+    assert!(datetime::validate_timezone("Europe/Berlin; reboot").is_err());
+    assert!(datetime::validate_timestamp(1_622_514_600).is_ok());
+    assert!(datetime::validate_timestamp(0).is_err());
+    assert!(datetime::validate_timestamp(-1).is_err());
+    assert!(datetime::validate_timestamp(4_102_444_800).is_err());
+}
+
+#[test]
+fn test_local_time() {
+    // 2021-06-01 02:30:00 UTC
+    let timestamp = 1_622_514_600;
+    assert_eq!(
+        datetime::local_time(timestamp, "Pacific/Auckland"),
+        "2021-06-01 14:30:00 NZST"
+    );
+    assert_eq!(
+        datetime::local_time(timestamp, "unknown"),
+        "2021-06-01 02:30:00 UTC"
+    );
+}
+
+#[test]
+fn test_tls_self_signed() {
+    let (cert, _key) = tls::generate_self_signed("peach.local", 10).unwrap();
+    let info = tls::cert_info(&cert).unwrap();
+    assert_eq!(info.subject, "peach.local");
+    assert_eq!(info.issuer, "peach.local");
+    assert_eq!(info.names, vec!["peach.local", "www.peach.local"]);
+    // 32 bytes as colon-separated hex
+    assert_eq!(info.fingerprint.len(), 95);
+    assert_eq!(info.fingerprint.matches(':').count(), 31);
+    let days = (info.not_after - Utc::now().timestamp()) / 86_400;
+    assert!(days == 9 || days == 10);
+}
+
+#[test]
+fn test_tls_ensure_self_signed() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = test_store_dir("tls-self-signed");
+    let now = Utc::now().timestamp();
+    assert!(tls::ensure_self_signed_at(&dir, "peach.local", now).unwrap());
+    let original = fs::read(dir.join("self-signed.crt")).unwrap();
+    // the certificate is kept while it is valid for the name
+    assert!(!tls::ensure_self_signed_at(&dir, "peach.local", now).unwrap());
+    assert_eq!(fs::read(dir.join("self-signed.crt")).unwrap(), original);
+    // and issued again when the hostname changes
+    assert!(tls::ensure_self_signed_at(&dir, "peach-kitchen.local", now).unwrap());
+    assert_ne!(fs::read(dir.join("self-signed.crt")).unwrap(), original);
+    let mode = fs::metadata(dir.join("self-signed.key"))
+        .unwrap()
+        .permissions()
+        .mode();
+    assert_eq!(mode & 0o777, 0o600);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_tls_acme_due() {
+    let now = 1_622_514_600;
+    let cert = |name: &str, days: i64| CertInfo {
+        subject: name.to_string(),
+        names: vec![name.to_string()],
+        issuer: "Pebble Intermediate CA".to_string(),
+        fingerprint: String::new(),
+        not_after: now + days * 86_400,
+        expires: String::new(),
+    };
+    let state = AcmeState::default();
+    // no domain, no request
+    assert!(!tls::acme_due(None, "", &state, now));
+    assert!(tls::acme_due(None, "peach.example.org", &state, now));
+    assert!(!tls::acme_due(
+        Some(&cert("peach.example.org", 60)),
+        "peach.example.org",
+        &state,
+        now
+    ));
+    // about to expire, or issued for another domain
+    assert!(tls::acme_due(
+        Some(&cert("peach.example.org", 20)),
+        "peach.example.org",
+        &state,
+        now
+    ));
+    assert!(tls::acme_due(
+        Some(&cert("old.example.org", 60)),
+        "peach.example.org",
+        &state,
+        now
+    ));
+    // a failed request is retried after an hour
+    let failed = AcmeState {
+        domain: "peach.example.org".to_string(),
+        last_attempt: now - 600,
+        last_success: 0,
+        last_error: "Failed to obtain certificate".to_string(),
+    };
+    assert!(!tls::acme_due(None, "peach.example.org", &failed, now));
+    assert!(tls::acme_due(
+        None,
+        "peach.example.org",
+        &failed,
+        now + 3600
+    ));
+    assert!(tls::acme_due(None, "other.example.org", &failed, now));
+}
+
+#[test]
+fn test_certbot_args() {
+    let dir = PathBuf::from("/var/lib/peachcloud/tls");
+    let args = tls::certbot_args("peach.example.org", "https://localhost:14000/dir", &dir);
+    let value = |flag: &str| {
+        let index = args.iter().position(|arg| arg == flag).unwrap();
+        args[index + 1].clone()
+    };
+    assert_eq!(args[0], "certonly");
+    assert!(args.contains(&"--non-interactive".to_string()));
+    assert_eq!(value("--domain"), "peach.example.org");
+    assert_eq!(value("--server"), "https://localhost:14000/dir");
+    assert_eq!(value("--webroot-path"), "/var/lib/peachcloud/tls/webroot");
+    assert_eq!(value("--config-dir"), "/var/lib/peachcloud/tls/letsencrypt");
+}
+
+#[test]
+fn test_render_tls_snippet() {
+    let snippet = tls::render_snippet(
+        &PathBuf::from("/var/lib/peachcloud/tls/self-signed.crt"),
+        &PathBuf::from("/var/lib/peachcloud/tls/self-signed.key"),
+    );
+    let lines: Vec<&str> = snippet.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            "listen 443 ssl;",
+            "ssl_certificate /var/lib/peachcloud/tls/self-signed.crt;",
+            "ssl_certificate_key /var/lib/peachcloud/tls/self-signed.key;",
+            "set $https_redirect 0;",
+        ]
+    );
+    assert_eq!(lines[4], "if ($scheme = http) { set $https_redirect 1; }");
+    assert_eq!(
+        lines[6],
+        "if ($https_redirect) { return 301 https://$host$request_uri; }"
+    );
+
+    // acme challenges, captive-portal probes and the first-run pages stay on
+    // plain http
+    let exempt = lines[5]
+        .strip_prefix("if ($uri ~ \"")
+        .and_then(|line| line.strip_suffix("\") { set $https_redirect 0; }"))
+        .unwrap();
+    let exempt = Regex::new(exempt).unwrap();
+    for path in &[
+        "/.well-known/acme-challenge/token",
+        "/generate_204",
+        "/gen_204",
+        "/hotspot-detect.html",
+        "/library/test/success.html",
+        "/connecttest.txt",
+        "/ncsi.txt",
+        "/success.txt",
+        "/setup",
+        "/setup/wifi",
+        "/css/peachcloud.css",
+        "/icons/peach-icon.png",
+    ] {
+        assert!(exempt.is_match(path), "{} is redirected", path);
+    }
+    for path in &["/", "/login", "/setupx", "/network/dns", "/ncsi.txt.bak"] {
+        assert!(!exempt.is_match(path), "{} is not redirected", path);
+    }
+}
+
+#[test]
+fn test_dyndns_history() {
+    let dir = test_store_dir("dyndns-history");
+    let store = DataStore::open_at(&dir).unwrap();
+    assert!(dyndns::record_ip_in(&store, "203.0.113.7", 1000).unwrap());
+    // an unchanged address is not recorded again
+    assert!(!dyndns::record_ip_in(&store, "203.0.113.7", 1300).unwrap());
+    assert!(dyndns::record_ip_in(&store, "198.51.100.20", 1600).unwrap());
+    let history: Vec<IpChange> = store.get_list(&["dns", "dyndns", "history"]).unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[1].ip, "198.51.100.20");
+    assert_eq!(history[1].time, 1600);
+    // only the most recent changes are kept
+    for i in 0..60 {
+        dyndns::record_ip_in(&store, &format!("192.0.2.{}", i), 2000 + i).unwrap();
+    }
+    let history: Vec<IpChange> = store.get_list(&["dns", "dyndns", "history"]).unwrap();
+    assert_eq!(history.len(), 50);
+    assert_eq!(history[49].ip, "192.0.2.59");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_dyndns_updates() {
+    let dir = test_store_dir("dyndns-updates");
+    let store = DataStore::open_at(&dir).unwrap();
+    assert!(dyndns::is_new_update_in(&store, 1000).unwrap());
+    assert!(dyndns::record_update_in(&store, 1000, "203.0.113.7", 1010).unwrap());
+    // the same update, reported a second later, is not new
+    assert!(!dyndns::is_new_update_in(&store, 1001).unwrap());
+    assert!(dyndns::is_new_update_in(&store, 1300).unwrap());
+    assert!(!dyndns::record_update_in(&store, 1300, "203.0.113.7", 1310).unwrap());
+    assert!(!dyndns::is_new_update_in(&store, 1300).unwrap());
+    let history: Vec<IpChange> = store.get_list(&["dns", "dyndns", "history"]).unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].time, 1010);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_dyndns_failures() {
+    let dir = test_store_dir("dyndns-failures");
+    let store = DataStore::open_at(&dir).unwrap();
+    // going online is not a failure, going offline is (once)
+    dyndns::record_online_in(&store, true, 1000).unwrap();
+    dyndns::record_online_in(&store, false, 1300).unwrap();
+    dyndns::record_online_in(&store, false, 1600).unwrap();
+    dyndns::record_failure_in(&store, "Update failed: nsupdate error", 1900).unwrap();
+    let failures: Vec<Failure> = store.get_list(&["dns", "dyndns", "failures"]).unwrap();
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0].time, 1300);
+    assert_eq!(failures[1].msg, "Update failed: nsupdate error");
+    for i in 0..30 {
+        dyndns::record_failure_in(&store, "Update failed", 2000 + i).unwrap();
+    }
+    let failures: Vec<Failure> = store.get_list(&["dns", "dyndns", "failures"]).unwrap();
+    assert_eq!(failures.len(), 20);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_dyndns_parse_dig() {
+    assert_eq!(
+        dyndns::parse_dig("203.0.113.7\n"),
+        Some("203.0.113.7".to_string())
+    );
+    let cname = "peach.example.org.\n198.51.100.20\n";
+    assert_eq!(dyndns::parse_dig(cname), Some("198.51.100.20".to_string()));
+    assert_eq!(dyndns::parse_dig(""), None);
+    assert_eq!(
+        dyndns::parse_dig(";; connection timed out; no servers could be reached\n"),
+        None
+    );
+}
+
+#[test]
+fn test_dyndns_updater_runs() {
+    let dir = test_store_dir("dyndns-runs");
+    let store = DataStore::open_at(&dir).unwrap();
+    let failed = dyndns::parse_updater_run(
+        "Result=exit-code\nExecMainStatus=1\n\
+         ExecMainExitTimestamp=Mon 2026-10-19 10:00:00 UTC\n",
+    );
+    assert_eq!(failed.result, "exit-code");
+    assert_eq!(failed.status, "1");
+    assert_eq!(failed.exited, "Mon 2026-10-19 10:00:00 UTC");
+    // each failed run is recorded once
+    assert!(dyndns::record_run_in(&store, &failed, 1000).unwrap());
+    assert!(!dyndns::record_run_in(&store, &failed, 1300).unwrap());
+    let succeeded = dyndns::parse_updater_run(
+        "Result=success\nExecMainStatus=0\n\
+         ExecMainExitTimestamp=Mon 2026-10-19 10:05:00 UTC\n",
+    );
+    assert!(!dyndns::record_run_in(&store, &succeeded, 1600).unwrap());
+    // an updater which has never run has no exit time
+    let never =
+        dyndns::parse_updater_run("Result=exit-code\nExecMainStatus=0\nExecMainExitTimestamp=\n");
+    assert!(!dyndns::record_run_in(&store, &never, 1900).unwrap());
+    let failures: Vec<Failure> = store.get_list(&["dns", "dyndns", "failures"]).unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].time, 1000);
+    assert_eq!(failures[0].msg, "Updater failed: exit-code (exit status 1)");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_interval() {
+    let var = "PEACH_WEB_TEST_INTERVAL";
+    env::remove_var(var);
+    assert_eq!(interval(var, 60), 60);
+    env::set_var(var, "15");
+    assert_eq!(interval(var, 60), 15);
+    env::set_var(var, "soon");
+    assert_eq!(interval(var, 60), 60);
+    env::remove_var(var);
+}
+
+#[test]
+fn test_store_invalid_value() {
+    let dir = test_store_dir("store-invalid");
+    let store = DataStore::open_at(&dir).unwrap();
+    let path = ["net", "notify", "warn"];
+    store.set(&path, Value::String("lots".to_string())).unwrap();
+    match store.get_u64(&path) {
+        Err(PeachWebError::StoreValueInvalid { path, .. }) => assert_eq!(path, "net/notify/warn"),
+        other => panic!("expected an invalid value error, got {:?}", other),
+    }
+    // existing values are kept when the store is reopened
+    let store = DataStore::open_at(&dir).unwrap();
+    assert!(store.get_u64(&path).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_set_values() {
+    let dir = test_store_dir("store-values");
+    let store = DataStore::open_at(&dir).unwrap();
+    store
+        .set_values(
+            &["net", "notify"],
+            vec![("warn", Value::Uint(500)), ("cut_flag", Value::Bool(true))],
+        )
+        .unwrap();
+    assert_eq!(store.get_u64(&["net", "notify", "warn"]).unwrap(), 500);
+    assert!(store.get_bool(&["net", "notify", "cut_flag"]).unwrap());
+    // other values in the same file are preserved
+    assert_eq!(
+        store.get_string(&["net", "notify", "cycle_tz"]).unwrap(),
+        "UTC"
+    );
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_set_values_concurrently() {
+    let dir = test_store_dir("store-concurrent");
+    DataStore::open_at(&dir).unwrap();
+    // each writer updates its own value in the same file
+    let writers: Vec<_> = ["warn", "cut"]
+        .iter()
+        .map(|&key| {
+            let dir = dir.clone();
+            thread::spawn(move || {
+                let store = DataStore::open_at(&dir).unwrap();
+                for value in 1..=50 {
+                    store
+                        .set_values(&["net", "notify"], vec![(key, Value::Uint(value))])
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let store = DataStore::open_at(&dir).unwrap();
+    assert_eq!(store.get_u64(&["net", "notify", "warn"]).unwrap(), 50);
+    assert_eq!(store.get_u64(&["net", "notify", "cut"]).unwrap(), 50);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_update_concurrently() {
+    let dir = test_store_dir("store-update");
+    DataStore::open_at(&dir).unwrap();
+    // each writer increments the same value; no increment is lost
+    let writers: Vec<_> = (0..4)
+        .map(|_| {
+            let dir = dir.clone();
+            thread::spawn(move || {
+                let store = DataStore::open_at(&dir).unwrap();
+                for _ in 0..25 {
+                    store
+                        .update(&["net", "notify"], |values| {
+                            let warn = values.get_u64("warn")?;
+                            values.set("warn", Value::Uint(warn + 1));
+                            Ok(())
+                        })
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let store = DataStore::open_at(&dir).unwrap();
+    assert_eq!(store.get_u64(&["net", "notify", "warn"]).unwrap(), 100);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_update_error() {
+    let dir = test_store_dir("store-update-error");
+    let store = DataStore::open_at(&dir).unwrap();
+    // values set before the change fails are not written
+    let result: Result<(), PeachWebError> = store.update(&["net", "notify"], |values| {
+        values.set("warn", Value::Uint(500));
+        Err(PeachWebError::AlertRuleNotFound { id: 1 })
+    });
+    assert!(result.is_err());
+    assert_eq!(store.get_u64(&["net", "notify", "warn"]).unwrap(), 0);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_set_values_unreadable() {
+    let dir = test_store_dir("store-unreadable");
+    let store = DataStore::open_at(&dir).unwrap();
+    let file = fs::read_dir(dir.join("net"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.file_stem().map_or(false, |stem| stem == "notify"))
+        .unwrap();
+    fs::write(&file, "{\"warn\": 0, \"cut").unwrap();
+    // the file is left as it is rather than replaced with the new value only
+    assert!(store
+        .set_values(&["net", "notify"], vec![("warn", Value::Uint(500))])
+        .is_err());
+    assert_eq!(fs::read_to_string(&file).unwrap(), "{\"warn\": 0, \"cut");
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_disabled_networks() {
+    let dir = test_store_dir("store-disabled");
+    let store = DataStore::open_at(&dir).unwrap();
+    // a version 11 store recorded a single network disabled by the cutoff
+    store
+        .update(&["net", "alert"], |values| {
+            values.remove("disabled");
+            values.set("ssid", Value::String("Home".to_string()));
+            Ok(())
+        })
+        .unwrap();
+    store.set(&["meta", "version"], Value::Uint(11)).unwrap();
+    let store = DataStore::open_at(&dir).unwrap();
+    let disabled: Vec<String> = store.get_list(&["net", "alert", "disabled"]).unwrap();
+    assert_eq!(disabled, vec!["Home".to_string()]);
+    assert!(store.get_string(&["net", "alert", "ssid"]).is_err());
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_store_newer_version() {
+    let dir = test_store_dir("store-version");
+    let store = DataStore::open_at(&dir).unwrap();
+    store
+        .set(&["meta", "version"], Value::Uint(SCHEMA_VERSION + 1))
+        .unwrap();
+    match DataStore::open_at(&dir) {
+        Err(PeachWebError::StoreVersion { version, supported }) => {
+            assert_eq!(version, SCHEMA_VERSION + 1);
+            assert_eq!(supported, SCHEMA_VERSION);
+        }
+        _ => panic!("expected a schema version error"),
+    }
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_export_range() {
+    let today = NaiveDate::from_ymd(2021, 3, 15);
+    let range = Range::parse(None, None, today).unwrap();
+    assert_eq!(range.from, NaiveDate::from_ymd(2021, 2, 14));
+    assert_eq!(range.to, today);
+    let range = Range::parse(Some("2021-03-01"), Some("2021-03-01"), today).unwrap();
+    let (start, end) = range.bounds();
+    assert_eq!(end - start, 86400);
+    assert!(Range::parse(Some("2021-03-02"), Some("2021-03-01"), today).is_err());
+    assert!(Range::parse(Some("01/03/2021"), None, today).is_err());
+    assert_eq!(Format::parse(None).unwrap(), Format::Csv);
+    assert_eq!(Format::parse(Some("json")).unwrap(), Format::Json);
+    assert!(Format::parse(Some("xls")).is_err());
+}
+
+#[test]
+fn test_csv_field() {
+    assert_eq!(csv_field("cpu"), "cpu");
+    assert_eq!(csv_field("a,b"), "\"a,b\"");
+    assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+}
+
+#[test]
+fn test_ap_config_validation() {
+    let config = |ssid: &str, pass: &str, channel: u8, country: &str| ApConfig {
+        ssid: ssid.to_string(),
+        pass: pass.to_string(),
+        channel,
+        country: country.to_string(),
+        hidden: false,
+    };
+    assert!(config("peach", "", 6, "NZ").validate().is_ok());
+    assert!(config("peach", "correct horse", 11, "NZ")
+        .validate()
+        .is_ok());
+    assert!(config("peach", &"a".repeat(64), 1, "NZ").validate().is_ok());
+    assert!(config("", "", 6, "NZ").validate().is_err());
+    assert!(config(&"p".repeat(33), "", 6, "NZ").validate().is_err());
+    assert!(config("peach\n", "", 6, "NZ").validate().is_err());
+    assert!(config("peach", "short", 6, "NZ").validate().is_err());
+    assert!(config("peach", "", 0, "NZ").validate().is_err());
+    assert!(config("peach", "", 14, "NZ").validate().is_err());
+    assert!(config("peach", "", 14, "JP").validate().is_ok());
+    assert!(config("peach", "", 6, "nz").validate().is_err());
+}
+
+#[test]
+fn test_hostapd_config_update() {
+    let conf = "# peachcloud ap\ninterface=ap0\nssid=peach\nchannel=6\nwpa_passphrase=cloudcloud\n";
+    let current = ap::parse(conf);
+    assert_eq!(current.ssid, "peach");
+    assert_eq!(current.channel, 6);
+    assert!(!current.hidden);
+
+    let config = ApConfig {
+        ssid: "orchard".to_string(),
+        pass: String::new(),
+        channel: 11,
+        country: "NZ".to_string(),
+        hidden: true,
+    };
+    let updated = ap::update(conf, &config);
+    assert!(updated.starts_with("# peachcloud ap\ninterface=ap0\nssid=orchard\nchannel=11\n"));
+    // an empty passphrase leaves the current passphrase in place
+    assert!(updated.contains("wpa_passphrase=cloudcloud\n"));
+    assert!(updated.contains("country_code=NZ\n"));
+    assert!(updated.contains("ignore_broadcast_ssid=1\n"));
+    let parsed = ap::parse(&updated);
+    assert_eq!(parsed.ssid, "orchard");
+    assert_eq!(parsed.country, "NZ");
+    assert!(parsed.hidden);
+}
+
+#[test]
+fn test_hostapd_deny_list() {
+    let conf = "interface=ap0\nssid=peach\nmacaddr_acl=1\n";
+    let updated = ap::with_deny_list(conf);
+    assert_eq!(
+        updated,
+        "interface=ap0\nssid=peach\nmacaddr_acl=0\ndeny_mac_file=/etc/hostapd/hostapd.deny\n"
+    );
+    // already enabled
+    assert_eq!(ap::with_deny_list(&updated), updated);
+}
+
+#[test]
+fn test_parse_ap_clients() {
+    let dump = "Station AA:BB:CC:00:11:22 (on ap0)
+	inactive time:	1200 ms
+	rx bytes:	51234
+	tx bytes:	98765
+	signal:  	-52 [-52] dBm
+	connected time:	300 seconds
+Station dd:ee:ff:33:44:55 (on ap0)
+	rx bytes:	10
+	tx bytes:	20
+";
+    let stations = ap::parse_stations(dump);
+    assert_eq!(stations.len(), 2);
+    assert_eq!(stations[0].mac, "aa:bb:cc:00:11:22");
+    assert_eq!(stations[0].rx_bytes, 51234);
+    assert_eq!(stations[0].tx_bytes, 98765);
+    assert_eq!(stations[0].signal, Some(-52));
+    assert_eq!(stations[0].connected, Some(300));
+    assert_eq!(stations[1].signal, None);
+
+    let leases = "1634000000 aa:bb:cc:00:11:22 11.11.11.20 phone 01:aa:bb:cc:00:11:22
+1634000100 dd:ee:ff:33:44:55 11.11.11.21 * *
+";
+    let leases = ap::parse_leases(leases);
+    assert_eq!(
+        leases.get("aa:bb:cc:00:11:22"),
+        Some(&("11.11.11.20".to_string(), Some("phone".to_string())))
+    );
+    assert_eq!(
+        leases.get("dd:ee:ff:33:44:55"),
+        Some(&("11.11.11.21".to_string(), None))
+    );
+}
+
+#[test]
+fn test_validate_mac() {
+    assert_eq!(
+        ap::validate_mac("AA:bb:CC:00:11:22").unwrap(),
+        "aa:bb:cc:00:11:22"
+    );
+    assert!(ap::validate_mac("aa:bb:cc:00:11").is_err());
+    assert!(ap::validate_mac("aa:bb:cc:00:11:2g").is_err());
+    assert!(ap::validate_mac("aa-bb-cc-00-11-22").is_err());
+    assert!(ap::validate_mac("aa:bb:cc:00:11:22\n").is_ok());
+    assert!(ap::validate_mac("aa:bb:cc:00:11:22;reboot").is_err());
+}
+
+#[test]
+fn test_ip_config_validation() {
+    let config = |method: &str, address: &str, gateway: &str, dns: &str| IpConfig {
+        iface: "wlan0".to_string(),
+        method: method.to_string(),
+        address: address.to_string(),
+        gateway: gateway.to_string(),
+        dns: dns.to_string(),
+        method6: "slaac".to_string(),
+        address6: String::new(),
+    };
+    assert!(config("dhcp", "", "", "").validate().is_ok());
+    assert!(config("dhcp", "", "", "1.1.1.1, 2606:4700::1111")
+        .validate()
+        .is_ok());
+    assert!(
+        config("static", "192.168.1.10/24", "192.168.1.1", "9.9.9.9")
+            .validate()
+            .is_ok()
+    );
+    assert!(config("static", "192.168.1.10", "192.168.1.1", "")
+        .validate()
+        .is_err());
+    assert!(config("static", "192.168.1.10/33", "", "")
+        .validate()
+        .is_err());
+    assert!(config("static", "192.168.1.10/24", "192.168.2.1", "")
+        .validate()
+        .is_err());
+    assert!(config("static", "192.168.1.10/24", "192.168.1.10", "")
+        .validate()
+        .is_err());
+    assert!(config("dhcp", "", "", "dns.example").validate().is_err());
+    assert!(config("manual", "", "", "").validate().is_err());
+
+    let mut v6 = config("dhcp", "", "", "");
+    v6.method6 = "static".to_string();
+    v6.address6 = "fd00::10/64".to_string();
+    assert!(v6.validate().is_ok());
+    v6.address6 = "fd00::10".to_string();
+    assert!(v6.validate().is_err());
+
+    let mut ap = config("dhcp", "", "", "");
+    ap.iface = "ap0".to_string();
+    assert!(ap.validate().is_err());
+}
+
+#[test]
+fn test_dhcpcd_config_update() {
+    let conf = "hostname\nclientid\n\ninterface ap0\nstatic ip_address=11.11.11.10/24\nnohook wpa_supplicant\n";
+    let dhcp = ipconfig::parse(conf, "wlan0");
+    assert_eq!(dhcp.method, "dhcp");
+    assert_eq!(dhcp.method6, "slaac");
+
+    let config = IpConfig {
+        iface: "wlan0".to_string(),
+        method: "static".to_string(),
+        address: "192.168.1.10/24".to_string(),
+        gateway: "192.168.1.1".to_string(),
+        dns: "1.1.1.1, 9.9.9.9".to_string(),
+        method6: "static".to_string(),
+        address6: "fd00::10/64".to_string(),
+    };
+    let updated = ipconfig::update(conf, &config);
+    // other interface blocks are left untouched
+    assert!(updated.starts_with(conf));
+    assert!(updated.contains("interface wlan0\nstatic ip_address=192.168.1.10/24\nstatic routers=192.168.1.1\nstatic domain_name_servers=1.1.1.1 9.9.9.9\nnoipv6rs\nstatic ip6_address=fd00::10/64\n"));
+    let parsed = ipconfig::parse(&updated, "wlan0");
+    assert_eq!(parsed.address, config.address);
+    assert_eq!(parsed.gateway, config.gateway);
+    assert_eq!(parsed.dns, "1.1.1.1 9.9.9.9");
+    assert_eq!(parsed.address6, config.address6);
+    assert_eq!(ipconfig::parse(&updated, "ap0").address, "11.11.11.10/24");
+
+    // returning to dhcp removes the block
+    let dhcp = IpConfig {
+        iface: "wlan0".to_string(),
+        method: "dhcp".to_string(),
+        method6: "slaac".to_string(),
+        ..Default::default()
+    };
+    let reverted = ipconfig::update(&updated, &dhcp);
+    assert!(!reverted.contains("interface wlan0"));
+    assert!(reverted.contains("nohook wpa_supplicant"));
+}
+
+#[test]
+fn test_dhcpcd_config_revert() {
+    let conf = "hostname\n\ninterface eth0\nstatic ip_address=10.0.0.2/24\n";
+    let previous = ipconfig::parse(conf, "eth0");
+    let static_config = |iface: &str, address: &str| IpConfig {
+        iface: iface.to_string(),
+        method: "static".to_string(),
+        address: address.to_string(),
+        method6: "slaac".to_string(),
+        ..Default::default()
+    };
+    // change eth0, then change (and confirm) wlan0
+    let updated = ipconfig::update(conf, &static_config("eth0", "10.0.0.3/24"));
+    let updated = ipconfig::update(&updated, &static_config("wlan0", "192.168.1.10/24"));
+
+    // reverting eth0 restores its block only
+    let reverted = ipconfig::update(&updated, &previous);
+    assert_eq!(ipconfig::parse(&reverted, "eth0").address, "10.0.0.2/24");
+    assert_eq!(
+        ipconfig::parse(&reverted, "wlan0").address,
+        "192.168.1.10/24"
+    );
+}
+
+#[test]
+fn test_parse_saved_networks() {
+    let output = "network id / ssid / bssid / flags
+0\tHome\tany\t[CURRENT]
+1\tCafe WiFi\tany\t
+2\teduroam\tany\t[DISABLED]
+";
+    let networks = wifi::parse_list(output);
+    assert_eq!(
+        networks,
+        vec![
+            ("0".to_string(), "Home".to_string(), true, true),
+            ("1".to_string(), "Cafe WiFi".to_string(), true, false),
+            ("2".to_string(), "eduroam".to_string(), false, false),
+        ]
+    );
+    assert_eq!(wifi::priorities(3), vec![3, 2, 1]);
+    assert!(wifi::priorities(0).is_empty());
+}
+
+#[test]
+fn test_wifi_network_settings() {
+    let wifi = |ssid: &str, pass: &str, security: &str| WiFi {
+        ssid: ssid.to_string(),
+        pass: pass.to_string(),
+        security: Some(security.to_string()),
+        ..Default::default()
+    };
+    assert_eq!(Security::parse(None).unwrap(), Security::WpaPsk);
+    assert!(Security::parse(Some("wep")).is_err());
+
+    // ssids are hex-encoded, hidden networks are probed for
+    let mut hidden = wifi("Café", "", "open");
+    hidden.hidden = true;
+    assert_eq!(
+        wifi::network_settings(&hidden).unwrap(),
+        vec![
+            ("ssid", "436166c3a9".to_string()),
+            ("scan_ssid", "1".to_string()),
+            ("key_mgmt", "NONE".to_string()),
+        ]
+    );
+
+    let psk = wifi::network_settings(&wifi("Home", "Password", "wpa-psk")).unwrap();
+    assert!(psk.contains(&("psk", "\"Password\"".to_string())));
+    assert!(wifi::network_settings(&wifi("Home", "short", "wpa-psk")).is_err());
+    assert!(wifi::network_settings(&wifi("Home", "pass\nword", "wpa-psk")).is_err());
+
+    let sae = wifi::network_settings(&wifi("Home", "pass", "sae")).unwrap();
+    assert!(sae.contains(&("key_mgmt", "SAE".to_string())));
+    assert!(sae.contains(&("ieee80211w", "2".to_string())));
+
+    let mut eduroam = wifi("eduroam", "secret", "peap");
+    assert!(wifi::network_settings(&eduroam).is_err());
+    eduroam.identity = Some("user@example.edu".to_string());
+    eduroam.anonymous_identity = Some("anonymous@example.edu".to_string());
+    let eap = wifi::network_settings(&eduroam).unwrap();
+    assert!(eap.contains(&("key_mgmt", "WPA-EAP".to_string())));
+    assert!(eap.contains(&("eap", "PEAP".to_string())));
+    assert!(eap.contains(&("identity", "\"user@example.edu\"".to_string())));
+    assert!(eap.contains(&(
+        "anonymous_identity",
+        "\"anonymous@example.edu\"".to_string()
+    )));
+    assert!(eap.contains(&("phase2", "\"auth=MSCHAPV2\"".to_string())));
+    assert!(!eap.iter().any(|(variable, _)| *variable == "ca_cert"));
+
+    // ttls requires the server domain, which enables certificate validation
+    eduroam.security = Some("ttls".to_string());
+    assert!(wifi::network_settings(&eduroam).is_err());
+    eduroam.server_domain = Some("example.edu".to_string());
+    let eap = wifi::network_settings(&eduroam).unwrap();
+    assert!(eap.contains(&("phase2", "\"auth=PAP\"".to_string())));
+    assert!(eap.contains(&(
+        "ca_cert",
+        "\"/etc/ssl/certs/ca-certificates.crt\"".to_string()
+    )));
+    assert!(eap.contains(&("domain_suffix_match", "\"example.edu\"".to_string())));
+    eduroam.server_domain = Some("example.edu\" ca_cert=\"/x".to_string());
+    assert!(wifi::network_settings(&eduroam).is_err());
+}
+
+#[test]
+fn test_wifi_uri() {
+    let wifi = WiFi {
+        ssid: "Peach;Cloud".to_string(),
+        pass: "a:b\\c".to_string(),
+        security: Some("wpa-psk".to_string()),
+        hidden: true,
+        ..Default::default()
+    };
+    let uri = qr::wifi_uri(&wifi).unwrap();
+    assert_eq!(uri, r#"WIFI:T:WPA;S:Peach\;Cloud;P:a\:b\\c;H:true;;"#);
+    let parsed = qr::parse_wifi_uri(&uri).unwrap();
+    assert_eq!(parsed.ssid, "Peach;Cloud");
+    assert_eq!(parsed.pass, "a:b\\c");
+    assert_eq!(parsed.security, Some("wpa-psk".to_string()));
+    assert!(parsed.hidden);
+
+    let open = qr::parse_wifi_uri("wifi:S:Cafe;;").unwrap();
+    assert_eq!(open.security, Some("open".to_string()));
+    let eap = qr::parse_wifi_uri("WIFI:T:WPA2-EAP;E:TTLS;S:eduroam;I:user;P:secret;;").unwrap();
+    assert_eq!(eap.security, Some("ttls".to_string()));
+    assert_eq!(eap.identity, Some("user".to_string()));
+    // enterprise credentials are personal and are not shared
+    assert!(qr::wifi_uri(&eap).is_err());
+    assert!(qr::parse_wifi_uri("WIFI:T:WEP;S:Old;P:12345;;").is_err());
+    assert!(qr::parse_wifi_uri("WIFI:T:WPA;P:secret;;").is_err());
+    assert!(qr::parse_wifi_uri("https://peachcloud.org").is_err());
+
+    let svg = qr::svg(&uri).unwrap();
+    assert!(svg.contains("<svg"));
+}
+
+#[test]
+fn test_shared_credentials() {
+    let hostapd = "interface=ap0\nssid=peach\nwpa=2\nwpa_passphrase=abundance\n";
+    let ap = ap::credentials(hostapd);
+    assert_eq!(ap.ssid, "peach");
+    assert_eq!(ap.pass, "abundance");
+    assert_eq!(ap.security, Some("wpa-psk".to_string()));
+    assert_eq!(
+        ap::credentials("ssid=open\n").security,
+        Some("open".to_string())
+    );
+
+    let conf = r#"ctrl_interface=DIR=/var/run/wpa_supplicant GROUP=netdev
+update_config=1
+
+network={
+	ssid="Home"
+	#psk="plaintext"
+	psk=0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0
+}
+
+network={
+	ssid=436166c3a9
+	key_mgmt=NONE
+	scan_ssid=1
+}
+
+network={
+	ssid="Hashed"
+	psk=0f1e2d3c4b5a69788796a5b4c3d2e1f00f1e2d3c4b5a69788796a5b4c3d2e1f0
+}
+"#;
+    let home = wifi::parse_credentials(conf, "Home").unwrap();
+    assert_eq!(home.pass, "plaintext");
+    assert_eq!(home.security, Some("wpa-psk".to_string()));
+    let cafe = wifi::parse_credentials(conf, "Café").unwrap();
+    assert_eq!(cafe.security, Some("open".to_string()));
+    assert!(cafe.hidden);
+    let hashed = wifi::parse_credentials(conf, "Hashed").unwrap();
+    assert_eq!(hashed.pass.len(), 64);
+    assert!(wifi::parse_credentials(conf, "Unknown").is_none());
+}
+
+#[test]
+fn test_diagnostics_parsers() {
+    let route = "default via 192.168.1.1 dev wlan0 proto dhcp src 192.168.1.20 metric 303\n";
+    assert_eq!(
+        diagnostics::parse_default_route(route),
+        Some(("192.168.1.1".to_string(), "wlan0".to_string()))
+    );
+    assert_eq!(diagnostics::parse_default_route(""), None);
+
+    let ping = "PING 192.168.1.1 (192.168.1.1) 56(84) bytes of data.
+64 bytes from 192.168.1.1: icmp_seq=1 ttl=64 time=1.01 ms
+
+--- 192.168.1.1 ping statistics ---
+3 packets transmitted, 2 received, 33.3333% packet loss, time 2003ms
+rtt min/avg/max/mdev = 1.012/2.034/3.120/0.410 ms
+";
+    assert_eq!(diagnostics::parse_ping(ping), (2, Some(2.034)));
+    assert_eq!(
+        diagnostics::parse_ping("3 packets transmitted, 0 received, 100% packet loss"),
+        (0, None)
+    );
+
+    let trace = "traceroute to 1.1.1.1 (1.1.1.1), 15 hops max, 60 byte packets
+ 1  192.168.1.1  1.123 ms
+ 2  *
+ 3  1.1.1.1  12.345 ms
+";
+    let (destination, hops) = diagnostics::parse_traceroute(trace);
+    assert_eq!(destination, Some("1.1.1.1".to_string()));
+    assert_eq!(hops.len(), 3);
+    assert_eq!(hops[2], "3 1.1.1.1 12.345 ms");
+}
+
+#[test]
+fn test_diagnostics_verdict() {
+    let result = |check: &str, target: &str, ok: bool, value: Option<u32>| CheckResult {
+        check: check.to_string(),
+        target: target.to_string(),
+        ok,
+        summary: String::new(),
+        details: Vec::new(),
+        value,
+        duration: 0,
+    };
+    let run = |gateway: bool, dns: bool, http: bool, trace: bool, mtu: u32| {
+        vec![
+            result("connection", "wlan0", true, None),
+            result("gateway", "192.168.1.1", gateway, None),
+            result("dns", "peachcloud.org", dns, None),
+            result("http", "http://example.com", http, None),
+            result("traceroute", "1.1.1.1", trace, None),
+            result("mtu", "1.1.1.1", true, Some(mtu)),
+        ]
+    };
+
+    assert_eq!(
+        diagnostics::verdict(&[result("connection", "", false, None)]),
+        "Not connected to a network with internet access"
+    );
+    assert_eq!(
+        diagnostics::verdict(&run(false, false, false, false, 1500)),
+        "Connected to WiFi but the router is not responding"
+    );
+    assert_eq!(
+        diagnostics::verdict(&run(true, false, false, false, 1500)),
+        "Connected to WiFi but no internet"
+    );
+    assert_eq!(
+        diagnostics::verdict(&run(true, false, false, true, 1500)),
+        "Connected to the internet but DNS is not working"
+    );
+    assert!(diagnostics::verdict(&run(true, true, false, true, 1500)).contains("captive portal"));
+    assert_eq!(
+        diagnostics::verdict(&run(true, true, true, true, 1500)),
+        "Connected to the internet"
+    );
+    assert!(diagnostics::verdict(&run(true, true, true, true, 1492)).contains("1492 bytes"));
+
+    let targets = Targets {
+        trace: Some(" ".to_string()),
+        ..Default::default()
+    };
+    assert!(targets.validate().is_ok());
+    assert_eq!(targets.trace_host(), diagnostics::DEFAULT_TRACE_HOST);
+    let targets = Targets {
+        http: Some("ftp://example.com".to_string()),
+        ..Default::default()
+    };
+    assert!(targets.validate().is_err());
+}
+
+#[test]
+fn test_wired_interfaces() {
+    let root = test_store_dir("sysfs-net");
+    // eth0 is wired, wlan0 is wireless and lo is virtual (no device)
+    for (name, device, wireless) in &[
+        ("eth0", true, false),
+        ("wlan0", true, true),
+        ("lo", false, false),
+    ] {
+        let dir = root.join(name);
+        fs::create_dir_all(dir.join("statistics")).unwrap();
+        if *device {
+            fs::create_dir_all(dir.join("device")).unwrap();
+        }
+        if *wireless {
+            fs::create_dir_all(dir.join("wireless")).unwrap();
+        }
+    }
+    let eth0 = root.join("eth0");
+    fs::write(eth0.join("operstate"), "up\n").unwrap();
+    fs::write(eth0.join("carrier"), "1\n").unwrap();
+    fs::write(eth0.join("speed"), "1000\n").unwrap();
+    fs::write(eth0.join("address"), "b8:27:eb:00:11:22\n").unwrap();
+    fs::write(eth0.join("statistics/rx_bytes"), "2048\n").unwrap();
+    fs::write(eth0.join("statistics/tx_bytes"), "1024\n").unwrap();
+
+    assert_eq!(wired::names_at(&root), vec!["eth0".to_string()]);
+    let iface = wired::read_at(&root, "eth0");
+    assert_eq!(iface.state, "up");
+    assert!(iface.carrier);
+    assert_eq!(iface.speed, Some(1000));
+    assert_eq!(iface.mac, Some("b8:27:eb:00:11:22".to_string()));
+    assert_eq!(iface.rx_bytes, 2048);
+    assert_eq!(iface.tx_bytes, 1024);
+
+    // without a link the speed is -1
+    fs::write(eth0.join("carrier"), "0\n").unwrap();
+    fs::write(eth0.join("speed"), "-1\n").unwrap();
+    let iface = wired::read_at(&root, "eth0");
+    assert!(!iface.carrier);
+    assert_eq!(iface.speed, None);
+
+    let _ = fs::remove_dir_all(&root);
+}
+
 // FILE TESTS
 
 #[test]
@@ -469,7 +2469,7 @@ fn invalid_path() {
 
 #[test]
 fn invalid_get_request() {
-    let client = Client::new(rocket()).unwrap();
+    let client = test_client();
 
     // try to get a path that doesn't exist
     let mut res = client
@@ -0,0 +1,547 @@
+//! HTTPS certificates for the admin interface.
+//!
+//! On first run, a self-signed certificate is generated for the local network
+//! name of the device (eg. `peach.local`); its SHA-256 fingerprint is shown on
+//! `/network/dns` so that it can be compared with the one the browser reports.
+//! The certificate is generated again when the hostname changes or when it is
+//! about to expire.
+//!
+//! When an external domain is configured, a certificate for it is obtained
+//! from an ACME server (Let's Encrypt by default) using `certbot` with the
+//! `http-01` challenge. `certbot` runs as the `peach-web` user and keeps its
+//! account and certificates in the certificate directory, while nginx serves
+//! the challenge files from the webroot. The ACME directory URL can be
+//! configured with the `PEACH_WEB_ACME_DIRECTORY` environment variable, and a
+//! CA bundle to trust for it (eg. the root of a local Pebble server) with
+//! `PEACH_WEB_ACME_CA_BUNDLE`.
+//!
+//! nginx serves whichever certificate is in use through the
+//! `/etc/nginx/snippets/peach-web-tls.conf` snippet, which also makes it
+//! listen on port 443 and redirects plain HTTP requests to HTTPS, so that
+//! passwords are not sent in the clear. ACME challenges, captive-portal probes
+//! and the first-run pages (with the static files they use) stay on plain
+//! HTTP: probes must be answered over HTTP, and devices joining the access
+//! point are sent to `http://<ap address>/setup`, which the certificate does
+//! not cover. nginx is reloaded whenever a certificate is generated or
+//! obtained. The certificates are checked periodically, and the outcome of
+//! the last ACME request is kept in the data store.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, thread, time::Duration};
+
+use chrono::{TimeZone, Utc};
+use log::{info, warn};
+use nest::Value;
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName};
+use openssl::x509::{X509NameBuilder, X509NameRef, X509Ref, X509};
+use peach_lib::config_manager;
+use serde::Serialize;
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::hostname;
+use crate::store::DataStore;
+
+/// Default certificate directory
+const TLS_DIR: &str = "/var/lib/peachcloud/tls";
+/// Environment variable used to override the certificate directory
+const TLS_DIR_VAR: &str = "PEACH_WEB_TLS_DIR";
+/// Default ACME directory URL
+const ACME_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+/// Environment variable used to override the ACME directory URL
+const ACME_DIRECTORY_VAR: &str = "PEACH_WEB_ACME_DIRECTORY";
+/// Environment variable naming a CA bundle to trust for the ACME server
+const ACME_CA_BUNDLE_VAR: &str = "PEACH_WEB_ACME_CA_BUNDLE";
+/// Path of the nginx snippet enabling HTTPS
+pub const NGINX_TLS: &str = "/etc/nginx/snippets/peach-web-tls.conf";
+/// Paths which are not redirected to HTTPS: ACME challenges, the first-run
+/// pages and their static files, and the captive-portal probes answered in
+/// `routes`
+const HTTP_PATHS: &str = "^/(\\.well-known/acme-challenge/|setup(/|$)|(css|js|icons|images)/|\
+    (generate_204|gen_204|hotspot-detect\\.html|library/test/success\\.html|connecttest\\.txt|\
+    ncsi\\.txt|success\\.txt)$)";
+/// Name of the certificate lineage managed by certbot
+const CERT_NAME: &str = "peach-web";
+/// Validity of a self-signed certificate (in days)
+const SELF_SIGNED_DAYS: u32 = 3650;
+/// Remaining validity below which a certificate is renewed (in days)
+const RENEW_DAYS: i64 = 30;
+/// Time to wait after a failed ACME request before trying again (in seconds)
+const ACME_RETRY: i64 = 3600;
+/// Default interval between certificate checks (in seconds)
+const CHECK_INTERVAL: u64 = 43_200;
+/// Path of the ACME state within the data store
+const ACME_FILE: [&str; 2] = ["tls", "acme"];
+
+/// Details of a certificate
+#[derive(Debug, Serialize)]
+pub struct CertInfo {
+    pub subject: String,     // common name
+    pub names: Vec<String>,  // DNS names (subject alternative names)
+    pub issuer: String,      // common name (or organisation) of the issuer
+    pub fingerprint: String, // SHA-256 fingerprint (colon-separated hex)
+    pub not_after: i64,      // unix timestamp
+    pub expires: String,     // expiry date (UTC)
+}
+
+/// Outcome of the ACME requests. Timestamps are unix times in seconds (0 if
+/// unset).
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct AcmeState {
+    pub domain: String,     // domain of the last request
+    pub last_attempt: i64,  // last request
+    pub last_success: i64,  // last certificate obtained
+    pub last_error: String, // failure of the last request (if it failed)
+}
+
+impl AcmeState {
+    /// Retrieve the ACME state from the store
+    fn get(store: &DataStore) -> Result<AcmeState, PeachWebError> {
+        Ok(AcmeState {
+            domain: store.get_string(&["tls", "acme", "domain"])?,
+            last_attempt: store.get_i64(&["tls", "acme", "last_attempt"])?,
+            last_success: store.get_i64(&["tls", "acme", "last_success"])?,
+            last_error: store.get_string(&["tls", "acme", "last_error"])?,
+        })
+    }
+
+    /// Store the ACME state
+    fn set(&self, store: &DataStore) -> Result<(), PeachWebError> {
+        store.set_values(
+            &ACME_FILE,
+            vec![
+                ("domain", Value::String(self.domain.clone())),
+                ("last_attempt", Value::Int(self.last_attempt)),
+                ("last_success", Value::Int(self.last_success)),
+                ("last_error", Value::String(self.last_error.clone())),
+            ],
+        )
+    }
+}
+
+/// Certificates, the one served by nginx and the state of the ACME requests
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub active: String, // "acme", "self-signed" or "none"
+    pub self_signed: Option<CertInfo>,
+    pub acme: Option<CertInfo>,
+    pub domain: String, // external domain (if any)
+    pub acme_directory: String,
+    pub state: AcmeState,
+}
+
+/// Certificate directory, as configured
+pub fn tls_dir() -> PathBuf {
+    env::var_os(TLS_DIR_VAR)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(TLS_DIR))
+}
+
+/// ACME directory URL, as configured
+pub fn acme_directory() -> String {
+    env::var(ACME_DIRECTORY_VAR).unwrap_or_else(|_| ACME_DIRECTORY.to_string())
+}
+
+// paths of the self-signed certificate and key
+fn self_signed_paths(dir: &Path) -> (PathBuf, PathBuf) {
+    (dir.join("self-signed.crt"), dir.join("self-signed.key"))
+}
+
+// paths of the certificate (with chain) and key obtained by certbot
+fn acme_paths(dir: &Path) -> (PathBuf, PathBuf) {
+    let live = dir.join("letsencrypt").join("live").join(CERT_NAME);
+    (live.join("fullchain.pem"), live.join("privkey.pem"))
+}
+
+/// Generate a self-signed certificate (and P-256 key) for the given name,
+/// also valid for its `www.` subdomain
+pub fn generate_self_signed(name: &str, days: u32) -> Result<(X509, PKey<Private>), ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut subject = X509NameBuilder::new()?;
+    subject.append_entry_by_nid(Nid::COMMONNAME, name)?;
+    let subject = subject.build();
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial.to_asn1_integer()?)?;
+    builder.set_subject_name(&subject)?;
+    builder.set_issuer_name(&subject)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(days)?)?;
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    let names = SubjectAlternativeName::new()
+        .dns(name)
+        .dns(&format!("www.{}", name))
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(names)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build(), key))
+}
+
+// first common name (or organisation) of an x509 name
+fn common_name(name: &X509NameRef) -> String {
+    name.entries_by_nid(Nid::COMMONNAME)
+        .chain(name.entries_by_nid(Nid::ORGANIZATIONNAME))
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|data| data.to_string())
+        .unwrap_or_default()
+}
+
+// convert an asn1 time to a unix timestamp
+fn unix_time(time: &Asn1TimeRef) -> Result<i64, ErrorStack> {
+    let diff = Asn1Time::from_unix(0)?.diff(time)?;
+    Ok(i64::from(diff.days) * 86_400 + i64::from(diff.secs))
+}
+
+/// Read the details of a certificate
+pub fn cert_info(cert: &X509Ref) -> Result<CertInfo, ErrorStack> {
+    let names = cert
+        .subject_alt_names()
+        .map(|names| {
+            names
+                .iter()
+                .filter_map(|name| name.dnsname().map(|dns| dns.to_string()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let fingerprint = cert
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(":");
+    let not_after = unix_time(cert.not_after())?;
+
+    Ok(CertInfo {
+        subject: common_name(cert.subject_name()),
+        names,
+        issuer: common_name(cert.issuer_name()),
+        fingerprint,
+        not_after,
+        expires: Utc.timestamp(not_after, 0).format("%Y-%m-%d").to_string(),
+    })
+}
+
+// read the details of the (first) certificate in a pem file, if there is one
+fn read_cert(path: &Path) -> Result<Option<CertInfo>, PeachWebError> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let pem = fs::read(path).map_err(|source| PeachWebError::CertificateFile {
+        path: path.display().to_string(),
+        source,
+    })?;
+    let cert = X509::from_pem(&pem)?;
+
+    Ok(Some(cert_info(&cert)?))
+}
+
+/// Whether a certificate is missing, not valid for the given name or about to
+/// expire
+pub fn needs_renewal(info: Option<&CertInfo>, name: &str, now: i64) -> bool {
+    match info {
+        Some(info) => {
+            !info.names.iter().any(|n| n == name) || info.not_after - now < RENEW_DAYS * 86_400
+        }
+        None => true,
+    }
+}
+
+/// Whether a certificate should be requested from the ACME server: it is
+/// missing, not valid for the domain or about to expire, and the last request
+/// for the domain did not fail in the last hour
+pub fn acme_due(info: Option<&CertInfo>, domain: &str, state: &AcmeState, now: i64) -> bool {
+    if domain.is_empty() || !needs_renewal(info, domain, now) {
+        return false;
+    }
+    let failed_recently = state.domain == domain
+        && !state.last_error.is_empty()
+        && now - state.last_attempt < ACME_RETRY;
+
+    !failed_recently
+}
+
+// write a file which only peach-web (and root) may read
+fn write_private(path: &Path, contents: &[u8], mode: u32) -> Result<(), PeachWebError> {
+    let err = |source| PeachWebError::CertificateFile {
+        path: path.display().to_string(),
+        source,
+    };
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)
+        .map_err(err)?;
+    file.write_all(contents).map_err(err)
+}
+
+/// Generate the self-signed certificate in the given directory if it is
+/// missing, not valid for the given name or about to expire. Returns whether a
+/// new certificate was generated.
+pub fn ensure_self_signed_at(dir: &Path, name: &str, now: i64) -> Result<bool, PeachWebError> {
+    let (cert_path, key_path) = self_signed_paths(dir);
+    let current = read_cert(&cert_path).unwrap_or_else(|e| {
+        warn!("Failed to read self-signed certificate: {}", e);
+        None
+    });
+    if !needs_renewal(current.as_ref(), name, now) {
+        return Ok(false);
+    }
+
+    info!("Generating self-signed certificate for {}", name);
+    fs::create_dir_all(dir).map_err(|source| PeachWebError::CertificateFile {
+        path: dir.display().to_string(),
+        source,
+    })?;
+    let (cert, key) = generate_self_signed(name, SELF_SIGNED_DAYS)?;
+    write_private(&key_path, &key.private_key_to_pem_pkcs8()?, 0o600)?;
+    write_private(&cert_path, &cert.to_pem()?, 0o644)?;
+
+    Ok(true)
+}
+
+/// Arguments of the `certbot` command obtaining a certificate for a domain
+pub fn certbot_args(domain: &str, directory: &str, dir: &Path) -> Vec<String> {
+    let path = |name: &str| dir.join(name).display().to_string();
+    let (webroot, config, work, logs) = (
+        path("webroot"),
+        path("letsencrypt"),
+        path("work"),
+        path("logs"),
+    );
+    let args = vec![
+        "certonly",
+        "--non-interactive",
+        "--agree-tos",
+        "--register-unsafely-without-email",
+        "--webroot",
+        "--webroot-path",
+        &webroot,
+        "--cert-name",
+        CERT_NAME,
+        "--domain",
+        domain,
+        "--server",
+        directory,
+        "--config-dir",
+        &config,
+        "--work-dir",
+        &work,
+        "--logs-dir",
+        &logs,
+        "--force-renewal",
+    ];
+
+    args.into_iter().map(|arg| arg.to_string()).collect()
+}
+
+/// Request a certificate for a domain from the ACME server
+fn request_acme(domain: &str) -> Result<(), PeachWebError> {
+    info!(
+        "Requesting certificate for {} from {}",
+        domain,
+        acme_directory()
+    );
+    let mut cmd = Command::new("certbot");
+    cmd.args(certbot_args(domain, &acme_directory(), &tls_dir()));
+    if let Some(bundle) = env::var_os(ACME_CA_BUNDLE_VAR) {
+        cmd.env("REQUESTS_CA_BUNDLE", bundle);
+    }
+    let output = cmd.output().map_err(|e| PeachWebError::Acme {
+        domain: domain.to_string(),
+        msg: e.to_string(),
+    })?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        // certbot explains the failure in its last lines of output
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let msg = stderr
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .last()
+            .unwrap_or("certbot failed")
+            .to_string();
+        Err(PeachWebError::Acme {
+            domain: domain.to_string(),
+            msg,
+        })
+    }
+}
+
+/// Request a certificate for a domain, recording the outcome in the store
+fn obtain(store: &DataStore, domain: &str) -> Result<(), PeachWebError> {
+    let now = Utc::now().timestamp();
+    let mut state = AcmeState::get(store)?;
+    state.domain = domain.to_string();
+    state.last_attempt = now;
+    let result = request_acme(domain);
+    match &result {
+        Ok(_) => {
+            state.last_success = now;
+            state.last_error = String::new();
+        }
+        Err(e) => state.last_error = e.to_string(),
+    }
+    state.set(store)?;
+
+    result
+}
+
+/// Render the nginx snippet serving a certificate over HTTPS and redirecting
+/// plain HTTP requests to HTTPS, except for the paths which must stay on
+/// plain HTTP (see `HTTP_PATHS`)
+pub fn render_snippet(cert: &Path, key: &Path) -> String {
+    format!(
+        "listen 443 ssl;\n\
+         ssl_certificate {};\n\
+         ssl_certificate_key {};\n\
+         set $https_redirect 0;\n\
+         if ($scheme = http) {{ set $https_redirect 1; }}\n\
+         if ($uri ~ \"{}\") {{ set $https_redirect 0; }}\n\
+         if ($https_redirect) {{ return 301 https://$host$request_uri; }}\n",
+        cert.display(),
+        key.display(),
+        HTTP_PATHS
+    )
+}
+
+// serve the acme certificate if it is valid for the domain, and the
+// self-signed certificate otherwise, reloading nginx if this changes or if
+// a certificate was `renewed` (written to the same path)
+fn install(
+    domain: &str,
+    acme: Option<&CertInfo>,
+    renewed: bool,
+    now: i64,
+) -> Result<(), PeachWebError> {
+    let dir = tls_dir();
+    let acme_valid = acme.map_or(false, |info| {
+        !domain.is_empty() && info.names.iter().any(|n| n == domain) && info.not_after > now
+    });
+    let (cert, key) = if acme_valid {
+        acme_paths(&dir)
+    } else {
+        self_signed_paths(&dir)
+    };
+    let snippet = render_snippet(&cert, &key);
+    let changed = fs::read_to_string(NGINX_TLS).ok().as_deref() != Some(snippet.as_str());
+    if changed {
+        device::write_file(NGINX_TLS, &snippet)?;
+    }
+    if changed || renewed {
+        device::run_privileged("systemctl", &["reload", "nginx"])?;
+    }
+
+    Ok(())
+}
+
+// retrieve the external domain from the peach configuration
+fn external_domain() -> Result<String, PeachWebError> {
+    let config = config_manager::load_peach_config()?;
+    Ok(config.external_domain.trim().to_string())
+}
+
+/// Check the certificates, generating a self-signed certificate and
+/// requesting one from the ACME server when they are due, and serve the best
+/// one. With `force`, a certificate is requested for the external domain even
+/// if the current one is still valid.
+pub fn update(force: bool) -> Result<(), PeachWebError> {
+    let dir = tls_dir();
+    let now = Utc::now().timestamp();
+    let generated = ensure_self_signed_at(&dir, &hostname::mdns_name(), now)?;
+
+    let domain = external_domain()?;
+    if force && domain.is_empty() {
+        return Err(PeachWebError::InvalidDomain {
+            msg: "Set an external domain to request a certificate".to_string(),
+        });
+    }
+    let acme_path = acme_paths(&dir).0;
+    let mut acme = read_cert(&acme_path)?;
+    if !domain.is_empty() {
+        let store = DataStore::open()?;
+        let state = AcmeState::get(&store)?;
+        if force || acme_due(acme.as_ref(), &domain, &state, now) {
+            let result = obtain(&store, &domain);
+            acme = read_cert(&acme_path)?;
+            install(&domain, acme.as_ref(), generated || result.is_ok(), now)?;
+            return result;
+        }
+    }
+
+    install(&domain, acme.as_ref(), generated, now)
+}
+
+/// Check the certificates in a separate thread (eg. after the external domain
+/// or the hostname has changed)
+pub fn spawn_update() {
+    thread::spawn(|| {
+        if let Err(e) = update(false) {
+            warn!("Failed to update certificates: {}", e);
+        }
+    });
+}
+
+/// Retrieve the certificates and the state of the ACME requests
+pub fn get_status() -> Result<Status, PeachWebError> {
+    let dir = tls_dir();
+    let self_signed = read_cert(&self_signed_paths(&dir).0)?;
+    let acme = read_cert(&acme_paths(&dir).0)?;
+    let domain = external_domain()?;
+    let state = AcmeState::get(&DataStore::open()?)?;
+
+    let snippet = fs::read_to_string(NGINX_TLS).unwrap_or_default();
+    let active = if snippet.contains("letsencrypt") {
+        "acme"
+    } else if snippet.contains("self-signed") {
+        "self-signed"
+    } else {
+        "none"
+    };
+
+    Ok(Status {
+        active: active.to_string(),
+        self_signed,
+        acme,
+        domain,
+        acme_directory: acme_directory(),
+        state,
+    })
+}
+
+/// Periodically check the certificates, renewing them when they are due.
+///
+/// The interval (in seconds) can be configured with the
+/// `PEACH_WEB_TLS_INTERVAL` environment variable.
+pub fn run() {
+    let interval = crate::interval("PEACH_WEB_TLS_INTERVAL", CHECK_INTERVAL);
+
+    loop {
+        if let Err(e) = update(false) {
+            warn!("Failed to update certificates: {}", e);
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
@@ -0,0 +1,532 @@
+//! Saved WiFi network management beyond `peach-network`'s WPA-PSK support.
+//!
+//! Networks which are hidden, open, WPA3-SAE or WPA-Enterprise (EAP-PEAP /
+//! EAP-TTLS) are added via `wpa_cli`, since `network_client::add` only handles
+//! visible WPA-PSK networks. The certificate of a WPA-Enterprise
+//! authentication server is validated against the system CA certificates and
+//! the server domain given with the credentials; the domain is required for
+//! EAP-TTLS, whose inner (PAP) authentication sends the password as is.
+//!
+//! wpa_supplicant prefers the available network with the highest `priority`
+//! value and skips networks which are disabled. Both are set for the saved
+//! networks of `wlan0` via `wpa_cli` and written to the wpa_supplicant
+//! configuration so they persist across reboots.
+//!
+//! The credentials of a saved network are read back from the wpa_supplicant
+//! configuration file so that the network can be shared via QR code.
+//!
+//! Scans are requested and their results read via `wpa_cli` (rather than
+//! `network_client::available_networks`) for the site survey, which needs
+//! the BSSID, frequency and security of every access point in range.
+
+use log::{info, warn};
+use serde::Serialize;
+
+use peach_lib::network_client;
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::forms::WiFi;
+
+/// WiFi client interface
+const WLAN_IFACE: &str = "wlan0";
+/// CA certificates used to validate WPA-Enterprise authentication servers
+const CA_CERTS: &str = "/etc/ssl/certs/ca-certificates.crt";
+/// Path of the wpa_supplicant configuration file for the client interface
+pub const WPA_SUPPLICANT_CONF: &str = "/etc/wpa_supplicant/wpa_supplicant-wlan0.conf";
+
+/// Saved WiFi network, as known to wpa_supplicant
+#[derive(Debug, Serialize)]
+pub struct SavedNetwork {
+    pub id: String,
+    pub ssid: String,
+    pub priority: i32,
+    pub auto_connect: bool, // false if the network is disabled
+    pub current: bool,      // true if currently connected
+}
+
+// run a wpa_cli command against the wifi client interface, treating a `FAIL`
+// response as an error. options are ended with `--` so that the arguments of
+// the command cannot be taken as options (eg. `-a <action script>`).
+fn wpa_cli(args: &[&str]) -> Result<String, PeachWebError> {
+    let mut cli_args = vec!["-i", WLAN_IFACE, "--"];
+    cli_args.extend_from_slice(args);
+    let output = device::run_privileged("wpa_cli", &cli_args)?;
+    if output.trim() == "FAIL" {
+        return Err(PeachWebError::SystemCommand {
+            cmd: format!("wpa_cli {}", args.join(" ")),
+            msg: "wpa_supplicant rejected the command".to_string(),
+        });
+    }
+
+    Ok(output)
+}
+
+/// Parse the output of `wpa_cli list_networks` into the id, SSID, enabled
+/// and current flags of each network
+pub fn parse_list(output: &str) -> Vec<(String, String, bool, bool)> {
+    output
+        .lines()
+        // skip the header line
+        .filter(|line| !line.starts_with("network id"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 4 || fields[0].parse::<u32>().is_err() {
+                return None;
+            }
+            let flags = fields[3];
+            Some((
+                fields[0].to_string(),
+                fields[1].to_string(),
+                !flags.contains("[DISABLED]"),
+                flags.contains("[CURRENT]"),
+            ))
+        })
+        .collect()
+}
+
+/// Retrieve the saved networks, ordered from highest to lowest priority
+/// (networks with equal priority are kept in the order they were saved)
+pub fn saved_networks() -> Result<Vec<SavedNetwork>, PeachWebError> {
+    let mut networks = Vec::new();
+    for (id, ssid, auto_connect, current) in parse_list(&wpa_cli(&["list_networks"])?) {
+        let priority = wpa_cli(&["get_network", &id, "priority"])?
+            .trim()
+            .parse()
+            .unwrap_or(0);
+        networks.push(SavedNetwork {
+            id,
+            ssid,
+            priority,
+            auto_connect,
+            current,
+        });
+    }
+    networks.sort_by(|a, b| b.priority.cmp(&a.priority));
+
+    Ok(networks)
+}
+
+// find the id of a saved network
+fn find<'a>(networks: &'a [SavedNetwork], ssid: &str) -> Result<&'a SavedNetwork, PeachWebError> {
+    networks
+        .iter()
+        .find(|network| network.ssid == ssid)
+        .ok_or_else(|| PeachWebError::UnknownNetwork {
+            ssid: ssid.to_string(),
+        })
+}
+
+/// Calculate priorities for networks listed from most to least preferred:
+/// the first network gets the highest value and the last gets 1
+pub fn priorities(count: usize) -> Vec<i32> {
+    (1..=count as i32).rev().collect()
+}
+
+/// Set the order in which saved networks are preferred. `ssids` lists the
+/// networks from most to least preferred; saved networks which are not
+/// listed keep their relative order after the listed ones.
+pub fn set_order(ssids: &[String]) -> Result<(), PeachWebError> {
+    let networks = saved_networks()?;
+    let mut ordered = Vec::new();
+    for ssid in ssids {
+        let network = find(&networks, ssid)?;
+        if !ordered.iter().any(|n: &&SavedNetwork| n.id == network.id) {
+            ordered.push(network);
+        }
+    }
+    for network in &networks {
+        if !ordered.iter().any(|n| n.id == network.id) {
+            ordered.push(network);
+        }
+    }
+
+    info!("Updating saved WiFi network priorities");
+    for (network, priority) in ordered.iter().zip(priorities(ordered.len())) {
+        if network.priority != priority {
+            wpa_cli(&[
+                "set_network",
+                &network.id,
+                "priority",
+                &priority.to_string(),
+            ])?;
+        }
+    }
+    wpa_cli(&["save_config"])?;
+
+    Ok(())
+}
+
+/// Move a saved network one place up (more preferred) or down (less
+/// preferred) in the priority order
+pub fn move_network(ssid: &str, up: bool) -> Result<(), PeachWebError> {
+    let mut ssids: Vec<String> = saved_networks()?.into_iter().map(|n| n.ssid).collect();
+    let index = match ssids.iter().position(|s| s == ssid) {
+        Some(index) => index,
+        None => {
+            return Err(PeachWebError::UnknownNetwork {
+                ssid: ssid.to_string(),
+            })
+        }
+    };
+    if up && index > 0 {
+        ssids.swap(index, index - 1);
+    } else if !up && index + 1 < ssids.len() {
+        ssids.swap(index, index + 1);
+    }
+
+    set_order(&ssids)
+}
+
+/// Enable or disable automatic connection to a saved network. Disabling the
+/// current network disconnects from it.
+pub fn set_auto_connect(ssid: &str, enabled: bool) -> Result<(), PeachWebError> {
+    let networks = saved_networks()?;
+    let network = find(&networks, ssid)?;
+    info!(
+        "{} auto-connect for WiFi network {}",
+        if enabled { "Enabling" } else { "Disabling" },
+        ssid
+    );
+    let cmd = if enabled {
+        "enable_network"
+    } else {
+        "disable_network"
+    };
+    wpa_cli(&[cmd, &network.id])?;
+    wpa_cli(&["save_config"])?;
+
+    Ok(())
+}
+
+/// Access point found by a scan
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ScanResult {
+    pub bssid: String,
+    pub frequency: u32, // MHz
+    pub signal: i32,    // dBm
+    pub flags: String,  // eg. "[WPA2-PSK-CCMP][ESS]"
+    pub ssid: String,   // empty for hidden networks
+}
+
+/// Parse the output of `wpa_cli scan_results`
+pub fn parse_scan_results(output: &str) -> Vec<ScanResult> {
+    output
+        .lines()
+        // skip the header line
+        .filter(|line| !line.starts_with("bssid"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            Some(ScanResult {
+                bssid: fields[0].to_string(),
+                frequency: fields[1].parse().ok()?,
+                signal: fields[2].parse().ok()?,
+                flags: fields[3].to_string(),
+                ssid: fields.get(4).unwrap_or(&"").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Describe the security of an access point from its scan flags
+pub fn security_label(flags: &str) -> &'static str {
+    if flags.contains("EAP") {
+        "WPA-Enterprise"
+    } else if flags.contains("SAE") && flags.contains("PSK") {
+        "WPA2/WPA3"
+    } else if flags.contains("SAE") {
+        "WPA3"
+    } else if flags.contains("WPA2") || flags.contains("RSN") {
+        "WPA2"
+    } else if flags.contains("WPA") {
+        "WPA"
+    } else if flags.contains("WEP") {
+        "WEP"
+    } else {
+        "Open"
+    }
+}
+
+/// Request a new scan of the access points in range. The results are
+/// available from `scan_results` a few seconds later.
+pub fn scan() -> Result<(), PeachWebError> {
+    wpa_cli(&["scan"])?;
+
+    Ok(())
+}
+
+/// Retrieve the results of the latest scan
+pub fn scan_results() -> Result<Vec<ScanResult>, PeachWebError> {
+    Ok(parse_scan_results(&wpa_cli(&["scan_results"])?))
+}
+
+/// WiFi network security type
+#[derive(Debug, PartialEq)]
+pub enum Security {
+    WpaPsk,
+    Open,
+    Sae,
+    Peap,
+    Ttls,
+}
+
+impl Security {
+    /// Parse the security type from a form value, defaulting to WPA-PSK
+    pub fn parse(security: Option<&str>) -> Result<Security, PeachWebError> {
+        match security {
+            None | Some("") | Some("wpa-psk") => Ok(Security::WpaPsk),
+            Some("open") => Ok(Security::Open),
+            Some("sae") => Ok(Security::Sae),
+            Some("peap") => Ok(Security::Peap),
+            Some("ttls") => Ok(Security::Ttls),
+            Some(other) => Err(PeachWebError::InvalidWiFi {
+                msg: format!("Unknown security type: {}", other),
+            }),
+        }
+    }
+}
+
+// quote a string value for wpa_supplicant (which does not support escapes)
+fn quoted(field: &str, value: &str) -> Result<String, PeachWebError> {
+    if value.chars().any(char::is_control) {
+        return Err(PeachWebError::InvalidWiFi {
+            msg: format!("The {} must not contain control characters", field),
+        });
+    }
+    Ok(format!("\"{}\"", value))
+}
+
+// filter out empty optional form values
+fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.is_empty())
+}
+
+/// Validate the credentials and build the wpa_supplicant network settings
+/// (as `set_network` variable and value pairs)
+pub fn network_settings(wifi: &WiFi) -> Result<Vec<(&'static str, String)>, PeachWebError> {
+    let invalid = |msg: &str| {
+        Err(PeachWebError::InvalidWiFi {
+            msg: msg.to_string(),
+        })
+    };
+    if wifi.ssid.is_empty() || wifi.ssid.len() > 32 {
+        return invalid("SSID must be between 1 and 32 bytes long");
+    }
+
+    // the ssid is hex-encoded so any characters are allowed
+    let ssid_hex: String = wifi.ssid.bytes().map(|b| format!("{:02x}", b)).collect();
+    let mut settings = vec![("ssid", ssid_hex)];
+    if wifi.hidden {
+        // probe for the network, since it does not broadcast its ssid
+        settings.push(("scan_ssid", "1".to_string()));
+    }
+
+    match Security::parse(wifi.security.as_deref())? {
+        Security::Open => settings.push(("key_mgmt", "NONE".to_string())),
+        Security::WpaPsk => {
+            let len = wifi.pass.len();
+            let hex = len == 64 && wifi.pass.chars().all(|c| c.is_ascii_hexdigit());
+            if !hex && !(8..=63).contains(&len) {
+                return invalid("Password must be between 8 and 63 characters long");
+            }
+            settings.push(("key_mgmt", "WPA-PSK".to_string()));
+            // a 64 digit hex psk is given unquoted
+            let psk = if hex {
+                wifi.pass.clone()
+            } else {
+                quoted("password", &wifi.pass)?
+            };
+            settings.push(("psk", psk));
+        }
+        Security::Sae => {
+            if wifi.pass.is_empty() {
+                return invalid("A password is required for WPA3 networks");
+            }
+            settings.push(("key_mgmt", "SAE".to_string()));
+            settings.push(("sae_password", quoted("password", &wifi.pass)?));
+            // management frame protection is mandatory for wpa3
+            settings.push(("ieee80211w", "2".to_string()));
+        }
+        security => {
+            let identity = match non_empty(&wifi.identity) {
+                Some(identity) => identity,
+                None => {
+                    return invalid(
+                        "An identity (username) is required for WPA-Enterprise networks",
+                    )
+                }
+            };
+            if wifi.pass.is_empty() {
+                return invalid("A password is required for WPA-Enterprise networks");
+            }
+            let (eap, phase2) = match security {
+                Security::Ttls => ("TTLS", "auth=PAP"),
+                _ => ("PEAP", "auth=MSCHAPV2"),
+            };
+            settings.push(("key_mgmt", "WPA-EAP".to_string()));
+            settings.push(("eap", eap.to_string()));
+            settings.push(("identity", quoted("identity", identity)?));
+            if let Some(anonymous) = non_empty(&wifi.anonymous_identity) {
+                settings.push((
+                    "anonymous_identity",
+                    quoted("anonymous identity", anonymous)?,
+                ));
+            }
+            settings.push(("password", quoted("password", &wifi.pass)?));
+            settings.push(("phase2", quoted("phase 2 method", phase2)?));
+            // without a server domain to validate its certificate against, the
+            // credentials are sent to any access point using the same ssid
+            match non_empty(&wifi.server_domain) {
+                Some(domain) => {
+                    let valid = domain
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+                    if !valid {
+                        return invalid("Server domain must be a domain name (eg. example.edu)");
+                    }
+                    settings.push(("ca_cert", quoted("CA certificate path", CA_CERTS)?));
+                    settings.push(("domain_suffix_match", quoted("server domain", domain)?));
+                }
+                None if security == Security::Ttls => {
+                    return invalid(
+                        "A server domain is required for WPA-Enterprise (TTLS) networks",
+                    )
+                }
+                None => warn!(
+                    "Adding WiFi network {} without validating the authentication server",
+                    wifi.ssid
+                ),
+            }
+        }
+    }
+
+    Ok(settings)
+}
+
+/// Add WiFi credentials for `wlan0`. Visible WPA-PSK networks are added via
+/// `peach-network`; all others are configured directly via `wpa_cli`.
+pub fn add(wifi: &WiFi) -> Result<(), PeachWebError> {
+    let settings = network_settings(wifi)?;
+    if Security::parse(wifi.security.as_deref())? == Security::WpaPsk && !wifi.hidden {
+        network_client::add(&wifi.ssid, &wifi.pass)?;
+        return Ok(());
+    }
+
+    info!("Adding WiFi network {}", wifi.ssid);
+    let id = wpa_cli(&["add_network"])?.trim().to_string();
+    for (variable, value) in &settings {
+        if let Err(err) = wpa_cli(&["set_network", &id, variable, value]) {
+            // do not leave a partially configured network behind
+            let _ = wpa_cli(&["remove_network", &id]);
+            return Err(err);
+        }
+    }
+    wpa_cli(&["enable_network", &id])?;
+    wpa_cli(&["save_config"])?;
+
+    Ok(())
+}
+
+// decode a wpa_supplicant string value, which is either quoted or hex-encoded
+fn unquote(value: &str) -> Option<String> {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return Some(value[1..value.len() - 1].to_string());
+    }
+    if value.len() % 2 != 0 {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect();
+    bytes.and_then(|bytes| String::from_utf8(bytes).ok())
+}
+
+/// Parse the credentials of a saved network from the contents of a
+/// wpa_supplicant configuration file. The plaintext passphrase is used when
+/// available; otherwise a hashed WPA-PSK is returned as 64 hex digits.
+pub fn parse_credentials(conf: &str, ssid: &str) -> Option<WiFi> {
+    let mut block: Option<Vec<(String, String)>> = None;
+    for line in conf.lines() {
+        let line = line.trim();
+        if line.starts_with("network={") {
+            block = Some(Vec::new());
+            continue;
+        }
+        if line == "}" {
+            if let Some(settings) = block.take() {
+                if let Some(wifi) = credentials(&settings, ssid) {
+                    return Some(wifi);
+                }
+            }
+            continue;
+        }
+        if let Some(settings) = block.as_mut() {
+            let mut parts = line.splitn(2, '=');
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                settings.push((key.trim().to_string(), value.trim().to_string()));
+            }
+        }
+    }
+
+    None
+}
+
+// build the credentials from the settings of a network block, if it is the
+// network with the given ssid
+fn credentials(settings: &[(String, String)], ssid: &str) -> Option<WiFi> {
+    let get = |key: &str| {
+        settings
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    };
+    if get("ssid").and_then(unquote).as_deref() != Some(ssid) {
+        return None;
+    }
+
+    let mut wifi = WiFi {
+        ssid: ssid.to_string(),
+        hidden: get("scan_ssid") == Some("1"),
+        ..WiFi::default()
+    };
+    let key_mgmt = get("key_mgmt").unwrap_or("WPA-PSK");
+    let security = if key_mgmt.contains("WPA-EAP") {
+        wifi.identity = get("identity").and_then(unquote);
+        wifi.anonymous_identity = get("anonymous_identity").and_then(unquote);
+        wifi.pass = get("password").and_then(unquote).unwrap_or_default();
+        match get("eap") {
+            Some(eap) if eap.contains("TTLS") => "ttls",
+            _ => "peap",
+        }
+    } else if key_mgmt.contains("SAE") {
+        wifi.pass = get("sae_password").and_then(unquote).unwrap_or_default();
+        "sae"
+    } else if key_mgmt == "NONE" {
+        "open"
+    } else {
+        // wpa_passphrase leaves the plaintext passphrase in a comment
+        wifi.pass = match get("psk") {
+            Some(psk) if psk.starts_with('"') => unquote(psk).unwrap_or_default(),
+            psk => get("#psk")
+                .and_then(unquote)
+                .or_else(|| psk.map(str::to_string))
+                .unwrap_or_default(),
+        };
+        "wpa-psk"
+    };
+    wifi.security = Some(security.to_string());
+
+    Some(wifi)
+}
+
+/// Retrieve the credentials of a saved network
+pub fn get_credentials(ssid: &str) -> Result<WiFi, PeachWebError> {
+    let conf = device::read_file(WPA_SUPPLICANT_CONF)?;
+    parse_credentials(&conf, ssid).ok_or_else(|| PeachWebError::UnknownNetwork {
+        ssid: ssid.to_string(),
+    })
+}
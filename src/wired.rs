@@ -0,0 +1,120 @@
+//! Wired (ethernet) network interfaces.
+//!
+//! Unlike `wlan0` and `ap0`, wired interfaces are not managed by
+//! `peach-network`. Their link state, speed, MAC address and traffic counters
+//! are read from sysfs (`/sys/class/net/<iface>`), so that any physical,
+//! non-wireless interface (eg. `eth0` or a USB ethernet adapter) is included.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use peach_lib::network_client;
+
+/// Root of the network interface tree in sysfs
+const SYSFS_NET: &str = "/sys/class/net";
+
+/// State of a wired network interface
+#[derive(Debug, Serialize)]
+pub struct WiredInterface {
+    pub name: String,
+    pub state: String,      // operational state (eg. "up", "down")
+    pub carrier: bool,      // true if a cable is connected to a live link
+    pub speed: Option<u32>, // link speed in Mb/s (unknown without a link)
+    pub mac: Option<String>,
+    pub ip: Option<String>,
+    pub rx_bytes: u64, // bytes received since boot
+    pub tx_bytes: u64, // bytes transmitted since boot
+}
+
+// read a single-line sysfs attribute (unreadable attributes are ignored)
+fn attribute(dir: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(dir.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+/// Names of the wired interfaces in the given sysfs network directory:
+/// physical interfaces (those with a device) without a wireless directory
+pub fn names_at(root: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| {
+                    let path = entry.path();
+                    path.join("device").exists() && !path.join("wireless").exists()
+                })
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Names of the wired interfaces present on the device
+pub fn names() -> Vec<String> {
+    names_at(Path::new(SYSFS_NET))
+}
+
+/// Read the state of an interface from the given sysfs network directory.
+/// The IP address is not included.
+pub fn read_at(root: &Path, name: &str) -> WiredInterface {
+    let dir = root.join(name);
+    let counter = |stat: &str| {
+        attribute(&dir, &format!("statistics/{}", stat))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    };
+
+    WiredInterface {
+        name: name.to_string(),
+        state: attribute(&dir, "operstate").unwrap_or_else(|| "unknown".to_string()),
+        // reading the carrier fails while the interface is down
+        carrier: attribute(&dir, "carrier").map_or(false, |c| c == "1"),
+        // the speed is -1 (or unreadable) without a link
+        speed: attribute(&dir, "speed")
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|s| *s > 0)
+            .map(|s| s as u32),
+        mac: attribute(&dir, "address"),
+        ip: None,
+        rx_bytes: counter("rx_bytes"),
+        tx_bytes: counter("tx_bytes"),
+    }
+}
+
+/// Retrieve the state of all wired interfaces, including their IP addresses
+pub fn get_interfaces() -> Vec<WiredInterface> {
+    let root = Path::new(SYSFS_NET);
+    names_at(root)
+        .iter()
+        .map(|name| {
+            let mut iface = read_at(root, name);
+            iface.ip = network_client::ip(name).ok();
+            iface
+        })
+        .collect()
+}
+
+/// Retrieve the state of a single wired interface (if present)
+pub fn get_interface(name: &str) -> Option<WiredInterface> {
+    get_interfaces()
+        .into_iter()
+        .find(|iface| iface.name == name)
+}
+
+/// Current traffic counters (received and transmitted bytes) of the wired
+/// interfaces, by interface name
+pub fn counters() -> Vec<(String, u64, u64)> {
+    let root = Path::new(SYSFS_NET);
+    names_at(root)
+        .into_iter()
+        .map(|name| {
+            let iface = read_at(root, &name);
+            (name, iface.rx_bytes, iface.tx_bytes)
+        })
+        .collect()
+}
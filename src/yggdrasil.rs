@@ -0,0 +1,375 @@
+//! Yggdrasil mesh networking.
+//!
+//! The Yggdrasil daemon routes IPv6 traffic over an encrypted overlay network.
+//! It is enabled or disabled as a systemd service, and is otherwise driven
+//! through its local admin socket, which accepts one JSON request per line and
+//! answers with a JSON response. The socket path can be configured with the
+//! `PEACH_WEB_YGGDRASIL_SOCKET` environment variable.
+//!
+//! Peers added through the admin socket are forgotten when the daemon
+//! restarts, so the peers configured in `peach-web` are kept in the data store
+//! and added to the daemon again whenever they are missing.
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::{env, thread, time::Duration};
+
+use log::{debug, info, warn};
+use rocket::request::FromForm;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::device;
+use crate::error::PeachWebError;
+use crate::store::DataStore;
+
+/// Default path of the admin socket
+const ADMIN_SOCKET: &str = "/var/run/yggdrasil.sock";
+/// Environment variable used to override the admin socket path
+const SOCKET_VAR: &str = "PEACH_WEB_YGGDRASIL_SOCKET";
+/// Time allowed for an admin socket request (in seconds)
+const SOCKET_TIMEOUT: u64 = 5;
+/// Default interval between checks of the configured peers (in seconds)
+const SYNC_INTERVAL: u64 = 60;
+/// Name of the systemd service
+const SERVICE: &str = "yggdrasil";
+/// Path of the configured peers within the data store
+const PEERS_PATH: [&str; 3] = ["mesh", "yggdrasil", "peers"];
+/// Peer URI schemes supported by the daemon
+const PEER_SCHEMES: [&str; 6] = ["tcp", "tls", "quic", "socks", "ws", "wss"];
+
+/// Yggdrasil settings (user-defined)
+#[derive(Debug, Deserialize, Serialize, FromForm)]
+pub struct Settings {
+    pub enabled: bool,
+}
+
+/// Address and public key of this node
+#[derive(Debug, Deserialize, Serialize)]
+pub struct NodeInfo {
+    pub address: String, // overlay IPv6 address
+    pub subnet: String,  // IPv6 subnet routed to this node
+    pub key: String,     // public key (hex)
+    #[serde(default)]
+    pub build_version: String,
+}
+
+/// Connection status of a peer, as reported by the daemon
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PeerStatus {
+    pub remote: String, // peer URI (or remote address of an inbound peer)
+    pub up: bool,
+    pub inbound: bool,
+    pub address: String, // overlay address of the peer
+    pub key: String,
+    pub uptime: f64, // seconds
+    pub bytes_recvd: u64,
+    pub bytes_sent: u64,
+}
+
+/// A configured peer or a peer connected to the daemon
+#[derive(Debug, Serialize)]
+pub struct Peer {
+    pub uri: String,
+    pub configured: bool, // saved in peach-web
+    pub status: Option<PeerStatus>,
+}
+
+/// Service state, node details and peers
+#[derive(Debug, Serialize)]
+pub struct Status {
+    pub enabled: bool,
+    pub active: bool,
+    pub node: Option<NodeInfo>,
+    pub peers: Vec<Peer>,
+    pub error: Option<String>, // failure to query the admin socket
+}
+
+// envelope of an admin socket response
+#[derive(Deserialize)]
+struct AdminResponse {
+    status: String,
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    response: Value,
+}
+
+/// Client for the Yggdrasil admin socket
+pub struct Admin {
+    socket: PathBuf,
+}
+
+impl Default for Admin {
+    fn default() -> Admin {
+        let socket = env::var_os(SOCKET_VAR)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(ADMIN_SOCKET));
+        Admin { socket }
+    }
+}
+
+impl Admin {
+    /// Client for the admin socket at the given path
+    pub fn at(socket: &Path) -> Admin {
+        Admin {
+            socket: socket.to_path_buf(),
+        }
+    }
+
+    // send a request and return the `response` field of the reply
+    fn request(&self, request: &str, arguments: Value) -> Result<Value, PeachWebError> {
+        let socket_err = |source| PeachWebError::YggdrasilSocket {
+            path: self.socket.display().to_string(),
+            source,
+        };
+        let request_err = |msg: String| PeachWebError::YggdrasilRequest {
+            request: request.to_string(),
+            msg,
+        };
+
+        let mut stream = UnixStream::connect(&self.socket).map_err(socket_err)?;
+        let timeout = Some(Duration::from_secs(SOCKET_TIMEOUT));
+        stream.set_read_timeout(timeout).map_err(socket_err)?;
+        stream.set_write_timeout(timeout).map_err(socket_err)?;
+        let body = json!({
+            "request": request,
+            "arguments": arguments,
+            "keepalive": false,
+        });
+        writeln!(stream, "{}", body).map_err(socket_err)?;
+
+        let reply: AdminResponse = serde_json::Deserializer::from_reader(&stream)
+            .into_iter()
+            .next()
+            .ok_or_else(|| request_err("no response".to_string()))?
+            .map_err(|e| request_err(e.to_string()))?;
+        if reply.status == "success" {
+            Ok(reply.response)
+        } else {
+            Err(request_err(reply.error))
+        }
+    }
+
+    /// Address and public key of this node
+    pub fn get_self(&self) -> Result<NodeInfo, PeachWebError> {
+        let response = self.request("getself", json!({}))?;
+        serde_json::from_value(response).map_err(|e| PeachWebError::YggdrasilRequest {
+            request: "getself".to_string(),
+            msg: e.to_string(),
+        })
+    }
+
+    /// Peers connected to the daemon
+    pub fn get_peers(&self) -> Result<Vec<PeerStatus>, PeachWebError> {
+        let mut response = self.request("getpeers", json!({}))?;
+        serde_json::from_value(response["peers"].take()).map_err(|e| {
+            PeachWebError::YggdrasilRequest {
+                request: "getpeers".to_string(),
+                msg: e.to_string(),
+            }
+        })
+    }
+
+    /// Connect to a peer
+    pub fn add_peer(&self, uri: &str) -> Result<(), PeachWebError> {
+        self.request("addpeer", json!({ "uri": uri }))?;
+        Ok(())
+    }
+
+    /// Disconnect from a peer
+    pub fn remove_peer(&self, uri: &str) -> Result<(), PeachWebError> {
+        self.request("removepeer", json!({ "uri": uri }))?;
+        Ok(())
+    }
+}
+
+/// Ensure a peer URI is of the form `scheme://host:port` (with an optional
+/// path or query), using a scheme supported by the daemon
+pub fn validate_peer(uri: &str) -> Result<(), PeachWebError> {
+    let invalid = |msg: &str| {
+        Err(PeachWebError::InvalidPeer {
+            msg: msg.to_string(),
+        })
+    };
+    if uri.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return invalid("Peer URI must not contain spaces");
+    }
+    let mut parts = uri.splitn(2, "://");
+    let scheme = parts.next().unwrap_or_default();
+    let rest = match parts.next() {
+        Some(rest) => rest,
+        None => return invalid("Peer URI must be of the form tls://host:port"),
+    };
+    if !PEER_SCHEMES.contains(&scheme) {
+        return invalid("Peer URI scheme must be tcp, tls, quic, socks, ws or wss");
+    }
+    let authority = rest
+        .split(|c| c == '/' || c == '?')
+        .next()
+        .unwrap_or_default();
+    let mut host_port = authority.rsplitn(2, ':');
+    let port = host_port.next().and_then(|port| port.parse::<u16>().ok());
+    let host = host_port.next().unwrap_or_default();
+    if host.is_empty() || port.map_or(true, |port| port == 0) {
+        return invalid("Peer URI must include a host and port (eg. tls://host:port)");
+    }
+
+    Ok(())
+}
+
+/// Combine the configured peers with the peers connected to the daemon
+pub fn merge(configured: &[String], statuses: Vec<PeerStatus>) -> Vec<Peer> {
+    let mut peers: Vec<Peer> = configured
+        .iter()
+        .map(|uri| Peer {
+            uri: uri.clone(),
+            configured: true,
+            status: None,
+        })
+        .collect();
+    for status in statuses {
+        match peers.iter_mut().find(|peer| peer.uri == status.remote) {
+            Some(peer) => peer.status = Some(status),
+            None => peers.push(Peer {
+                uri: status.remote.clone(),
+                configured: false,
+                status: Some(status),
+            }),
+        }
+    }
+
+    peers
+}
+
+// retrieve the configured peers from the store
+fn get_configured(store: &DataStore) -> Result<Vec<String>, PeachWebError> {
+    store.get_list(&PEERS_PATH)
+}
+
+// whether the systemd service is in the given state ("active" or "enabled")
+fn service_is(state: &str) -> bool {
+    let check = format!("is-{}", state);
+    Command::new("systemctl")
+        .args(&[check.as_str(), "--quiet", SERVICE])
+        .status()
+        .map_or(false, |status| status.success())
+}
+
+/// Retrieve the service state, node details and peers
+pub fn get_status() -> Result<Status, PeachWebError> {
+    let store = DataStore::open()?;
+    let configured = get_configured(&store)?;
+    let active = service_is("active");
+    let admin = Admin::default();
+
+    let (node, statuses, error) = if active {
+        match admin
+            .get_self()
+            .and_then(|node| Ok((node, admin.get_peers()?)))
+        {
+            Ok((node, statuses)) => (Some(node), statuses, None),
+            Err(e) => (None, Vec::new(), Some(e.to_string())),
+        }
+    } else {
+        (None, Vec::new(), None)
+    };
+
+    Ok(Status {
+        enabled: service_is("enabled"),
+        active,
+        node,
+        peers: merge(&configured, statuses),
+        error,
+    })
+}
+
+/// Add the configured peers which are not connected to the daemon
+pub fn sync_peers(admin: &Admin, configured: &[String]) -> Result<(), PeachWebError> {
+    let connected = admin.get_peers()?;
+    for uri in configured {
+        if !connected.iter().any(|peer| &peer.remote == uri) {
+            debug!("Adding Yggdrasil peer {}", uri);
+            if let Err(e) = admin.add_peer(uri) {
+                warn!("Failed to add Yggdrasil peer {}: {}", uri, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Enable and start, or stop and disable, the daemon
+pub fn update_settings(settings: Settings) -> Result<(), PeachWebError> {
+    if settings.enabled {
+        info!("Enabling Yggdrasil");
+        device::run_privileged("systemctl", &["enable", "--now", SERVICE])?;
+    } else {
+        info!("Disabling Yggdrasil");
+        device::run_privileged("systemctl", &["disable", "--now", SERVICE])?;
+    }
+
+    Ok(())
+}
+
+/// Validate and add a peer, connecting to it if the daemon is running
+pub fn add_peer(uri: &str) -> Result<(), PeachWebError> {
+    validate_peer(uri)?;
+    let store = DataStore::open()?;
+    store.update(&["mesh", "yggdrasil"], |values| {
+        let mut configured: Vec<String> = values.get_list("peers")?;
+        if configured.iter().any(|peer| peer == uri) {
+            return Err(PeachWebError::InvalidPeer {
+                msg: format!("Peer {} is already configured", uri),
+            });
+        }
+        if service_is("active") {
+            Admin::default().add_peer(uri)?;
+        }
+        configured.push(uri.to_string());
+        values.set_list("peers", &configured)
+    })
+}
+
+/// Remove a peer, disconnecting from it if the daemon is running. The peer is
+/// removed from the configured peers even if the daemon rejects the request
+/// (eg. because the peer is not connected).
+pub fn remove_peer(uri: &str) -> Result<(), PeachWebError> {
+    let store = DataStore::open()?;
+    store.update(&["mesh", "yggdrasil"], |values| {
+        let mut configured: Vec<String> = values.get_list("peers")?;
+        configured.retain(|peer| peer != uri);
+        values.set_list("peers", &configured)
+    })?;
+    if service_is("active") {
+        if let Err(e) = Admin::default().remove_peer(uri) {
+            warn!("Failed to disconnect from Yggdrasil peer {}: {}", uri, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Periodically add the configured peers to the daemon, since it forgets
+/// them when it restarts.
+///
+/// The interval (in seconds) can be configured with the
+/// `PEACH_WEB_YGGDRASIL_INTERVAL` environment variable.
+pub fn run() {
+    let interval = crate::interval("PEACH_WEB_YGGDRASIL_INTERVAL", SYNC_INTERVAL);
+
+    loop {
+        if service_is("active") {
+            let result = DataStore::open()
+                .and_then(|store| get_configured(&store))
+                .and_then(|configured| sync_peers(&Admin::default(), &configured));
+            if let Err(e) = result {
+                warn!("Failed to add configured Yggdrasil peers: {}", e);
+            }
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
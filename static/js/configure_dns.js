@@ -7,6 +7,8 @@ corresponding to the web route `/network/dns`
  - perform json api call
  - update the dom
 
+the certificate request form is submitted without javascript
+
 */
 
 var PEACH_DNS = {};
@@ -14,11 +16,11 @@ var PEACH_DNS = {};
 // catch click of 'Add' button and make POST request
 PEACH_DNS.add = function() {
     document.addEventListener('DOMContentLoaded', function() {
-        document.body.addEventListener('submit', function(e) {
+        // capture form data
+        var formElement = document.getElementById("configureDNS");
+        formElement.addEventListener('submit', function(e) {
             // prevent redirect on button press (default behavior)
             e.preventDefault();
-            // capture form data
-            var formElement = document.querySelector("form");
             // create form data object from the wifiCreds form element
             var formData = new FormData(formElement);
             var object = {};
@@ -0,0 +1,44 @@
+/*
+
+behavioural layer for the `datetime.html.tera` template,
+corresponding to the web route `/settings/datetime`
+
+ - show the time of the browser next to the time of the device
+ - fill in the time of the browser when the clock form is submitted
+
+methods:
+
+ PEACH_DATETIME.showBrowserTime();
+ PEACH_DATETIME.setClock();
+
+*/
+
+var PEACH_DATETIME = {};
+
+// display the current time of the browser
+PEACH_DATETIME.showBrowserTime = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        var timeElement = document.getElementById("browserTime");
+        if (timeElement) {
+            timeElement.textContent = new Date().toString();
+        }
+    });
+}
+
+// catch click of 'Set Clock From Browser' and add the browser time
+PEACH_DATETIME.setClock = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        var formElement = document.getElementById("setClock");
+        if (formElement) {
+            formElement.addEventListener('submit', function() {
+                // unix timestamp (seconds) of the moment of submission
+                var timestamp = Math.floor(Date.now() / 1000);
+                document.getElementById("timestamp").value = timestamp;
+            });
+        }
+    });
+}
+
+var dateTimeInstance = PEACH_DATETIME;
+dateTimeInstance.showBrowserTime();
+dateTimeInstance.setClock();
@@ -3,12 +3,14 @@
 behavioural layer for the `network_add.html.tera` template,
 corresponding to the web route `/network/wifi/add`
 
+ - show only the fields needed for the chosen security type
  - intercept button click for add (form submission of credentials)
  - perform json api call
  - update the dom
 
 methods:
 
+ PEACH_NETWORK.securityFields();
  PEACH_NETWORK.add();
  PEACH_NETWORK.flashMsg(status, msg);
 
@@ -16,14 +18,29 @@ methods:
 
 var PEACH_NETWORK = {};
 
+// show or hide the identity and password inputs for the selected security type
+PEACH_NETWORK.securityFields = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        var security = document.getElementById("security");
+        var update = function() {
+            var type = security.value;
+            var enterprise = (type === "peap" || type === "ttls");
+            document.getElementById("eapFields").style.display = enterprise ? "" : "none";
+            document.getElementById("pass").style.display = (type === "open") ? "none" : "";
+        };
+        security.addEventListener('change', update);
+        update();
+    });
+}
+
 // catch click of 'Add' button and make POST request
 PEACH_NETWORK.add = function() {
     document.addEventListener('DOMContentLoaded', function() {
-        document.body.addEventListener('submit', function(e) {
+        // only the credentials form is submitted via the json api
+        var formElement = document.getElementById("wifiCreds");
+        formElement.addEventListener('submit', function(e) {
             // prevent redirect on button press (default behavior)
             e.preventDefault();
-            // capture form data
-            var formElement = document.querySelector("form");
             // create form data object from the wifiCreds form element
             var formData = new FormData(formElement);
             var object = {};
@@ -31,6 +48,8 @@ PEACH_NETWORK.add = function() {
             formData.forEach(function(value, key){
                 object[key] = value;
             });
+            // send the hidden network checkbox as a boolean
+            object["hidden"] = document.getElementById("hidden").checked;
             // perform json serialization
             var jsonData = JSON.stringify(object);
             // write in-progress status message to ui
@@ -90,4 +109,5 @@ PEACH_NETWORK.flashMsg = function(status, msg) {
 }
 
 var addInstance = PEACH_NETWORK;
+addInstance.securityFields();
 addInstance.add();
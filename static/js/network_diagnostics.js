@@ -0,0 +1,150 @@
+/*
+
+behavioural layer for the `network_diagnostics.html.tera` template,
+corresponding to the web route `/network/diagnostics`
+
+ - intercept form submission and start diagnostics via the json api
+ - poll for results while the checks are running
+ - update the dom as each result arrives
+
+methods:
+
+ PEACH_NETWORK.run();
+ PEACH_NETWORK.poll();
+ PEACH_NETWORK.render(report);
+ PEACH_NETWORK.flashMsg(status, msg);
+
+*/
+
+var PEACH_NETWORK = {};
+
+// interval between requests for results (milliseconds)
+PEACH_NETWORK.pollInterval = 1000;
+
+// catch click of 'Run Diagnostics' and make POST request
+PEACH_NETWORK.run = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        var formElement = document.getElementById("diagnostics");
+        formElement.addEventListener('submit', function(e) {
+            // prevent redirect on button press (default behavior)
+            e.preventDefault();
+            var object = {};
+            // send empty targets as null so the defaults are used
+            new FormData(formElement).forEach(function(value, key){
+                object[key] = value.trim() === "" ? null : value.trim();
+            });
+            fetch("/api/v1/network/diagnostics", {
+                method: "post",
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify(object)
+            })
+            .then( (response) => {
+                return response.json()
+            })
+            .then( (jsonData) => {
+                PEACH_NETWORK.flashMsg(jsonData.status, jsonData.msg);
+                if (jsonData.status === "success") {
+                    PEACH_NETWORK.poll();
+                }
+            })
+        }, false);
+        // keep polling if a run is already in progress
+        if (document.getElementById("results").dataset.running === "true") {
+            PEACH_NETWORK.poll();
+        }
+    });
+}
+
+// request the results until the run has completed
+PEACH_NETWORK.poll = function() {
+    fetch("/api/v1/network/diagnostics")
+    .then( (response) => {
+        return response.json()
+    })
+    .then( (jsonData) => {
+        if (jsonData.status !== "success") {
+            PEACH_NETWORK.flashMsg(jsonData.status, jsonData.msg);
+            return;
+        }
+        PEACH_NETWORK.render(jsonData.data);
+        if (jsonData.data.running) {
+            setTimeout(PEACH_NETWORK.poll, PEACH_NETWORK.pollInterval);
+        }
+    })
+}
+
+// replace the results list and verdict with those of the given report
+PEACH_NETWORK.render = function(report) {
+    var list = document.getElementById("results");
+    while (list.firstChild) {
+        list.removeChild(list.firstChild);
+    }
+    var label = function(text) {
+        var element = document.createElement("LABEL");
+        element.className = "label-small font-gray";
+        element.innerText = text;
+        return element;
+    };
+    report.results.forEach(function(result) {
+        var item = document.createElement("LI");
+        item.className = result.ok ? "list-item light-bg" : "list-item light-bg warning-border";
+        var text = document.createElement("P");
+        text.className = "list-text";
+        var target = result.target ? " (" + result.target + ")" : "";
+        text.innerText = result.check.toUpperCase() + target + ": " + (result.ok ? "OK" : "FAILED");
+        item.appendChild(text);
+        item.appendChild(label(result.summary));
+        result.details.forEach(function(detail) {
+            item.appendChild(label(detail));
+        });
+        list.appendChild(item);
+    });
+    if (report.running) {
+        var running = document.createElement("LI");
+        running.className = "list-item light-bg";
+        running.innerText = "Running...";
+        list.appendChild(running);
+    }
+    document.getElementById("runDiagnostics").disabled = report.running;
+    var verdict = document.getElementById("verdict");
+    verdict.innerText = report.verdict || "";
+    verdict.style.display = report.verdict ? "" : "none";
+}
+
+// display a message by appending a paragraph element
+PEACH_NETWORK.flashMsg = function(status, msg) {
+    // set the class of the element according to status
+    var elementClass;
+    if (status === "success") {
+        elementClass = "capsule center-text flash-message font-success";
+    } else if (status === "info") {
+        elementClass = "capsule center-text flash-message font-info";
+    } else {
+        elementClass = "capsule center-text flash-message font-failure";
+    };
+
+    var flashElement = document.getElementById("flashMsg");
+    // if flashElement exists, update the class & text
+    if (flashElement) {
+        flashElement.className = elementClass;
+        flashElement.innerText = msg;
+    // if flashElement does not exist, create it, set id, class, text & append
+    } else {
+        // create new div for flash message
+        var flashDiv = document.createElement("DIV");
+        // set div attributes
+        flashDiv.id = "flashMsg";
+        flashDiv.className = elementClass;
+        // add json response message to flash message div
+        var flashMsg = document.createTextNode(msg);
+        flashDiv.appendChild(flashMsg);
+        // insert the flash message div below the results list
+        var list = document.getElementById("results");
+        list.parentNode.insertBefore(flashDiv, list.nextSibling);
+    }
+}
+
+var diagnosticsInstance = PEACH_NETWORK;
+diagnosticsInstance.run();
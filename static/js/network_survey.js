@@ -0,0 +1,143 @@
+/*
+
+behavioural layer for the `network_survey.html.tera` template,
+corresponding to the web route `/network/wifi/survey`
+
+ - intercept form submission and start the survey via the json api
+ - poll for results while the scans are running
+ - update the dom after each scan
+
+methods:
+
+ PEACH_NETWORK.run();
+ PEACH_NETWORK.poll();
+ PEACH_NETWORK.render(report);
+ PEACH_NETWORK.flashMsg(status, msg);
+
+*/
+
+var PEACH_NETWORK = {};
+
+// interval between requests for results (milliseconds)
+PEACH_NETWORK.pollInterval = 2000;
+
+// catch click of 'Run Survey' and make POST request
+PEACH_NETWORK.run = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        var formElement = document.getElementById("survey");
+        formElement.addEventListener('submit', function(e) {
+            // prevent redirect on button press (default behavior)
+            e.preventDefault();
+            var object = {
+                rounds: parseInt(document.getElementById("rounds").value, 10),
+                interval: parseInt(document.getElementById("interval").value, 10)
+            };
+            fetch("/api/v1/network/wifi/survey", {
+                method: "post",
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: JSON.stringify(object)
+            })
+            .then( (response) => {
+                return response.json()
+            })
+            .then( (jsonData) => {
+                PEACH_NETWORK.flashMsg(jsonData.status, jsonData.msg);
+                if (jsonData.status === "success") {
+                    PEACH_NETWORK.poll();
+                }
+            })
+        }, false);
+        // keep polling if a survey is already in progress
+        if (document.getElementById("results").dataset.running === "true") {
+            PEACH_NETWORK.poll();
+        }
+    });
+}
+
+// request the results until the survey has completed
+PEACH_NETWORK.poll = function() {
+    fetch("/api/v1/network/wifi/survey")
+    .then( (response) => {
+        return response.json()
+    })
+    .then( (jsonData) => {
+        if (jsonData.status !== "success") {
+            PEACH_NETWORK.flashMsg(jsonData.status, jsonData.msg);
+            return;
+        }
+        PEACH_NETWORK.render(jsonData.data);
+        if (jsonData.data.running) {
+            setTimeout(PEACH_NETWORK.poll, PEACH_NETWORK.pollInterval);
+        }
+    })
+}
+
+// replace the results table and progress with those of the given report
+PEACH_NETWORK.render = function(report) {
+    var body = document.getElementById("results").tBodies[0];
+    while (body.firstChild) {
+        body.removeChild(body.firstChild);
+    }
+    report.entries.forEach(function(entry) {
+        var row = document.createElement("TR");
+        row.title = entry.bssid;
+        var cells = [
+            entry.ssid || "(hidden)",
+            entry.channel ? entry.channel : entry.frequency + " MHz",
+            entry.band,
+            entry.security,
+            entry.min + " / " + entry.avg + " / " + entry.max + " (" + entry.quality + "%)",
+            entry.seen
+        ];
+        cells.forEach(function(text) {
+            var cell = document.createElement("TD");
+            cell.className = "card-text";
+            cell.innerText = text;
+            row.appendChild(cell);
+        });
+        body.appendChild(row);
+    });
+    var progress = document.getElementById("progress");
+    var error = report.error ? " (" + report.error + ")" : "";
+    progress.innerText = (report.running ? "Scanning... " : "") + report.completed + " of " + report.rounds + " scans completed" + error;
+    progress.style.display = "";
+    document.getElementById("runSurvey").disabled = report.running;
+}
+
+// display a message by appending a paragraph element
+PEACH_NETWORK.flashMsg = function(status, msg) {
+    // set the class of the element according to status
+    var elementClass;
+    if (status === "success") {
+        elementClass = "capsule center-text flash-message font-success";
+    } else if (status === "info") {
+        elementClass = "capsule center-text flash-message font-info";
+    } else {
+        elementClass = "capsule center-text flash-message font-failure";
+    };
+
+    var flashElement = document.getElementById("flashMsg");
+    // if flashElement exists, update the class & text
+    if (flashElement) {
+        flashElement.className = elementClass;
+        flashElement.innerText = msg;
+    // if flashElement does not exist, create it, set id, class, text & append
+    } else {
+        // create new div for flash message
+        var flashDiv = document.createElement("DIV");
+        // set div attributes
+        flashDiv.id = "flashMsg";
+        flashDiv.className = elementClass;
+        // add json response message to flash message div
+        var flashMsg = document.createTextNode(msg);
+        flashDiv.appendChild(flashMsg);
+        // insert the flash message div below the results table
+        var table = document.getElementById("results");
+        table.parentNode.insertBefore(flashDiv, table.nextSibling);
+    }
+}
+
+var surveyInstance = PEACH_NETWORK;
+surveyInstance.run();
@@ -10,6 +10,7 @@ corresponding to the web route `/network/wifi/usage`
 methods:
 
  PEACH_NETWORK.updateAlerts();
+ PEACH_NETWORK.updateCycle();
  PEACH_NETWORK.resetUsage();
  PEACH_NETWORK.toggleWarning();
  PEACH_NETWORK.toggleCutoff();
@@ -23,10 +24,14 @@ var PEACH_NETWORK = {};
 PEACH_NETWORK.updateAlerts = function() {
     document.addEventListener('DOMContentLoaded', function() {
         document.body.addEventListener('submit', function(e) {
+            // only handle submission of the alerts form
+            if (e.target.id !== "wifiAlerts") {
+                return;
+            }
             // prevent redirect on button press (default behavior)
             e.preventDefault();
             // capture form data
-            var formElement = document.querySelector("form");
+            var formElement = document.getElementById("wifiAlerts");
             let warn = formElement.elements.warn.value;
             let cut = formElement.elements.cut.value;
             let warn_flag = formElement.elements.warn_flag.checked;
@@ -57,6 +62,46 @@ PEACH_NETWORK.updateAlerts = function() {
     });
 }
 
+// catch click of 'Save Cycle' and make POST request
+PEACH_NETWORK.updateCycle = function() {
+    document.addEventListener('DOMContentLoaded', function() {
+        document.body.addEventListener('submit', function(e) {
+            // only handle submission of the billing cycle form
+            if (e.target.id !== "wifiCycle") {
+                return;
+            }
+            // prevent redirect on button press (default behavior)
+            e.preventDefault();
+            // capture form data
+            var formElement = document.getElementById("wifiCycle");
+            let period = formElement.elements.period.value;
+            let day = formElement.elements.day.value;
+            let timezone = formElement.elements.timezone.value;
+            // perform json serialization
+            var jsonData = JSON.stringify({
+                "period": period,
+                "day": parseInt(day),
+                "timezone": timezone,
+            });
+            // send update_wifi_cycle POST request
+            fetch("/api/v1/network/wifi/usage/cycle", {
+                method: "post",
+                headers: {
+                    'Content-Type': 'application/json',
+                },
+                body: jsonData
+            })
+            .then( (response) => {
+                return response.json()
+            })
+            .then( (jsonData) => {
+                // write json response message to ui
+                PEACH_NETWORK.flashMsg(jsonData.status, jsonData.msg);
+            })
+        }, false);
+    });
+}
+
 // catch click of 'Reset' and make POST request
 PEACH_NETWORK.resetUsage = function() {
     document.addEventListener('DOMContentLoaded', function() {
@@ -171,3 +216,4 @@ usageInstance.resetUsage();
 usageInstance.toggleWarning();
 usageInstance.toggleCutoff();
 usageInstance.updateAlerts();
+usageInstance.updateCycle();